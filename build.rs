@@ -0,0 +1,16 @@
+//! Compiles the C wrappers that catch structured exceptions, which Rust cannot do itself.
+
+fn main() {
+    println!("cargo:rerun-if-changed=src/seh.c");
+
+    // `__try`/`__except` is only understood by MSVC, and there is nothing to catch when the pure
+    // logic is tested on the host.
+    if std::env::var("CARGO_CFG_TARGET_ENV").as_deref() != Ok("msvc") {
+        return;
+    }
+
+    cc::Build::new()
+        .file("src/seh.c")
+        .flag("/kernel")
+        .compile("win_kernel_seh");
+}
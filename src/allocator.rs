@@ -1,30 +1,324 @@
 //! This module provides an allocator to use with the [`alloc`] crate. You can define your own
 //! global allocator with the `#[global_allocator]` attribute when not using the `alloc` feature,
 //! in case you want to specify your own tag to use with [`ExAllocatePool2`] and
-//! [`ExAllocatePoolWithTag`].
+//! [`ExAllocatePoolWithTag`]. [`PoolAllocator`] selects the tag and the pool type at compile time,
+//! for use with the allocator API, e.g. `Vec<T, PoolAllocator<TAG, Paged>>`.
 
+use alloc::boxed::Box;
+use alloc::collections::TryReserveError;
+use alloc::string::String;
+use alloc::vec::Vec;
 use core::alloc::{AllocError, Allocator, GlobalAlloc, Layout};
-use core::ptr::NonNull;
-use lazy_static::lazy_static;
-use win_kernel_sys::base::{MM_ANY_NODE_OK, PHYSICAL_ADDRESS};
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::mem::size_of;
+use core::ptr::{addr_of, addr_of_mut, NonNull};
+use core::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+use win_kernel_sys::base::{LARGE_INTEGER, MM_ANY_NODE_OK, PHYSICAL_ADDRESS};
 use win_kernel_sys::base::_MEMORY_CACHING_TYPE::MmCached;
-use win_kernel_sys::base::_POOL_TYPE::{self as POOL_TYPE, NonPagedPool};
-use win_kernel_sys::ntoskrnl::{ExAllocatePool2, ExAllocatePoolWithTag, ExFreePool, MmAllocateContiguousMemorySpecifyCacheNode, MmFreeContiguousMemory};
-use crate::version::VersionInfo;
+use win_kernel_sys::base::_MODE::KernelMode;
+use win_kernel_sys::base::_POOL_TYPE::{
+    self as POOL_TYPE, NonPagedPool, NonPagedPoolNx, PagedPool,
+};
+use win_kernel_sys::ntoskrnl::{
+    ExAllocatePoolWithTag, ExFreePool, MmAllocateContiguousMemorySpecifyCacheNode,
+    MmFreeContiguousMemorySpecifyCache, MmGetPhysicalAddress,
+};
+use win_kernel_sys::ntoskrnl::{KeBugCheckEx, KeDelayExecutionThread};
+use crate::error::{Error, IntoResult};
+use crate::headers::{
+    ExDeleteLookasideListEx, ExInitializeLookasideListEx, ExpInterlockedPopEntrySList,
+    ExpInterlockedPushEntrySList, LOOKASIDE_LIST_EX, SLIST_ENTRY,
+};
+use crate::intrin::current_irql;
+use crate::import::ExAllocatePool2;
+use crate::memory::{MemoryCaching, PhysicalAddress};
+use crate::version::{VersionInfo, WindowsRelease};
 
-/// See issue #52191.
+#[cfg(feature = "alloc-stats")]
+pub mod stats;
+
+/// The bug check code raised by the default [`AllocErrorPolicy`]. The parameters are the size and
+/// the alignment of the allocation that failed.
+pub const ALLOC_ERROR_BUGCHECK_CODE: u32 = 0xA110_C000;
+
+/// What to do when an infallible allocation of the `alloc` crate, e.g. `Box::new`, fails. Use the
+/// `try_` functions of this module on paths that must survive low memory instead.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AllocErrorPolicy {
+    /// Bug check with the given code, see [`ALLOC_ERROR_BUGCHECK_CODE`].
+    BugCheck(u32),
+    /// Log the failed allocation and halt the current thread forever. At `DISPATCH_LEVEL` and
+    /// above, the processor spins instead.
+    Halt,
+}
+
+/// The bug check code of the current policy, or zero for [`AllocErrorPolicy::Halt`].
+static ALLOC_ERROR_POLICY: AtomicU32 = AtomicU32::new(ALLOC_ERROR_BUGCHECK_CODE);
+
+/// Sets the policy that is applied when an infallible allocation fails. A bug check code of zero
+/// is treated as [`AllocErrorPolicy::Halt`].
+pub fn set_alloc_error_policy(policy: AllocErrorPolicy) {
+    let code = match policy {
+        AllocErrorPolicy::BugCheck(code) => code,
+        AllocErrorPolicy::Halt => 0,
+    };
+
+    ALLOC_ERROR_POLICY.store(code, Ordering::Relaxed);
+}
+
+/// Returns the policy that is applied when an infallible allocation fails.
+pub fn alloc_error_policy() -> AllocErrorPolicy {
+    match ALLOC_ERROR_POLICY.load(Ordering::Relaxed) {
+        0 => AllocErrorPolicy::Halt,
+        code => AllocErrorPolicy::BugCheck(code),
+    }
+}
+
+/// See issue #52191. Host unit tests link `std`, which brings its own handler.
+#[cfg(not(test))]
 #[alloc_error_handler]
-fn alloc_error(_: Layout) -> ! {
-    loop {}
+fn alloc_error(layout: Layout) -> ! {
+    match alloc_error_policy() {
+        AllocErrorPolicy::BugCheck(code) => unsafe {
+            KeBugCheckEx(code, layout.size() as _, layout.align() as _, 0, 0);
+        },
+        AllocErrorPolicy::Halt => {
+            crate::println!(
+                "[kernel-alloc] failed to allocate {} bytes aligned to {}, halting thread",
+                layout.size(),
+                layout.align()
+            );
+
+            // Below DISPATCH_LEVEL the thread can wait, which keeps the processor available.
+            const DISPATCH_LEVEL: u8 = 2;
+
+            if current_irql() < DISPATCH_LEVEL {
+                let mut interval: LARGE_INTEGER = unsafe { core::mem::zeroed() };
+
+                // A relative interval of one second, in units of 100 nanoseconds.
+                interval.QuadPart = -10_000_000;
+
+                loop {
+                    unsafe {
+                        KeDelayExecutionThread(KernelMode as _, false as _, &mut interval);
+                    }
+                }
+            }
+        }
+    }
+
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+/// Allocates a [`Box`], failing with [`Error::INSUFFICIENT_RESOURCES`] instead of invoking the
+/// [`AllocErrorPolicy`].
+pub fn try_box<T>(value: T) -> Result<Box<T>, Error> {
+    Ok(Box::try_new(value)?)
+}
+
+/// Allocates a [`Vec`] with room for at least `capacity` elements, failing with
+/// [`Error::INSUFFICIENT_RESOURCES`] instead of invoking the [`AllocErrorPolicy`].
+pub fn try_vec_with_capacity<T>(capacity: usize) -> Result<Vec<T>, Error> {
+    let mut vec = Vec::new();
+    vec.try_reserve_exact(capacity)?;
+
+    Ok(vec)
+}
+
+/// Allocates a [`Vec`] of `len` clones of `value`, see [`try_vec_with_capacity`].
+pub fn try_vec<T: Clone>(value: T, len: usize) -> Result<Vec<T>, Error> {
+    let mut vec = try_vec_with_capacity(len)?;
+    vec.resize(len, value);
+
+    Ok(vec)
+}
+
+/// Copies a slice into a new [`Vec`], see [`try_vec_with_capacity`].
+pub fn try_vec_from_slice<T: Clone>(slice: &[T]) -> Result<Vec<T>, Error> {
+    let mut vec = try_vec_with_capacity(slice.len())?;
+    vec.extend_from_slice(slice);
+
+    Ok(vec)
+}
+
+/// Appends an element to a [`Vec`], growing it fallibly, see [`try_vec_with_capacity`].
+pub fn try_push<T>(vec: &mut Vec<T>, value: T) -> Result<(), Error> {
+    vec.try_reserve(1)?;
+    vec.push(value);
+
+    Ok(())
+}
+
+/// Copies a string slice into a new [`String`], failing with [`Error::INSUFFICIENT_RESOURCES`]
+/// instead of invoking the [`AllocErrorPolicy`].
+pub fn try_string(s: &str) -> Result<String, Error> {
+    let mut string = String::new();
+    string.try_reserve_exact(s.len())?;
+    string.push_str(s);
+
+    Ok(string)
+}
+
+/// Appends a string slice to a [`String`], growing it fallibly, see [`try_string`].
+pub fn try_push_str(string: &mut String, s: &str) -> Result<(), Error> {
+    string.try_reserve(s.len())?;
+    string.push_str(s);
+
+    Ok(())
+}
+
+impl From<AllocError> for Error {
+    fn from(_: AllocError) -> Self {
+        Error::INSUFFICIENT_RESOURCES
+    }
+}
+
+impl From<TryReserveError> for Error {
+    fn from(_: TryReserveError) -> Self {
+        Error::INSUFFICIENT_RESOURCES
+    }
+}
+
+/// The alignment of every pool allocation, `MEMORY_ALLOCATION_ALIGNMENT` on 64-bit Windows.
+pub const POOL_ALIGNMENT: usize = 16;
+
+/// The size of a page. Pool allocations of at least a page are page-aligned.
+pub const PAGE_SIZE: usize = 0x1000;
+
+/// The size of the header that is stored right before an over-aligned allocation, and holds the
+/// address and the size of the underlying pool allocation.
+pub const ALIGN_HEADER_SIZE: usize = core::mem::size_of::<AlignHeader>();
+
+/// The header that is stored right before an over-aligned allocation. The size of the pool
+/// allocation is recorded rather than derived from the layout, as the layout changes with every
+/// resize in place while the pool allocation does not.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct AlignHeader {
+    base: usize,
+    size: usize,
+}
+
+/// Returns whether an allocation with the given layout has to be over-allocated and carry a
+/// header, i.e. whether the pool does not already guarantee its alignment. The result only
+/// depends on the layout, such that allocation and deallocation always agree on it.
+pub const fn needs_align_header(size: usize, align: usize) -> bool {
+    if align <= POOL_ALIGNMENT {
+        return false;
+    }
+
+    !(align <= PAGE_SIZE && size >= PAGE_SIZE)
+}
+
+/// Returns the size of the pool allocation that backs an over-aligned allocation, or `None` on
+/// overflow. This leaves room for the header and for aligning the start of the allocation.
+pub const fn padded_size(size: usize, align: usize) -> Option<usize> {
+    match size.checked_add(align - 1) {
+        Some(size) => size.checked_add(ALIGN_HEADER_SIZE),
+        None => None,
+    }
 }
 
-lazy_static! {
-    /// The version of Microsoft Windows that is currently running. This is used by
-    /// [`KernelAllocator`] to determine whether to use [`ExAllocatePool2`] or
-    /// [`ExAllocatePoolWithTag`].
-    static ref VERSION_INFO: VersionInfo = {
-        VersionInfo::query().unwrap()
+/// Returns the offset of the aligned allocation from the start of the pool allocation at `base`.
+/// The offset is at least [`ALIGN_HEADER_SIZE`], and at most `align - 1 + ALIGN_HEADER_SIZE`.
+pub const fn aligned_offset(base: usize, align: usize) -> usize {
+    let start = base + ALIGN_HEADER_SIZE;
+    let aligned = (start + align - 1) & !(align - 1);
+
+    aligned - base
+}
+
+/// Returns the number of bytes that are usable at the aligned allocation, given the offset from
+/// [`aligned_offset`] and the size of the pool allocation that was allocated with
+/// [`padded_size`].
+pub const fn usable_size(offset: usize, padded: usize) -> usize {
+    padded - offset
+}
+
+/// Allocates memory for the layout through `alloc`, which allocates pool memory of the given size
+/// with [`POOL_ALIGNMENT`]. Alignments that the pool does not guarantee are handled by
+/// over-allocating and storing the address of the pool allocation in a header right before the
+/// returned pointer.
+///
+/// # Safety
+///
+/// The layout must have a non-zero size.
+unsafe fn alloc_aligned<F>(layout: Layout, alloc: F) -> *mut u8
+where
+    F: FnOnce(usize) -> *mut u8,
+{
+    if !needs_align_header(layout.size(), layout.align()) {
+        return alloc(layout.size());
+    }
+
+    let padded = match padded_size(layout.size(), layout.align()) {
+        Some(padded) => padded,
+        None => return core::ptr::null_mut(),
     };
+
+    let base = alloc(padded);
+
+    if base.is_null() {
+        return base;
+    }
+
+    let ptr = base.add(aligned_offset(base as usize, layout.align()));
+
+    (ptr.sub(ALIGN_HEADER_SIZE) as *mut AlignHeader).write_unaligned(AlignHeader {
+        base: base as usize,
+        size: padded,
+    });
+
+    ptr
+}
+
+/// Reads the header of an over-aligned allocation.
+///
+/// # Safety
+///
+/// The pointer must have been returned by [`alloc_aligned`] for a layout that needs a header.
+unsafe fn align_header(ptr: *mut u8) -> AlignHeader {
+    (ptr.sub(ALIGN_HEADER_SIZE) as *const AlignHeader).read_unaligned()
+}
+
+/// Returns the pool allocation that backs the allocation at `ptr` with the given layout.
+///
+/// # Safety
+///
+/// The pointer must have been returned by [`alloc_aligned`] with the same layout.
+unsafe fn pool_base(ptr: *mut u8, layout: Layout) -> *mut u8 {
+    if needs_align_header(layout.size(), layout.align()) {
+        align_header(ptr).base as *mut u8
+    } else {
+        ptr
+    }
+}
+
+/// Returns whether the allocation at `ptr` with the given layout can be resized to `new_size` in
+/// place, i.e. whether the pool allocation is large enough, and whether the allocation with the
+/// new size resolves to the same pool allocation when it is freed.
+///
+/// # Safety
+///
+/// The pointer must have been returned by [`alloc_aligned`] with the same layout.
+unsafe fn resize_in_place(ptr: *mut u8, layout: Layout, new_size: usize) -> bool {
+    let align = layout.align();
+
+    if needs_align_header(layout.size(), align) != needs_align_header(new_size, align) {
+        return false;
+    }
+
+    if needs_align_header(layout.size(), align) {
+        let header = align_header(ptr);
+
+        new_size <= usable_size(ptr as usize - header.base, header.size)
+    } else {
+        // The pool rounds every allocation up to its alignment.
+        new_size <= (layout.size() + POOL_ALIGNMENT - 1) & !(POOL_ALIGNMENT - 1)
+    }
 }
 
 /// Represents a kernel allocator that relies on the `ExAllocatePool` family of functions to
@@ -33,11 +327,10 @@ lazy_static! {
 #[derive(Clone, Copy)]
 pub struct KernelAllocator {
     /// The 32-bit tag to use for the pool, this is usually derived from a quadruplet of ASCII
-    /// bytes, e.g. by invoking `u32::from_ne_bytes(*b"rust")`.
+    /// bytes, e.g. by invoking `pool_tag(*b"rust")`.
     tag: u32,
 }
 
-
 /// Represents a kernel allocator that relies on the `ExAllocatePool` family of functions to
 /// allocate and free memory for the `alloc` crate.
 #[cfg(feature = "alloctag")]
@@ -47,196 +340,318 @@ pub struct KernelAllocator;
 #[cfg(not(feature = "alloctag"))]
 impl KernelAllocator {
     /// Sets up a new kernel allocator with the 32-bit tag specified. The tag is usually derived
-    /// from a quadruplet of ASCII bytes, e.g. by invoking `u32::from_ne_bytes(*b"rust")`.
+    /// from a quadruplet of ASCII bytes, e.g. by invoking `pool_tag(*b"rust")`.
     pub const fn new(tag: u32) -> Self {
         Self {
             tag,
         }
     }
+
+    /// Returns the pool tag.
+    fn tag(&self) -> u32 {
+        self.tag
+    }
 }
 
-#[cfg(not(feature = "alloctag"))]
-unsafe impl GlobalAlloc for KernelAllocator {
-    /// Uses [`ExAllocatePool2`] on Microsoft Windows 10.0.19041 and later, and
-    /// [`ExAllocatePoolWithTag`] on older versions of Microsoft Windows to allocate memory.
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let use_ex_allocate_pool2 =
-            VERSION_INFO.major() > 10 ||
-            (VERSION_INFO.major() == 10 && VERSION_INFO.build_number() == 19041);
-
-        let ptr = if use_ex_allocate_pool2 {
-            ExAllocatePool2(
-                POOL_TYPE::NonPagedPool as _,
-                layout.size() as u64,
-                self.tag,
-            )
-        } else {
-            ExAllocatePoolWithTag(
-                POOL_TYPE::NonPagedPool,
-                layout.size() as u64,
-                self.tag,
-            )
-        };
+#[cfg(feature = "alloctag")]
+impl KernelAllocator {
+    /// Returns the pool tag.
+    fn tag(&self) -> u32 {
+        pool_tag(*b"rust")
+    }
+}
+
+/// `POOL_FLAG_NON_PAGED`, no-execute non-paged pool for [`ExAllocatePool2`].
+const POOL_FLAG_NON_PAGED: u64 = 0x40;
+
+/// `POOL_FLAG_NON_PAGED_EXECUTE`, executable non-paged pool for [`ExAllocatePool2`].
+const POOL_FLAG_NON_PAGED_EXECUTE: u64 = 0x80;
+
+/// `POOL_FLAG_PAGED`, paged pool for [`ExAllocatePool2`].
+const POOL_FLAG_PAGED: u64 = 0x100;
+
+/// Builds a pool tag from four ASCII bytes, e.g. `pool_tag(*b"rust")`. The tag reads the same
+/// in pool diagnostics such as `!poolused`.
+pub const fn pool_tag(tag: [u8; 4]) -> u32 {
+    u32::from_ne_bytes(tag)
+}
+
+/// A kind of pool memory, selecting the arguments for [`ExAllocatePool2`] and
+/// [`ExAllocatePoolWithTag`].
+pub trait PoolType {
+    /// The pool type for [`ExAllocatePoolWithTag`].
+    const POOL_TYPE: POOL_TYPE::Type;
+    /// The pool flags for [`ExAllocatePool2`].
+    const POOL_FLAGS: u64;
+}
+
+/// Paged pool, which may only be accessed below `DISPATCH_LEVEL`.
+pub struct Paged;
+
+impl PoolType for Paged {
+    const POOL_TYPE: POOL_TYPE::Type = PagedPool;
+    const POOL_FLAGS: u64 = POOL_FLAG_PAGED;
+}
+
+/// Executable non-paged pool. Prefer [`NonPagedNx`] unless the memory has to hold code.
+pub struct NonPaged;
+
+impl PoolType for NonPaged {
+    const POOL_TYPE: POOL_TYPE::Type = NonPagedPool;
+    const POOL_FLAGS: u64 = POOL_FLAG_NON_PAGED_EXECUTE;
+}
+
+/// No-execute non-paged pool. `NonPagedPoolNx` was introduced with Windows 8, so earlier releases
+/// fall back to executable non-paged pool unless the `no-exec` feature is enabled.
+pub struct NonPagedNx;
+
+impl PoolType for NonPagedNx {
+    const POOL_TYPE: POOL_TYPE::Type = NonPagedPoolNx;
+    const POOL_FLAGS: u64 = POOL_FLAG_NON_PAGED;
+}
+
+/// The cached state of [`nx_pool_available`] before the version has been checked.
+const NX_POOL_UNKNOWN: u8 = 0;
+
+/// The cached state of [`nx_pool_available`] on Windows 8 and later.
+const NX_POOL_AVAILABLE: u8 = 1;
 
-        if ptr.is_null() {
-            panic!("[kernel-alloc] failed to allocate pool.");
+/// The cached state of [`nx_pool_available`] before Windows 8.
+const NX_POOL_MISSING: u8 = 2;
+
+static NX_POOL: AtomicU8 = AtomicU8::new(NX_POOL_UNKNOWN);
+
+/// Returns whether the kernel knows `NonPagedPoolNx`, which it rejects with the bug check
+/// `BAD_POOL_CALLER` before Windows 8. The version is checked once and the result is cached. With
+/// the `no-exec` feature, the driver requires Windows 8 and never falls back to executable pool.
+fn nx_pool_available() -> bool {
+    if cfg!(feature = "no-exec") {
+        return true;
+    }
+
+    match NX_POOL.load(Ordering::Relaxed) {
+        NX_POOL_AVAILABLE => true,
+        NX_POOL_MISSING => false,
+        _ => {
+            let available = VersionInfo::current() >= WindowsRelease::Win8;
+
+            let state = if available {
+                NX_POOL_AVAILABLE
+            } else {
+                NX_POOL_MISSING
+            };
+
+            NX_POOL.store(state, Ordering::Relaxed);
+
+            available
         }
+    }
+}
 
-        ptr as _
+/// Returns the pool type for [`ExAllocatePoolWithTag`] and lookaside lists, which is
+/// `NonPagedPool` in place of `NonPagedPoolNx` on releases before Windows 8.
+fn legacy_pool_type<P: PoolType>() -> POOL_TYPE::Type {
+    if P::POOL_TYPE == NonPagedPoolNx && !nx_pool_available() {
+        NonPagedPool
+    } else {
+        P::POOL_TYPE
     }
+}
 
-    /// Uses [`ExFreePool`] to free allocated memory.
-    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
-        ExFreePool(ptr as _)
+/// Allocates `size` bytes of pool memory using [`ExAllocatePool2`] where the kernel exports it,
+/// i.e. on Microsoft Windows 10.0.19041 and later, and [`ExAllocatePoolWithTag`] otherwise.
+unsafe fn alloc_pool<P: PoolType>(tag: u32, size: usize) -> *mut u8 {
+    let ptr = match ExAllocatePool2.get() {
+        Some(allocate) => allocate(P::POOL_FLAGS, size as u64, tag),
+        None => ExAllocatePoolWithTag(legacy_pool_type::<P>(), size as u64, tag),
+    };
+
+    ptr as _
+}
+
+/// Allocates memory for the layout from the pool, honoring its alignment. Returns null on
+/// failure, as `GlobalAlloc` requires.
+unsafe fn alloc_layout<P: PoolType>(tag: u32, layout: Layout) -> *mut u8 {
+    let ptr = alloc_aligned(layout, |size| alloc_pool::<P>(tag, size));
+
+    #[cfg(feature = "alloc-stats")]
+    if !ptr.is_null() {
+        stats::record_alloc(tag, ptr, layout.size());
     }
+
+    ptr
 }
 
-#[cfg(feature = "alloctag")]
-unsafe impl GlobalAlloc for KernelAllocator {
-    /// Uses [`ExAllocatePool2`] on Microsoft Windows 10.0.19041 and later, and
-    /// [`ExAllocatePoolWithTag`] on older versions of Microsoft Windows to allocate memory.
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let use_ex_allocate_pool2 =
-            VERSION_INFO.major() > 10 ||
-            (VERSION_INFO.major() == 10 && VERSION_INFO.build_number() == 19041);
-
-        let ptr = if use_ex_allocate_pool2 {
-            ExAllocatePool2(
-                POOL_TYPE::NonPagedPool as _,
-                layout.size() as u64,
-                u32::from_ne_bytes(*b"rust"),
-            )
-        } else {
-            ExAllocatePoolWithTag(
-                POOL_TYPE::NonPagedPool,
-                layout.size() as u64,
-                u32::from_ne_bytes(*b"rust"),
-            )
-        };
+/// Frees memory allocated by [`alloc_layout`] using [`ExFreePool`].
+unsafe fn dealloc_layout(tag: u32, ptr: *mut u8, layout: Layout) {
+    #[cfg(feature = "alloc-stats")]
+    stats::record_free(tag, ptr, layout.size());
+    #[cfg(not(feature = "alloc-stats"))]
+    let _ = tag;
 
-        if ptr.is_null() {
-            panic!("[kernel-alloc] failed to allocate pool.");
-        }
+    ExFreePool(pool_base(ptr, layout) as _)
+}
 
-        ptr as _
+/// Resizes in place if the underlying pool allocation is large enough, and otherwise moves the
+/// allocation.
+unsafe fn realloc_layout<P: PoolType>(
+    tag: u32,
+    ptr: *mut u8,
+    layout: Layout,
+    new_size: usize,
+) -> *mut u8 {
+    if resize_in_place(ptr, layout, new_size) {
+        #[cfg(feature = "alloc-stats")]
+        stats::record_resize(tag, ptr, layout.size(), new_size);
+
+        return ptr;
     }
 
-    /// Uses [`ExFreePool`] to free allocated memory.
-    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
-        ExFreePool(ptr as _)
+    let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+    let new_ptr = alloc_layout::<P>(tag, new_layout);
+
+    if !new_ptr.is_null() {
+        core::ptr::copy_nonoverlapping(ptr, new_ptr, core::cmp::min(layout.size(), new_size));
+        dealloc_layout(tag, ptr, layout);
     }
+
+    new_ptr
 }
 
+/// Implements [`Allocator::allocate`] on top of [`alloc_layout`].
+fn allocate_layout<P: PoolType>(tag: u32, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+    if layout.size() == 0 {
+        let dangling = unsafe { NonNull::new_unchecked(layout.align() as *mut u8) };
+        return Ok(NonNull::slice_from_raw_parts(dangling, 0));
+    }
 
+    match NonNull::new(unsafe { alloc_layout::<P>(tag, layout) }) {
+        Some(memory) => Ok(NonNull::slice_from_raw_parts(memory, layout.size())),
+        None => Err(AllocError),
+    }
+}
 
-#[cfg(not(feature = "alloctag"))]
-unsafe impl Allocator for KernelAllocator{
-    /// Allocates memory
-    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
-        #[cfg(feature = "no-exec")]
-        let pool_type = NonPagedPoolNx;
-        #[cfg(not(feature = "no-exec"))]
-        let pool_type = NonPagedPool;
-
-
-        let use_ex_allocate_pool2 =
-        VERSION_INFO.major() > 10 ||
-        (VERSION_INFO.major() == 10 && VERSION_INFO.build_number() == 19041);
-
-        let memory =  if use_ex_allocate_pool2 {
-            unsafe { 
-                ExAllocatePool2(pool_type as _, layout.size() as u64, self.tag ) as *mut u8
-            } 
-        }  else {
-            unsafe { ExAllocatePoolWithTag(
-                pool_type,
-                layout.size() as u64,
-                self.tag,
-            ) as *mut u8}
-        };
-        
-        if memory.is_null() {
-            Err(AllocError)
-        } else {
-            let slice = unsafe { core::slice::from_raw_parts_mut(memory, layout.size()) };
-            Ok(unsafe { NonNull::new_unchecked(slice) })
-        }
+/// Implements [`Allocator::deallocate`] on top of [`dealloc_layout`].
+unsafe fn deallocate_layout(tag: u32, ptr: NonNull<u8>, layout: Layout) {
+    if layout.size() != 0 {
+        dealloc_layout(tag, ptr.as_ptr(), layout);
     }
+}
 
-    /// Deallocates memory
-    unsafe fn deallocate(&self, ptr: NonNull<u8>, _layout: Layout) {
-        ExFreePool(ptr.cast::<u64>().as_ptr() as _);
+unsafe impl GlobalAlloc for KernelAllocator {
+    /// Allocates memory from the no-execute non-paged pool, honoring the alignment of the layout.
+    /// Before Windows 8, the memory comes from executable non-paged pool, see [`NonPagedNx`].
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // A null pointer is how `GlobalAlloc` reports failure, the `alloc` crate then applies
+        // the `AllocErrorPolicy`, while the fallible APIs return an error.
+        alloc_layout::<NonPagedNx>(self.tag(), layout)
+    }
+
+    /// Uses [`ExFreePool`] to free allocated memory.
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        dealloc_layout(self.tag(), ptr, layout)
+    }
+
+    /// Resizes in place if the underlying pool allocation is large enough, and otherwise moves
+    /// the allocation.
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        realloc_layout::<NonPagedNx>(self.tag(), ptr, layout, new_size)
     }
 }
 
-#[cfg(feature = "alloctag")]
-unsafe impl Allocator for KernelAllocator{
+unsafe impl Allocator for KernelAllocator {
     /// Allocates memory
     fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
-        #[cfg(feature = "no-exec")]
-        let pool_type = NonPagedPoolNx;
-        #[cfg(not(feature = "no-exec"))]
-        let pool_type = NonPagedPool;
-
-
-        let use_ex_allocate_pool2 =
-        VERSION_INFO.major() > 10 ||
-        (VERSION_INFO.major() == 10 && VERSION_INFO.build_number() == 19041);
-
-        let memory =  if use_ex_allocate_pool2 {
-            unsafe { 
-                ExAllocatePool2(pool_type as _, layout.size() as u64, u32::from_ne_bytes(*b"rust") ) as *mut u8
-            } 
-        }  else {
-            unsafe { ExAllocatePoolWithTag(
-                pool_type,
-                layout.size() as u64,
-                u32::from_ne_bytes(*b"rust"),
-            ) as *mut u8}
-        };
-        
-        if memory.is_null() {
-            Err(AllocError)
-        } else {
-            let slice = unsafe { core::slice::from_raw_parts_mut(memory, layout.size()) };
-            Ok(unsafe { NonNull::new_unchecked(slice) })
-        }
+        allocate_layout::<NonPagedNx>(self.tag(), layout)
     }
 
     /// Deallocates memory
-    unsafe fn deallocate(&self, ptr: NonNull<u8>, _layout: Layout) {
-        ExFreePool(ptr.cast::<u64>().as_ptr() as _);
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        deallocate_layout(self.tag(), ptr, layout)
     }
 }
 
+/// An allocator for the given pool type that tags its allocations with `TAG`, such that every
+/// subsystem can show up under its own tag in pool diagnostics.
+///
+/// ```ignore
+/// type RequestAllocator = PoolAllocator<{ pool_tag(*b"Rreq") }, NonPagedNx>;
+///
+/// let mut requests = Vec::new_in(RequestAllocator::new());
+/// ```
+pub struct PoolAllocator<const TAG: u32, P: PoolType = NonPagedNx> {
+    pool: PhantomData<P>,
+}
 
-/// The physical kernel allocator structure.
-#[derive(Clone, Copy)]
-pub struct PhysicalAllocator;
+/// A [`PoolAllocator`] for paged pool.
+pub type PagedPoolAllocator<const TAG: u32> = PoolAllocator<TAG, Paged>;
+
+/// A [`PoolAllocator`] for executable non-paged pool.
+pub type NonPagedPoolAllocator<const TAG: u32> = PoolAllocator<TAG, NonPaged>;
+
+/// A [`PoolAllocator`] for no-execute non-paged pool.
+pub type NonPagedNxPoolAllocator<const TAG: u32> = PoolAllocator<TAG, NonPagedNx>;
+
+impl<const TAG: u32, P: PoolType> PoolAllocator<TAG, P> {
+    /// Returns the allocator.
+    pub const fn new() -> Self {
+        Self { pool: PhantomData }
+    }
+
+    /// Returns the pool tag.
+    pub const fn tag(&self) -> u32 {
+        TAG
+    }
+}
+
+impl<const TAG: u32, P: PoolType> Clone for PoolAllocator<TAG, P> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<const TAG: u32, P: PoolType> Copy for PoolAllocator<TAG, P> {}
 
+impl<const TAG: u32, P: PoolType> Default for PoolAllocator<TAG, P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl<const TAG: u32, P: PoolType> GlobalAlloc for PoolAllocator<TAG, P> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        alloc_layout::<P>(TAG, layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        dealloc_layout(TAG, ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        realloc_layout::<P>(TAG, ptr, layout, new_size)
+    }
+}
+
+unsafe impl<const TAG: u32, P: PoolType> Allocator for PoolAllocator<TAG, P> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        allocate_layout::<P>(TAG, layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        deallocate_layout(TAG, ptr, layout)
+    }
+}
 
+/// The physical kernel allocator structure. Allocations are physically contiguous, cached, and
+/// may be anywhere in physical memory, see [`ContiguousBuffer`] for control over the placement.
+#[derive(Clone, Copy)]
+pub struct PhysicalAllocator;
 
 unsafe impl Allocator for PhysicalAllocator {
     /// Create a new physical allocator
     fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
-        let mut boundary: PHYSICAL_ADDRESS = unsafe { core::mem::zeroed() };
-        let mut lowest: PHYSICAL_ADDRESS = unsafe { core::mem::zeroed() };
-        let mut highest: PHYSICAL_ADDRESS = unsafe { core::mem::zeroed() };
-
-        boundary.QuadPart = 0;
-        lowest.QuadPart =0;
-        highest.QuadPart = -1;
-
-        let memory = unsafe {
-            MmAllocateContiguousMemorySpecifyCacheNode(
-                layout.size() as u64,
-                lowest,
-                highest,
-                boundary,
-                MmCached,
-                MM_ANY_NODE_OK,
-            )
-        } as *mut u8;
+        let memory = unsafe { alloc_contiguous(layout.size(), &ContiguousOptions::default()) };
+
         if memory.is_null() {
             Err(AllocError)
         } else {
@@ -246,7 +661,514 @@ unsafe impl Allocator for PhysicalAllocator {
     }
 
     /// Free allocated memory
-    unsafe fn deallocate(&self, ptr: NonNull<u8>, _layout: Layout) {
-        MmFreeContiguousMemory(ptr.cast().as_ptr());
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        MmFreeContiguousMemorySpecifyCache(ptr.cast().as_ptr(), layout.size() as _, MmCached);
+    }
+}
+
+/// Where and how a [`ContiguousBuffer`] is allocated. The default allows any physical address,
+/// no boundary, cached memory and any NUMA node, like [`PhysicalAllocator`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ContiguousOptions {
+    /// The lowest physical address the buffer may start at.
+    pub lowest: PhysicalAddress,
+    /// The highest physical address the buffer may end at, e.g. `0xFFFF_FFFF` for devices that
+    /// can only address 32 bits.
+    pub highest: PhysicalAddress,
+    /// If not zero, a power of two that is at least the size of the buffer, such that the buffer
+    /// does not cross a multiple of it, e.g. for devices that cannot cross a 64 KiB boundary.
+    pub boundary: u64,
+    /// The caching of the mapping of the buffer.
+    pub caching: MemoryCaching,
+    /// The preferred NUMA node, or `None` for any node.
+    pub node: Option<u32>,
+}
+
+impl ContiguousOptions {
+    /// Options for a buffer that is addressable with 32 bits, i.e. below 4 GiB.
+    pub fn below_4gb() -> Self {
+        Self {
+            highest: PhysicalAddress::from(0xFFFF_FFFF),
+            ..Self::default()
+        }
+    }
+
+    /// Checks the options for a buffer of the given size.
+    fn validate(&self, size: usize) -> Result<(), Error> {
+        let lowest: u64 = self.lowest.into();
+        let highest: u64 = self.highest.into();
+
+        if size == 0 || lowest > highest {
+            return Err(Error::INVALID_PARAMETER);
+        }
+
+        if self.boundary != 0
+            && (!self.boundary.is_power_of_two() || self.boundary < size as u64)
+        {
+            return Err(Error::INVALID_PARAMETER);
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for ContiguousOptions {
+    fn default() -> Self {
+        Self {
+            lowest: PhysicalAddress::from(0),
+            highest: PhysicalAddress::from(u64::MAX),
+            boundary: 0,
+            caching: MemoryCaching::Cached,
+            node: None,
+        }
+    }
+}
+
+/// Allocates physically contiguous memory with [`MmAllocateContiguousMemorySpecifyCacheNode`],
+/// which has to be freed with [`MmFreeContiguousMemorySpecifyCache`].
+unsafe fn alloc_contiguous(size: usize, options: &ContiguousOptions) -> *mut u8 {
+    let mut boundary: PHYSICAL_ADDRESS = core::mem::zeroed();
+
+    boundary.QuadPart = options.boundary as _;
+
+    MmAllocateContiguousMemorySpecifyCacheNode(
+        size as u64,
+        options.lowest.into(),
+        options.highest.into(),
+        boundary,
+        options.caching as _,
+        options.node.unwrap_or(MM_ANY_NODE_OK),
+    ) as *mut u8
+}
+
+/// A physically contiguous buffer in non-paged memory, e.g. for DMA, which is freed on drop.
+///
+/// ```ignore
+/// let options = ContiguousOptions {
+///     boundary: 0x10000,
+///     caching: MemoryCaching::NonCached,
+///     ..ContiguousOptions::below_4gb()
+/// };
+///
+/// let buffer = ContiguousBuffer::new(0x4000, &options)?;
+/// device.set_ring_base(buffer.physical_address());
+/// ```
+pub struct ContiguousBuffer {
+    ptr: NonNull<u8>,
+    size: usize,
+    caching: MemoryCaching,
+    physical_address: PhysicalAddress,
+}
+
+unsafe impl Send for ContiguousBuffer {}
+
+unsafe impl Sync for ContiguousBuffer {}
+
+impl ContiguousBuffer {
+    /// Allocates a zeroed buffer of the given size. Fails with [`Error::INVALID_PARAMETER`] if
+    /// the options do not fit the size, and with [`Error::INSUFFICIENT_RESOURCES`] if no
+    /// contiguous range satisfies them.
+    pub fn new(size: usize, options: &ContiguousOptions) -> Result<Self, Error> {
+        options.validate(size)?;
+
+        let ptr = NonNull::new(unsafe { alloc_contiguous(size, options) })
+            .ok_or(Error::INSUFFICIENT_RESOURCES)?;
+
+        unsafe { ptr.as_ptr().write_bytes(0, size) };
+
+        let physical_address = unsafe { MmGetPhysicalAddress(ptr.as_ptr() as _) }.into();
+
+        Ok(Self {
+            ptr,
+            size,
+            caching: options.caching,
+            physical_address,
+        })
+    }
+
+    /// Returns the virtual address of the buffer.
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.ptr.as_ptr()
+    }
+
+    /// Returns the size of the buffer in bytes.
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    /// Returns whether the buffer is empty, which it never is.
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Returns the physical address of the start of the buffer. The buffer is contiguous, so the
+    /// byte at offset `n` is at this address plus `n`.
+    pub fn physical_address(&self) -> PhysicalAddress {
+        self.physical_address
+    }
+
+    /// Returns the caching of the mapping of the buffer.
+    pub fn caching(&self) -> MemoryCaching {
+        self.caching
+    }
+
+    /// Returns the buffer as a slice.
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.ptr.as_ptr(), self.size) }
+    }
+
+    /// Returns the buffer as a mutable slice.
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.size) }
     }
-}
\ No newline at end of file
+}
+
+impl core::ops::Deref for ContiguousBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl core::ops::DerefMut for ContiguousBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.as_mut_slice()
+    }
+}
+
+impl Drop for ContiguousBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            MmFreeContiguousMemorySpecifyCache(
+                self.ptr.as_ptr() as _,
+                self.size as _,
+                self.caching as _,
+            );
+        }
+    }
+}
+
+/// A typed object pool backed by a lookaside list, see [`ExInitializeLookasideListEx`]. Objects
+/// are handed out as [`PoolBox`]es, which return their memory to the list when dropped, such that
+/// hot paths that allocate and free the same kind of object rarely reach the pool allocator.
+///
+/// ```ignore
+/// let pool = LookasidePool::<RequestContext>::new(pool_tag(*b"Rctx"))?;
+///
+/// let context = pool.try_alloc(RequestContext::default())?;
+/// ```
+pub struct LookasidePool<T, P: PoolType = NonPagedNx> {
+    // The list is linked into a list of the system that is used to tune its depth, so it has to
+    // stay at the same address.
+    list: Box<UnsafeCell<LOOKASIDE_LIST_EX>>,
+    marker: PhantomData<(fn() -> T, P)>,
+}
+
+unsafe impl<T: Send, P: PoolType> Send for LookasidePool<T, P> {}
+
+unsafe impl<T: Send, P: PoolType> Sync for LookasidePool<T, P> {}
+
+/// A [`LookasidePool`] for paged pool, which may only be used below `DISPATCH_LEVEL`.
+pub type PagedLookasidePool<T> = LookasidePool<T, Paged>;
+
+impl<T, P: PoolType> LookasidePool<T, P> {
+    /// The size of the entries. Free entries hold an `SLIST_ENTRY`, so they are never smaller.
+    const ENTRY_SIZE: usize = if size_of::<T>() > size_of::<SLIST_ENTRY>() {
+        size_of::<T>()
+    } else {
+        size_of::<SLIST_ENTRY>()
+    };
+
+    /// Sets up a lookaside list for objects of type `T` with the given pool tag. Types that need a
+    /// larger alignment than the pool guarantees are rejected with [`Error::INVALID_PARAMETER`].
+    pub fn new(tag: u32) -> Result<Self, Error> {
+        if core::mem::align_of::<T>() > POOL_ALIGNMENT {
+            return Err(Error::INVALID_PARAMETER);
+        }
+
+        let list = try_box(UnsafeCell::new(unsafe { core::mem::zeroed::<LOOKASIDE_LIST_EX>() }))?;
+
+        unsafe {
+            ExInitializeLookasideListEx(
+                list.get(),
+                None,
+                None,
+                legacy_pool_type::<P>() as _,
+                0,
+                Self::ENTRY_SIZE as _,
+                tag,
+                0,
+            )
+        }
+        .into_result()?;
+
+        Ok(Self {
+            list,
+            marker: PhantomData,
+        })
+    }
+
+    /// Returns the counter at the given field of the list.
+    fn counter(&self, field: *mut u32) -> &AtomicU32 {
+        // The inline functions of the WDK update the counters with plain increments from any
+        // processor, atomics are the sound equivalent.
+        unsafe { AtomicU32::from_ptr(field) }
+    }
+
+    /// Allocates an entry, like `ExAllocateFromLookasideListEx`.
+    fn alloc_entry(&self) -> *mut u8 {
+        let list = self.list.get();
+
+        unsafe {
+            self.counter(addr_of_mut!((*list).L.TotalAllocates))
+                .fetch_add(1, Ordering::Relaxed);
+
+            let entry = ExpInterlockedPopEntrySList(addr_of_mut!((*list).L.ListHead));
+
+            if !entry.is_null() {
+                return entry as _;
+            }
+
+            self.counter(addr_of_mut!((*list).L.AllocateMisses))
+                .fetch_add(1, Ordering::Relaxed);
+
+            match (*list).L.AllocateEx {
+                Some(allocate) => {
+                    allocate((*list).L.Type, (*list).L.Size as _, (*list).L.Tag, list) as _
+                }
+                None => core::ptr::null_mut(),
+            }
+        }
+    }
+
+    /// Frees an entry, like `ExFreeToLookasideListEx`.
+    unsafe fn free_entry(&self, entry: *mut u8) {
+        let list = self.list.get();
+
+        self.counter(addr_of_mut!((*list).L.TotalFrees))
+            .fetch_add(1, Ordering::Relaxed);
+
+        let depth = core::ptr::read_volatile(addr_of!((*list).L.Depth));
+
+        if (*list).L.ListHead.depth() >= depth {
+            self.counter(addr_of_mut!((*list).L.FreeMisses))
+                .fetch_add(1, Ordering::Relaxed);
+
+            if let Some(free) = (*list).L.FreeEx {
+                free(entry as _, list);
+            }
+        } else {
+            ExpInterlockedPushEntrySList(addr_of_mut!((*list).L.ListHead), entry as _);
+        }
+    }
+
+    /// Moves the value into an entry of the list, failing with
+    /// [`Error::INSUFFICIENT_RESOURCES`] if the list is empty and the pool is exhausted.
+    pub fn try_alloc(&self, value: T) -> Result<PoolBox<'_, T, P>, Error> {
+        let entry =
+            NonNull::new(self.alloc_entry() as *mut T).ok_or(Error::INSUFFICIENT_RESOURCES)?;
+
+        unsafe { entry.as_ptr().write(value) };
+
+        Ok(PoolBox { entry, pool: self })
+    }
+}
+
+impl<T, P: PoolType> Drop for LookasidePool<T, P> {
+    fn drop(&mut self) {
+        // Every `PoolBox` borrows the pool, so all the entries are back in the list or freed.
+        unsafe { ExDeleteLookasideListEx(self.list.get()) }
+    }
+}
+
+/// An object allocated from a [`LookasidePool`], which is dropped and returned to the list when
+/// the box is dropped.
+pub struct PoolBox<'a, T, P: PoolType = NonPagedNx> {
+    entry: NonNull<T>,
+    pool: &'a LookasidePool<T, P>,
+}
+
+unsafe impl<T: Send, P: PoolType> Send for PoolBox<'_, T, P> {}
+
+unsafe impl<T: Sync, P: PoolType> Sync for PoolBox<'_, T, P> {}
+
+impl<T, P: PoolType> core::ops::Deref for PoolBox<'_, T, P> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.entry.as_ref() }
+    }
+}
+
+impl<T, P: PoolType> core::ops::DerefMut for PoolBox<'_, T, P> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.entry.as_mut() }
+    }
+}
+
+impl<T, P: PoolType> PoolBox<'_, T, P> {
+    /// Moves the value out of the box, and returns the entry to the list.
+    pub fn into_inner(self) -> T {
+        let this = core::mem::ManuallyDrop::new(self);
+
+        unsafe {
+            let value = this.entry.as_ptr().read();
+            this.pool.free_entry(this.entry.as_ptr() as _);
+
+            value
+        }
+    }
+}
+
+impl<T, P: PoolType> Drop for PoolBox<'_, T, P> {
+    fn drop(&mut self) {
+        unsafe {
+            core::ptr::drop_in_place(self.entry.as_ptr());
+            self.pool.free_entry(self.entry.as_ptr() as _);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A pool allocation made with the host allocator, rounded up and aligned like the pool does.
+    struct HostPool {
+        base: *mut u8,
+        size: usize,
+    }
+
+    impl HostPool {
+        /// Allocates through [`alloc_aligned`], and returns the pool allocation and the pointer.
+        fn alloc(layout: Layout) -> (Self, *mut u8) {
+            let mut pool = HostPool {
+                base: core::ptr::null_mut(),
+                size: 0,
+            };
+
+            let ptr = unsafe {
+                alloc_aligned(layout, |size| {
+                    pool.size = (size + POOL_ALIGNMENT - 1) & !(POOL_ALIGNMENT - 1);
+                    pool.base = alloc::alloc::alloc(Self::layout(pool.size));
+                    pool.base
+                })
+            };
+
+            assert!(!ptr.is_null());
+
+            (pool, ptr)
+        }
+
+        fn layout(size: usize) -> Layout {
+            Layout::from_size_align(size, POOL_ALIGNMENT).unwrap()
+        }
+
+        /// Returns whether `len` bytes at `ptr` lie within the pool allocation.
+        fn contains(&self, ptr: *mut u8, len: usize) -> bool {
+            ptr as usize >= self.base as usize
+                && ptr as usize + len <= self.base as usize + self.size
+        }
+    }
+
+    impl Drop for HostPool {
+        fn drop(&mut self) {
+            unsafe { alloc::alloc::dealloc(self.base, Self::layout(self.size)) }
+        }
+    }
+
+    /// Resizes like [`realloc_layout`] would, returning the new layout if the allocation could be
+    /// resized in place.
+    fn resize(pool: &HostPool, ptr: *mut u8, layout: Layout, new_size: usize) -> Option<Layout> {
+        if !unsafe { resize_in_place(ptr, layout, new_size) } {
+            return None;
+        }
+
+        assert!(pool.contains(ptr, new_size), "resized past the pool allocation");
+        assert_eq!(unsafe { pool_base(ptr, layout) }, pool.base);
+
+        Some(Layout::from_size_align(new_size, layout.align()).unwrap())
+    }
+
+    #[test]
+    fn header_is_only_needed_for_unguaranteed_alignments() {
+        assert!(!needs_align_header(8, 8));
+        assert!(!needs_align_header(100, POOL_ALIGNMENT));
+        assert!(needs_align_header(100, 64));
+        assert!(needs_align_header(100, PAGE_SIZE));
+        assert!(!needs_align_header(PAGE_SIZE, 64));
+        assert!(!needs_align_header(PAGE_SIZE, PAGE_SIZE));
+        assert!(needs_align_header(PAGE_SIZE, 2 * PAGE_SIZE));
+    }
+
+    #[test]
+    fn padding_leaves_room_for_header_and_alignment() {
+        assert_eq!(padded_size(100, 64), Some(100 + 63 + ALIGN_HEADER_SIZE));
+        assert_eq!(padded_size(usize::MAX - 10, 64), None);
+
+        for base in (0x1000..0x1100).step_by(POOL_ALIGNMENT) {
+            for align in [32, 64, 128, PAGE_SIZE] {
+                let offset = aligned_offset(base, align);
+
+                assert_eq!((base + offset) % align, 0);
+                assert!(offset >= ALIGN_HEADER_SIZE);
+                assert!(offset <= align - 1 + ALIGN_HEADER_SIZE);
+                assert!(usable_size(offset, padded_size(100, align).unwrap()) >= 100);
+            }
+        }
+    }
+
+    #[test]
+    fn aligns_allocations() {
+        for align in [1, 8, 16, 32, 64, 256, PAGE_SIZE] {
+            let layout = Layout::from_size_align(100, align).unwrap();
+            let (pool, ptr) = HostPool::alloc(layout);
+
+            assert_eq!(ptr as usize % align, 0);
+            assert!(pool.contains(ptr, layout.size()));
+            assert_eq!(unsafe { pool_base(ptr, layout) }, pool.base);
+        }
+    }
+
+    #[test]
+    fn grows_and_shrinks_over_aligned_allocation_in_place() {
+        let layout = Layout::from_size_align(100, 64).unwrap();
+        let (pool, ptr) = HostPool::alloc(layout);
+        let padded = padded_size(layout.size(), layout.align()).unwrap();
+        let usable = usable_size(ptr as usize - pool.base as usize, padded);
+
+        // Growing twice must be checked against the pool allocation, not against the size the
+        // first resize grew the allocation to.
+        let layout = resize(&pool, ptr, layout, 110).unwrap();
+        let layout = resize(&pool, ptr, layout, usable).unwrap();
+        assert!(resize(&pool, ptr, layout, usable + 1).is_none());
+
+        let layout = resize(&pool, ptr, layout, 50).unwrap();
+        let layout = resize(&pool, ptr, layout, usable).unwrap();
+
+        assert_eq!(layout.size(), usable);
+    }
+
+    #[test]
+    fn grows_and_shrinks_pool_aligned_allocation_in_place() {
+        let layout = Layout::from_size_align(17, 8).unwrap();
+        let (pool, ptr) = HostPool::alloc(layout);
+
+        let layout = resize(&pool, ptr, layout, 24).unwrap();
+        let layout = resize(&pool, ptr, layout, 32).unwrap();
+        assert!(resize(&pool, ptr, layout, 33).is_none());
+
+        let layout = resize(&pool, ptr, layout, 8).unwrap();
+        assert!(resize(&pool, ptr, layout, 32).is_none());
+    }
+
+    #[test]
+    fn does_not_resize_across_the_header_boundary() {
+        let layout = Layout::from_size_align(PAGE_SIZE - 64, 64).unwrap();
+        let (pool, ptr) = HostPool::alloc(layout);
+
+        // The grown allocation would be freed without looking for a header.
+        assert!(resize(&pool, ptr, layout, PAGE_SIZE).is_none());
+    }
+}
@@ -0,0 +1,365 @@
+//! Per-tag allocation statistics, enabled with the `alloc-stats` feature.
+//!
+//! Every allocation of [`KernelAllocator`](super::KernelAllocator) and
+//! [`PoolAllocator`](super::PoolAllocator) is counted under its pool tag, and recorded in a table
+//! of live allocations, such that the allocations that are still outstanding can be dumped when
+//! the driver unloads. All the bookkeeping lives in fixed-size static tables that are only
+//! updated with atomic operations, so it never allocates, never blocks and is safe to use at
+//! `DISPATCH_LEVEL`. Allocations that do not fit into the tables are still counted, see
+//! [`untracked`].
+//!
+//! ```ignore
+//! extern "system" fn driver_unload(driver: *mut DRIVER_OBJECT) {
+//!     // ...
+//!     win_kernel::allocator::stats::dump_leaks();
+//! }
+//! ```
+
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+
+use crate::headers::RtlCaptureStackBackTrace;
+
+/// The maximum number of distinct pool tags that are tracked.
+pub const MAX_TAGS: usize = 32;
+
+/// The maximum number of live allocations that are tracked.
+pub const MAX_LIVE_ALLOCATIONS: usize = 4096;
+
+/// The number of return addresses that are recorded per allocation when call sites are captured.
+pub const CALL_SITE_FRAMES: usize = 4;
+
+/// The number of slots that are probed to record or find a live allocation.
+const MAX_PROBES: usize = 64;
+
+/// The frames of the allocator itself that are skipped when capturing call sites.
+const SKIPPED_FRAMES: u32 = 3;
+
+/// The statistics of a pool tag. The layout is fixed, such that it can be returned to user mode
+/// as is, e.g. from an ioctl.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TagStats {
+    /// The pool tag.
+    pub tag: u32,
+    /// The number of live allocations.
+    pub count: usize,
+    /// The number of live bytes.
+    pub bytes: usize,
+    /// The highest number of live bytes so far.
+    pub peak_bytes: usize,
+    /// The number of allocations so far.
+    pub total_allocations: usize,
+}
+
+/// A live allocation.
+#[derive(Clone, Copy, Debug)]
+pub struct LiveAllocation {
+    /// The address of the allocation.
+    pub address: usize,
+    /// The size of the allocation in bytes.
+    pub size: usize,
+    /// The pool tag.
+    pub tag: u32,
+    /// The return addresses of the callers of the allocator, innermost first, or zeroes if call
+    /// sites are not captured, see [`set_capture_call_sites`].
+    pub call_site: [usize; CALL_SITE_FRAMES],
+}
+
+struct TagSlot {
+    tag: AtomicU32,
+    count: AtomicUsize,
+    bytes: AtomicUsize,
+    peak_bytes: AtomicUsize,
+    total_allocations: AtomicUsize,
+}
+
+impl TagSlot {
+    const fn new() -> Self {
+        Self {
+            tag: AtomicU32::new(0),
+            count: AtomicUsize::new(0),
+            bytes: AtomicUsize::new(0),
+            peak_bytes: AtomicUsize::new(0),
+            total_allocations: AtomicUsize::new(0),
+        }
+    }
+
+    fn snapshot(&self) -> TagStats {
+        TagStats {
+            tag: self.tag.load(Ordering::Relaxed),
+            count: self.count.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            peak_bytes: self.peak_bytes.load(Ordering::Relaxed),
+            total_allocations: self.total_allocations.load(Ordering::Relaxed),
+        }
+    }
+}
+
+struct LiveSlot {
+    /// The address of the allocation, or zero if the slot is free.
+    address: AtomicUsize,
+    size: AtomicUsize,
+    tag: AtomicU32,
+    call_site: [AtomicUsize; CALL_SITE_FRAMES],
+}
+
+impl LiveSlot {
+    const fn new() -> Self {
+        Self {
+            address: AtomicUsize::new(0),
+            size: AtomicUsize::new(0),
+            tag: AtomicU32::new(0),
+            call_site: [const { AtomicUsize::new(0) }; CALL_SITE_FRAMES],
+        }
+    }
+}
+
+static TAGS: [TagSlot; MAX_TAGS] = [const { TagSlot::new() }; MAX_TAGS];
+
+static LIVE: [LiveSlot; MAX_LIVE_ALLOCATIONS] = [const { LiveSlot::new() }; MAX_LIVE_ALLOCATIONS];
+
+/// The number of allocations that are not in the table of live allocations.
+static UNTRACKED: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of allocations whose tag is not in the table of tags.
+static UNTRACKED_TAGS: AtomicUsize = AtomicUsize::new(0);
+
+static CAPTURE_CALL_SITES: AtomicBool = AtomicBool::new(false);
+
+/// Enables or disables capturing the call site of every allocation. Capturing walks the stack on
+/// every allocation, so it is disabled by default.
+pub fn set_capture_call_sites(enabled: bool) {
+    CAPTURE_CALL_SITES.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns the slot of the tag, claiming a free slot for new tags.
+fn tag_slot(tag: u32, claim: bool) -> Option<&'static TagSlot> {
+    for slot in TAGS.iter() {
+        match slot.tag.load(Ordering::Acquire) {
+            current if current == tag => return Some(slot),
+            0 if claim => {
+                match slot.tag.compare_exchange(0, tag, Ordering::AcqRel, Ordering::Acquire) {
+                    Ok(_) => return Some(slot),
+                    Err(current) if current == tag => return Some(slot),
+                    Err(_) => {}
+                }
+            }
+            0 => return None,
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Returns the index of the first slot to probe for the address.
+fn live_index(address: usize) -> usize {
+    // Pool allocations are at least 16-byte aligned, so the low bits carry no information. The
+    // multiplication mixes the remaining bits into the high bits, which select the slot.
+    let hash = (address >> 4).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+
+    hash >> (usize::BITS - MAX_LIVE_ALLOCATIONS.trailing_zeros())
+}
+
+fn probe(address: usize) -> impl Iterator<Item = &'static LiveSlot> {
+    let start = live_index(address);
+
+    (0..MAX_PROBES).map(move |i| &LIVE[(start + i) % MAX_LIVE_ALLOCATIONS])
+}
+
+/// Records a new allocation.
+pub(super) fn record_alloc(tag: u32, ptr: *mut u8, size: usize) {
+    match tag_slot(tag, true) {
+        Some(slot) => {
+            slot.count.fetch_add(1, Ordering::Relaxed);
+            slot.total_allocations.fetch_add(1, Ordering::Relaxed);
+
+            let bytes = slot.bytes.fetch_add(size, Ordering::Relaxed) + size;
+            slot.peak_bytes.fetch_max(bytes, Ordering::Relaxed);
+        }
+        None => {
+            UNTRACKED_TAGS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    let address = ptr as usize;
+
+    for slot in probe(address) {
+        if slot
+            .address
+            .compare_exchange(0, address, Ordering::AcqRel, Ordering::Relaxed)
+            .is_ok()
+        {
+            slot.size.store(size, Ordering::Relaxed);
+            slot.tag.store(tag, Ordering::Relaxed);
+
+            let mut frames = [core::ptr::null_mut(); CALL_SITE_FRAMES];
+
+            if CAPTURE_CALL_SITES.load(Ordering::Relaxed) {
+                unsafe {
+                    RtlCaptureStackBackTrace(
+                        SKIPPED_FRAMES,
+                        CALL_SITE_FRAMES as _,
+                        frames.as_mut_ptr(),
+                        core::ptr::null_mut(),
+                    );
+                }
+            }
+
+            for (frame, address) in slot.call_site.iter().zip(frames) {
+                frame.store(address as usize, Ordering::Relaxed);
+            }
+
+            return;
+        }
+    }
+
+    UNTRACKED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Records that an allocation was freed.
+pub(super) fn record_free(tag: u32, ptr: *mut u8, size: usize) {
+    if let Some(slot) = tag_slot(tag, false) {
+        slot.count.fetch_sub(1, Ordering::Relaxed);
+        slot.bytes.fetch_sub(size, Ordering::Relaxed);
+    }
+
+    let address = ptr as usize;
+
+    for slot in probe(address) {
+        if slot
+            .address
+            .compare_exchange(address, 0, Ordering::AcqRel, Ordering::Relaxed)
+            .is_ok()
+        {
+            return;
+        }
+    }
+}
+
+/// Records that an allocation was resized in place.
+pub(super) fn record_resize(tag: u32, ptr: *mut u8, old_size: usize, new_size: usize) {
+    if let Some(slot) = tag_slot(tag, false) {
+        let bytes = slot.bytes.fetch_add(new_size, Ordering::Relaxed) + new_size;
+        slot.bytes.fetch_sub(old_size, Ordering::Relaxed);
+        slot.peak_bytes.fetch_max(bytes.saturating_sub(old_size), Ordering::Relaxed);
+    }
+
+    let address = ptr as usize;
+
+    for slot in probe(address) {
+        if slot.address.load(Ordering::Acquire) == address {
+            slot.size.store(new_size, Ordering::Relaxed);
+            return;
+        }
+    }
+}
+
+/// Returns the statistics of the given tag, if it has been used.
+pub fn tag_stats(tag: u32) -> Option<TagStats> {
+    tag_slot(tag, false).map(TagSlot::snapshot)
+}
+
+/// Copies the statistics of every tag that has been used into `out`, and returns the number of
+/// entries written.
+pub fn all_tag_stats(out: &mut [TagStats]) -> usize {
+    let slots = TAGS
+        .iter()
+        .take_while(|slot| slot.tag.load(Ordering::Acquire) != 0);
+
+    let mut written = 0;
+
+    for (out, slot) in out.iter_mut().zip(slots) {
+        *out = slot.snapshot();
+        written += 1;
+    }
+
+    written
+}
+
+/// Returns the number of allocations that were not recorded in the table of live allocations,
+/// and the number of allocations whose tag did not fit into the table of tags.
+pub fn untracked() -> (usize, usize) {
+    (
+        UNTRACKED.load(Ordering::Relaxed),
+        UNTRACKED_TAGS.load(Ordering::Relaxed),
+    )
+}
+
+/// Calls `f` for every live allocation that is recorded.
+pub fn for_each_live_allocation<F>(mut f: F)
+where
+    F: FnMut(&LiveAllocation),
+{
+    for slot in LIVE.iter() {
+        let address = slot.address.load(Ordering::Acquire);
+
+        if address == 0 {
+            continue;
+        }
+
+        let mut call_site = [0; CALL_SITE_FRAMES];
+
+        for (out, frame) in call_site.iter_mut().zip(slot.call_site.iter()) {
+            *out = frame.load(Ordering::Relaxed);
+        }
+
+        f(&LiveAllocation {
+            address,
+            size: slot.size.load(Ordering::Relaxed),
+            tag: slot.tag.load(Ordering::Relaxed),
+            call_site,
+        });
+    }
+}
+
+/// Prints the statistics of every tag with live allocations and every outstanding allocation,
+/// usually from the unload routine of the driver, once everything has been freed. Returns the
+/// number of outstanding allocations.
+pub fn dump_leaks() -> usize {
+    let mut leaks = 0;
+
+    for slot in TAGS.iter() {
+        let stats = slot.snapshot();
+
+        if stats.tag == 0 || stats.count == 0 {
+            continue;
+        }
+
+        leaks += stats.count;
+
+        let tag = stats.tag.to_ne_bytes();
+
+        crate::println!(
+            "[kernel-alloc] tag '{}{}{}{}': {} allocations, {} bytes outstanding (peak {} bytes)",
+            tag[0] as char,
+            tag[1] as char,
+            tag[2] as char,
+            tag[3] as char,
+            stats.count,
+            stats.bytes,
+            stats.peak_bytes
+        );
+    }
+
+    for_each_live_allocation(|allocation| {
+        crate::println!(
+            "[kernel-alloc]   {:#x}: {} bytes, called from {:#x?}",
+            allocation.address,
+            allocation.size,
+            allocation.call_site
+        );
+    });
+
+    let (untracked, untracked_tags) = untracked();
+
+    if untracked != 0 || untracked_tags != 0 {
+        crate::println!(
+            "[kernel-alloc] {} allocations and {} tags were not tracked",
+            untracked,
+            untracked_tags
+        );
+    }
+
+    leaks
+}
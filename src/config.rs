@@ -0,0 +1,352 @@
+//! config mod
+//!
+//! Declarative driver configuration loaded from the `Parameters` subkey of the service key. A
+//! configuration is a plain Rust struct declared through [`registry_config!`], where each field
+//! names its registry value and its default.
+//!
+//! ```ignore
+//! win_kernel::registry_config! {
+//!     /// The configuration of the driver.
+//!     pub struct Config {
+//!         /// The maximum number of queued requests.
+//!         pub max_requests: u32 as "MaxRequests" = 64,
+//!         /// Whether to log every request.
+//!         pub verbose: bool as "Verbose" = false,
+//!     }
+//!
+//!     validate = |config: &Config| {
+//!         if config.max_requests == 0 {
+//!             return Err(win_kernel::Error::INVALID_PARAMETER);
+//!         }
+//!
+//!         Ok(())
+//!     };
+//! }
+//!
+//! let config = Config::load(registry_path)?;
+//!
+//! // Reload the configuration whenever the `Parameters` key changes.
+//! let watcher = Config::watch(registry_path, |config| match config {
+//!     Ok(config) => apply(config),
+//!     Err(e) => println!("invalid configuration: {}", e),
+//! })?;
+//! ```
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::error::Error;
+use crate::registry::{NotifyFilter, RegistryAccess, RegistryKey, RegistryValue, RegistryWatcher};
+
+/// Converts a [`RegistryValue`] into a configuration field. Values of the wrong type are
+/// reported as [`Error::OBJECT_TYPE_MISMATCH`].
+pub trait FromRegistryValue: Sized {
+    /// Converts the value.
+    fn from_registry_value(value: RegistryValue) -> Result<Self, Error>;
+}
+
+impl FromRegistryValue for RegistryValue {
+    fn from_registry_value(value: RegistryValue) -> Result<Self, Error> {
+        Ok(value)
+    }
+}
+
+impl FromRegistryValue for u32 {
+    fn from_registry_value(value: RegistryValue) -> Result<Self, Error> {
+        match value {
+            RegistryValue::Dword(value) => Ok(value),
+            _ => Err(Error::OBJECT_TYPE_MISMATCH),
+        }
+    }
+}
+
+impl FromRegistryValue for u64 {
+    fn from_registry_value(value: RegistryValue) -> Result<Self, Error> {
+        match value {
+            RegistryValue::Qword(value) => Ok(value),
+            RegistryValue::Dword(value) => Ok(value as _),
+            _ => Err(Error::OBJECT_TYPE_MISMATCH),
+        }
+    }
+}
+
+impl FromRegistryValue for bool {
+    fn from_registry_value(value: RegistryValue) -> Result<Self, Error> {
+        match value {
+            RegistryValue::Dword(value) => Ok(value != 0),
+            _ => Err(Error::OBJECT_TYPE_MISMATCH),
+        }
+    }
+}
+
+impl FromRegistryValue for String {
+    fn from_registry_value(value: RegistryValue) -> Result<Self, Error> {
+        match value {
+            RegistryValue::String(value) | RegistryValue::ExpandString(value) => Ok(value),
+            _ => Err(Error::OBJECT_TYPE_MISMATCH),
+        }
+    }
+}
+
+impl FromRegistryValue for Vec<String> {
+    fn from_registry_value(value: RegistryValue) -> Result<Self, Error> {
+        match value {
+            RegistryValue::MultiString(value) => Ok(value),
+            _ => Err(Error::OBJECT_TYPE_MISMATCH),
+        }
+    }
+}
+
+impl FromRegistryValue for Vec<u8> {
+    fn from_registry_value(value: RegistryValue) -> Result<Self, Error> {
+        match value {
+            RegistryValue::Binary(value) => Ok(value),
+            _ => Err(Error::OBJECT_TYPE_MISMATCH),
+        }
+    }
+}
+
+/// Reads the value with the given name from the key, falling back to the default if either the
+/// key or the value does not exist.
+pub fn read_value<T, F>(key: Option<&RegistryKey>, name: &str, default: F) -> Result<T, Error>
+where
+    T: FromRegistryValue,
+    F: FnOnce() -> T,
+{
+    let key = match key {
+        Some(key) => key,
+        None => return Ok(default()),
+    };
+
+    match key.query_value(name) {
+        Ok(value) => T::from_registry_value(value),
+        Err(e) if e == Error::OBJECT_NAME_NOT_FOUND => Ok(default()),
+        Err(e) => Err(e),
+    }
+}
+
+/// A driver configuration that is loaded from the registry, usually implemented through
+/// [`registry_config!`].
+pub trait DriverConfig: Sized {
+    /// The name of the subkey of the service key that holds the configuration.
+    const SUBKEY: &'static str = "Parameters";
+
+    /// Reads every field from the given key, or uses the defaults if there is no key.
+    fn from_key(key: Option<&RegistryKey>) -> Result<Self, Error>;
+
+    /// Validates the configuration once all the fields have been read.
+    fn validate(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Loads and validates the configuration from `<registry_path>\Parameters`, where the
+    /// registry path is the one passed to `DriverEntry`. A missing subkey results in the
+    /// defaults.
+    fn load(registry_path: &str) -> Result<Self, Error> {
+        let path = format!("{}\\{}", registry_path, Self::SUBKEY);
+
+        let key = match RegistryKey::open(&path, RegistryAccess::READ) {
+            Ok(key) => Some(key),
+            Err(e) if e == Error::OBJECT_NAME_NOT_FOUND => None,
+            Err(e) => return Err(e),
+        };
+
+        let config = Self::from_key(key.as_ref())?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Watches `<registry_path>\Parameters` and reloads the configuration with
+    /// [`DriverConfig::load`] whenever a value of the key is set or deleted, passing the result
+    /// to `on_reload` on the thread of the returned [`RegistryWatcher`]. Reloading stops when the
+    /// watcher is dropped. Unlike loading, watching requires the subkey to exist.
+    fn watch<F>(registry_path: &str, on_reload: F) -> Result<RegistryWatcher, Error>
+    where
+        Self: 'static,
+        F: Fn(Result<Self, Error>) + Send + Sync + 'static,
+    {
+        let path = format!("{}\\{}", registry_path, Self::SUBKEY);
+        let key = RegistryKey::open(&path, RegistryAccess::READ | RegistryAccess::NOTIFY)?;
+
+        let registry_path = String::from(registry_path);
+
+        RegistryWatcher::new(
+            key,
+            NotifyFilter::NAME | NotifyFilter::LAST_SET,
+            false,
+            move || on_reload(Self::load(&registry_path)),
+        )
+    }
+}
+
+/// Declares a driver configuration struct and implements [`DriverConfig`] and [`Default`] for
+/// it. Each field is written as `name: Type as "ValueName" = default`, where the type implements
+/// [`FromRegistryValue`]. An optional `validate = expr;` takes a `fn(&Self) -> Result<(), Error>`
+/// that is run after loading.
+#[macro_export]
+macro_rules! registry_config {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $(
+                $(#[$field_meta:meta])*
+                $field_vis:vis $field:ident : $ty:ty as $value:literal = $default:expr
+            ),* $(,)?
+        }
+
+        $(validate = $validate:expr;)?
+    ) => {
+        $(#[$meta])*
+        $vis struct $name {
+            $(
+                $(#[$field_meta])*
+                $field_vis $field: $ty,
+            )*
+        }
+
+        impl ::core::default::Default for $name {
+            fn default() -> Self {
+                Self {
+                    $($field: $default,)*
+                }
+            }
+        }
+
+        impl $crate::config::DriverConfig for $name {
+            fn from_key(
+                key: ::core::option::Option<&$crate::registry::RegistryKey>,
+            ) -> ::core::result::Result<Self, $crate::Error> {
+                Ok(Self {
+                    $($field: $crate::config::read_value::<$ty, _>(key, $value, || $default)?,)*
+                })
+            }
+
+            $(
+                fn validate(&self) -> ::core::result::Result<(), $crate::Error> {
+                    let validate: fn(&Self) -> ::core::result::Result<(), $crate::Error> =
+                        $validate;
+
+                    validate(self)
+                }
+            )?
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use alloc::vec;
+
+    crate::registry_config! {
+        /// A configuration with a field of every supported type.
+        struct TestConfig {
+            max_requests: u32 as "MaxRequests" = 64,
+            timeout: u64 as "Timeout" = 30,
+            verbose: bool as "Verbose" = false,
+            name: String as "Name" = String::from("default"),
+            hosts: Vec<String> as "Hosts" = Vec::new(),
+            key: Vec<u8> as "Key" = vec![1, 2, 3],
+        }
+
+        validate = |config: &TestConfig| {
+            if config.max_requests == 0 {
+                return Err(Error::INVALID_PARAMETER);
+            }
+
+            Ok(())
+        };
+    }
+
+    #[test]
+    fn missing_key_uses_the_default() {
+        assert_eq!(read_value::<u32, _>(None, "MaxRequests", || 7), Ok(7));
+        assert_eq!(
+            read_value::<String, _>(None, "Name", || String::from("x")),
+            Ok(String::from("x"))
+        );
+    }
+
+    #[test]
+    fn converts_matching_values() {
+        assert_eq!(u32::from_registry_value(RegistryValue::Dword(5)), Ok(5));
+        assert_eq!(u64::from_registry_value(RegistryValue::Qword(1 << 40)), Ok(1 << 40));
+        assert_eq!(u64::from_registry_value(RegistryValue::Dword(5)), Ok(5));
+        assert_eq!(bool::from_registry_value(RegistryValue::Dword(2)), Ok(true));
+        assert_eq!(bool::from_registry_value(RegistryValue::Dword(0)), Ok(false));
+        assert_eq!(
+            String::from_registry_value(RegistryValue::ExpandString(String::from("%SystemRoot%"))),
+            Ok(String::from("%SystemRoot%"))
+        );
+        assert_eq!(
+            Vec::<String>::from_registry_value(RegistryValue::MultiString(vec![String::from("a")])),
+            Ok(vec![String::from("a")])
+        );
+        assert_eq!(
+            Vec::<u8>::from_registry_value(RegistryValue::Binary(vec![0xAA])),
+            Ok(vec![0xAA])
+        );
+        assert_eq!(
+            RegistryValue::from_registry_value(RegistryValue::Dword(1)),
+            Ok(RegistryValue::Dword(1))
+        );
+    }
+
+    #[test]
+    fn rejects_mismatched_values() {
+        assert_eq!(
+            u32::from_registry_value(RegistryValue::Qword(1)),
+            Err(Error::OBJECT_TYPE_MISMATCH)
+        );
+        assert_eq!(
+            bool::from_registry_value(RegistryValue::String(String::from("true"))),
+            Err(Error::OBJECT_TYPE_MISMATCH)
+        );
+        assert_eq!(
+            String::from_registry_value(RegistryValue::MultiString(Vec::new())),
+            Err(Error::OBJECT_TYPE_MISMATCH)
+        );
+        assert_eq!(
+            Vec::<String>::from_registry_value(RegistryValue::String(String::new())),
+            Err(Error::OBJECT_TYPE_MISMATCH)
+        );
+        assert_eq!(
+            Vec::<u8>::from_registry_value(RegistryValue::Dword(1)),
+            Err(Error::OBJECT_TYPE_MISMATCH)
+        );
+    }
+
+    #[test]
+    fn defaults_every_field() {
+        let config = TestConfig::default();
+
+        assert_eq!(config.max_requests, 64);
+        assert_eq!(config.timeout, 30);
+        assert!(!config.verbose);
+        assert_eq!(config.name, "default");
+        assert!(config.hosts.is_empty());
+        assert_eq!(config.key, [1, 2, 3]);
+    }
+
+    #[test]
+    fn loads_the_defaults_without_a_key() {
+        let config = TestConfig::from_key(None).unwrap();
+
+        assert_eq!(config.max_requests, 64);
+        assert_eq!(config.name, "default");
+        assert_eq!(config.validate(), Ok(()));
+    }
+
+    #[test]
+    fn runs_the_validation() {
+        let config = TestConfig {
+            max_requests: 0,
+            ..TestConfig::default()
+        };
+
+        assert_eq!(config.validate(), Err(Error::INVALID_PARAMETER));
+    }
+}
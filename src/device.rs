@@ -401,7 +401,15 @@ extern "C" fn dispatch_callback<T: DeviceOperations>(
     let result = match major as _ {
         IRP_MJ_CREATE => data.create(&device, request),
         IRP_MJ_CLOSE => data.close(&device, request),
-        IRP_MJ_CLEANUP => data.cleanup(&device, request),
+        IRP_MJ_CLEANUP => {
+            let file_object = request.stack_location().FileObject;
+            let result = data.cleanup(&device, request);
+
+            // Buffers locked through the handle must not outlive it.
+            crate::user_buffer::release_file(file_object);
+
+            result
+        }
         IRP_MJ_READ => {
             let read_request = ReadRequest { inner: request };
 
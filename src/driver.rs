@@ -1,8 +1,6 @@
 //! DRIVER_OBJECT  wrapper a driver object
 use alloc::boxed::Box;
 
-use widestring::U16CString;
-
 use win_kernel_sys::base::DRIVER_OBJECT;
 use win_kernel_sys::ntoskrnl::IoCreateDevice;
 
@@ -11,7 +9,7 @@ use crate::device::{
     DeviceOperationsVtable, DeviceType,
 };
 use crate::error::{Error, IntoResult};
-use crate::string::create_unicode_string;
+use crate::string::OwnedUnicodeString;
 
 
 /// is DRIVER_OBJECT
@@ -52,8 +50,8 @@ impl Driver {
         let data = Box::new(data);
 
         // Convert the name to UTF-16 and then create a UNICODE_STRING.
-        let name = U16CString::from_str(name).unwrap();
-        let mut name = create_unicode_string(name.as_slice());
+        let name = OwnedUnicodeString::try_from(name)?;
+        let mut name = name.as_unicode_str();
 
         // Create the device.
         let mut device = core::ptr::null_mut();
@@ -62,7 +60,7 @@ impl Driver {
             IoCreateDevice(
                 self.raw,
                 core::mem::size_of::<DeviceExtension>() as u32,
-                &mut name,
+                name.as_mut_ptr(),
                 device_type.into(),
                 device_flags.bits(),
                 access.is_exclusive() as _,
@@ -2,20 +2,39 @@
 use win_kernel_sys::base::NTSTATUS;
 use win_kernel_sys::base::{
     STATUS_ACCESS_VIOLATION, STATUS_ARRAY_BOUNDS_EXCEEDED, STATUS_BREAKPOINT,
-    STATUS_DATATYPE_MISALIGNMENT, STATUS_END_OF_FILE, STATUS_FLOAT_DENORMAL_OPERAND,
-    STATUS_FLOAT_DIVIDE_BY_ZERO, STATUS_FLOAT_INEXACT_RESULT, STATUS_FLOAT_INVALID_OPERATION,
-    STATUS_FLOAT_OVERFLOW, STATUS_FLOAT_STACK_CHECK, STATUS_FLOAT_UNDERFLOW,
-    STATUS_GUARD_PAGE_VIOLATION, STATUS_ILLEGAL_INSTRUCTION, STATUS_INSUFFICIENT_RESOURCES,
-    STATUS_INTEGER_DIVIDE_BY_ZERO, STATUS_INTEGER_OVERFLOW, STATUS_INVALID_DISPOSITION,
-    STATUS_INVALID_HANDLE, STATUS_INVALID_PARAMETER, STATUS_INVALID_USER_BUFFER,
-    STATUS_IN_PAGE_ERROR, STATUS_NONCONTINUABLE_EXCEPTION, STATUS_NOT_IMPLEMENTED,
-    STATUS_NO_MEMORY, STATUS_PRIVILEGED_INSTRUCTION, STATUS_SINGLE_STEP, STATUS_STACK_OVERFLOW,
-    STATUS_SUCCESS, STATUS_UNSUCCESSFUL, STATUS_UNWIND_CONSOLIDATE,
+    STATUS_BUFFER_OVERFLOW, STATUS_DATATYPE_MISALIGNMENT, STATUS_END_OF_FILE, STATUS_FILE_CLOSED,
+    STATUS_FLOAT_DENORMAL_OPERAND, STATUS_FLOAT_DIVIDE_BY_ZERO, STATUS_FLOAT_INEXACT_RESULT,
+    STATUS_FLOAT_INVALID_OPERATION, STATUS_FLOAT_OVERFLOW, STATUS_FLOAT_STACK_CHECK,
+    STATUS_FLOAT_UNDERFLOW, STATUS_GUARD_PAGE_VIOLATION, STATUS_ILLEGAL_INSTRUCTION,
+    STATUS_INSUFFICIENT_RESOURCES, STATUS_INTEGER_DIVIDE_BY_ZERO, STATUS_INTEGER_OVERFLOW,
+    STATUS_INVALID_DEVICE_STATE, STATUS_INVALID_DISPOSITION, STATUS_INVALID_HANDLE,
+    STATUS_INVALID_PARAMETER, STATUS_INVALID_USER_BUFFER, STATUS_IN_PAGE_ERROR,
+    STATUS_NAME_TOO_LONG, STATUS_NONCONTINUABLE_EXCEPTION, STATUS_NOT_IMPLEMENTED, STATUS_NO_MEMORY,
+    STATUS_OBJECT_NAME_INVALID, STATUS_OBJECT_NAME_NOT_FOUND, STATUS_OBJECT_PATH_SYNTAX_BAD,
+    STATUS_OBJECT_TYPE_MISMATCH, STATUS_PRIVILEGED_INSTRUCTION, STATUS_SINGLE_STEP,
+    STATUS_STACK_OVERFLOW, STATUS_SUCCESS, STATUS_UNSUCCESSFUL, STATUS_UNWIND_CONSOLIDATE,
 };
 
+mod context;
+mod table;
+
+pub use context::{ContextError, Frame, ResultExt, MAX_CONTEXT_FRAMES};
+
+/// The severity of an [NTSTATUS], stored in its two most significant bits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The operation completed successfully.
+    Success,
+    /// The operation completed successfully, with additional information.
+    Informational,
+    /// The operation completed, but not necessarily as expected.
+    Warning,
+    /// The operation failed.
+    Error,
+}
 
 /// Error codes from the Windows NTSTATUS system.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct Error(NTSTATUS);
 
 impl Error {
@@ -79,6 +98,22 @@ impl Error {
     pub const INVALID_USER_BUFFER: Error = Error(STATUS_INVALID_USER_BUFFER);
     /// STACK_OVERFLOW
     pub const STACK_OVERFLOW: Error = Error(STATUS_STACK_OVERFLOW);
+    /// OBJECT_NAME_NOT_FOUND
+    pub const OBJECT_NAME_NOT_FOUND: Error = Error(STATUS_OBJECT_NAME_NOT_FOUND);
+    /// OBJECT_TYPE_MISMATCH
+    pub const OBJECT_TYPE_MISMATCH: Error = Error(STATUS_OBJECT_TYPE_MISMATCH);
+    /// NAME_TOO_LONG
+    pub const NAME_TOO_LONG: Error = Error(STATUS_NAME_TOO_LONG);
+    /// OBJECT_PATH_SYNTAX_BAD
+    pub const OBJECT_PATH_SYNTAX_BAD: Error = Error(STATUS_OBJECT_PATH_SYNTAX_BAD);
+    /// OBJECT_NAME_INVALID
+    pub const OBJECT_NAME_INVALID: Error = Error(STATUS_OBJECT_NAME_INVALID);
+    /// BUFFER_OVERFLOW
+    pub const BUFFER_OVERFLOW: Error = Error(STATUS_BUFFER_OVERFLOW);
+    /// FILE_CLOSED
+    pub const FILE_CLOSED: Error = Error(STATUS_FILE_CLOSED);
+    /// INVALID_DEVICE_STATE
+    pub const INVALID_DEVICE_STATE: Error = Error(STATUS_INVALID_DEVICE_STATE);
 
     /// self [Error] from [NTSTATUS]
     pub fn from_ntstatus(status: NTSTATUS) -> Error {
@@ -89,14 +124,88 @@ impl Error {
     pub fn to_ntstatus(&self) -> NTSTATUS {
         self.0
     }
+
+    /// Returns the severity of the status.
+    pub const fn severity(&self) -> Severity {
+        match (self.0 as u32) >> 30 {
+            0 => Severity::Success,
+            1 => Severity::Informational,
+            2 => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+
+    /// Returns whether the customer bit is set, i.e. the status is defined by a third party
+    /// rather than by Microsoft.
+    pub const fn is_customer(&self) -> bool {
+        (self.0 as u32) & 0x2000_0000 != 0
+    }
+
+    /// Returns the facility of the status, e.g. `0x1C` for the filter manager.
+    pub const fn facility(&self) -> u16 {
+        (((self.0 as u32) >> 16) & 0xFFF) as u16
+    }
+
+    /// Returns the facility-specific code of the status.
+    pub const fn code(&self) -> u16 {
+        self.0 as u32 as u16
+    }
+
+    /// Returns whether the status indicates success, which like `NT_SUCCESS` includes the
+    /// informational codes.
+    pub const fn is_success(&self) -> bool {
+        self.0 >= 0
+    }
+
+    /// Returns whether the status is informational, e.g. `STATUS_OBJECT_NAME_EXISTS`.
+    pub const fn is_informational(&self) -> bool {
+        matches!(self.severity(), Severity::Informational)
+    }
+
+    /// Returns whether the status is a warning, e.g. `STATUS_BUFFER_OVERFLOW`.
+    pub const fn is_warning(&self) -> bool {
+        matches!(self.severity(), Severity::Warning)
+    }
+
+    /// Returns whether the status is an error, e.g. `STATUS_INVALID_PARAMETER`.
+    pub const fn is_error(&self) -> bool {
+        matches!(self.severity(), Severity::Error)
+    }
+
+    /// Returns the name of the status, e.g. `STATUS_INVALID_PARAMETER`, or `None` if the status
+    /// is not a documented one.
+    pub fn name(&self) -> Option<&'static str> {
+        let status = self.0 as u32;
+
+        table::STATUS_NAMES
+            .binary_search_by_key(&status, |(value, _)| *value)
+            .ok()
+            .map(|i| table::STATUS_NAMES[i].1)
+    }
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.name() {
+            Some(name) => write!(f, "{} (0x{:08X})", name, self.0 as u32),
+            None => write!(f, "0x{:08X}", self.0 as u32),
+        }
+    }
 }
 
+impl core::error::Error for Error {}
+
 
 ///# trait
 /// self error into [Result<(), Error>] error is self err
 pub trait IntoResult {
     /// [IntoResult] defualt
     fn into_result(self) -> Result<(), Error>;
+
+    /// Like [IntoResult::into_result], but only statuses with [Severity::Error] are failures, such
+    /// that e.g. `STATUS_PENDING` and `STATUS_BUFFER_OVERFLOW` are not. Used by callers that
+    /// handle partial results themselves.
+    fn into_result_allow_warnings(self) -> Result<(), Error>;
 }
 
 impl IntoResult for NTSTATUS {
@@ -107,4 +216,108 @@ impl IntoResult for NTSTATUS {
             status => Err(Error::from_ntstatus(status)),
         }
     }
+
+    fn into_result_allow_warnings(self) -> Result<(), Error> {
+        match Error::from_ntstatus(self) {
+            error if error.is_error() => Err(error),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(value: u32) -> Error {
+        Error::from_ntstatus(value as NTSTATUS)
+    }
+
+    #[test]
+    fn decodes_severity() {
+        assert_eq!(status(0x0000_0103).severity(), Severity::Success);
+        assert_eq!(status(0x4000_0000).severity(), Severity::Informational);
+        assert_eq!(status(0x8000_0005).severity(), Severity::Warning);
+        assert_eq!(status(0xC000_000D).severity(), Severity::Error);
+
+        assert!(status(0x4000_0000).is_success());
+        assert!(status(0x4000_0000).is_informational());
+        assert!(!status(0x8000_0005).is_success());
+        assert!(status(0x8000_0005).is_warning());
+        assert!(status(0xC000_000D).is_error());
+    }
+
+    #[test]
+    fn decodes_facility_and_code() {
+        let error = status(0xC01C_0004);
+
+        assert_eq!(error.facility(), 0x1C);
+        assert_eq!(error.code(), 0x0004);
+        assert!(!error.is_customer());
+
+        let error = status(0xE123_4567);
+
+        assert!(error.is_customer());
+        assert_eq!(error.facility(), 0x123);
+        assert_eq!(error.code(), 0x4567);
+        assert_eq!(error.severity(), Severity::Error);
+    }
+
+    #[test]
+    fn looks_up_names() {
+        assert_eq!(status(0x0000_0000).name(), Some("STATUS_SUCCESS"));
+        assert_eq!(status(0x0000_0103).name(), Some("STATUS_PENDING"));
+        assert_eq!(status(0x8000_001A).name(), Some("STATUS_NO_MORE_ENTRIES"));
+        assert_eq!(status(0xC000_000D).name(), Some("STATUS_INVALID_PARAMETER"));
+        assert_eq!(status(0xC000_A2A2).name(), Some("STATUS_HASH_NOT_PRESENT"));
+        assert_eq!(status(0xE123_4567).name(), None);
+    }
+
+    #[test]
+    fn table_is_sorted() {
+        assert!(table::STATUS_NAMES.windows(2).all(|w| w[0].0 < w[1].0));
+        assert!(table::STATUS_NAMES
+            .iter()
+            .all(|(_, name)| name.starts_with("STATUS_")));
+    }
+
+    #[test]
+    fn formats_name_and_value() {
+        assert_eq!(
+            alloc::format!("{}", Error::INVALID_PARAMETER),
+            "STATUS_INVALID_PARAMETER (0xC000000D)"
+        );
+        assert_eq!(alloc::format!("{}", status(0xE123_4567)), "0xE1234567");
+    }
+
+    #[test]
+    fn only_success_is_ok() {
+        assert_eq!((0x0000_0000 as NTSTATUS).into_result(), Ok(()));
+        assert_eq!(
+            (0x0000_0103 as NTSTATUS).into_result(),
+            Err(status(0x0000_0103))
+        );
+        assert_eq!(
+            (0x8000_001A_u32 as NTSTATUS).into_result(),
+            Err(status(0x8000_001A))
+        );
+        assert_eq!(
+            (0xC000_000D_u32 as NTSTATUS).into_result(),
+            Err(Error::INVALID_PARAMETER)
+        );
+    }
+
+    #[test]
+    fn allows_warnings_on_request() {
+        assert_eq!((0x0000_0103 as NTSTATUS).into_result_allow_warnings(), Ok(()));
+        assert_eq!((0x4000_0000 as NTSTATUS).into_result_allow_warnings(), Ok(()));
+        assert_eq!(
+            (0x8000_0005_u32 as NTSTATUS).into_result_allow_warnings(),
+            Ok(())
+        );
+        assert_eq!(
+            (0xC000_000D_u32 as NTSTATUS).into_result_allow_warnings(),
+            Err(Error::INVALID_PARAMETER)
+        );
+    }
 }
@@ -0,0 +1,323 @@
+//! Error context chains.
+//!
+//! A [`ContextError`] wraps an [`Error`] together with the chain of static messages that were
+//! attached to it on its way up, and the source location of each. The chain is stored inline, so
+//! that neither raising nor propagating an error allocates.
+//!
+//! ```ignore
+//! use win_kernel::error::{ContextError, ResultExt};
+//!
+//! fn map(section: &Section) -> Result<View, ContextError> {
+//!     let view = section.map_view(..).context("failed to map the shared section")?;
+//!     Ok(view)
+//! }
+//!
+//! fn dispatch() -> NTSTATUS {
+//!     match map(&section) {
+//!         Ok(_) => STATUS_SUCCESS,
+//!         Err(e) => {
+//!             e.log();
+//!             e.into()
+//!         }
+//!     }
+//! }
+//! ```
+
+use core::panic::Location;
+
+use win_kernel_sys::base::NTSTATUS;
+
+use super::Error;
+
+/// The maximum number of frames a [`ContextError`] keeps. Once the chain is full, the outermost
+/// frames are dropped, as the innermost ones are closer to the cause.
+pub const MAX_CONTEXT_FRAMES: usize = 8;
+
+/// A message attached to an error, and the location it was attached at.
+#[derive(Clone, Copy, Debug)]
+pub struct Frame {
+    /// The message, e.g. `failed to open the device`.
+    pub message: &'static str,
+    /// The location of the caller that attached the message.
+    pub location: &'static Location<'static>,
+}
+
+impl core::fmt::Display for Frame {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} at {}:{}:{}",
+            self.message,
+            self.location.file(),
+            self.location.line(),
+            self.location.column()
+        )
+    }
+}
+
+/// An [`Error`] with a chain of context messages. Each frame is the cause of the frame that was
+/// attached after it, and the innermost cause is the [`Error`] itself.
+#[derive(Clone, Copy, Debug)]
+pub struct ContextError {
+    error: Error,
+    location: &'static Location<'static>,
+    frames: [Option<Frame>; MAX_CONTEXT_FRAMES],
+    len: usize,
+    dropped: usize,
+}
+
+impl ContextError {
+    /// Wraps the error, recording the location of the caller as the place it was raised.
+    #[track_caller]
+    pub fn new(error: Error) -> Self {
+        Self {
+            error,
+            location: Location::caller(),
+            frames: [None; MAX_CONTEXT_FRAMES],
+            len: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Wraps the error with a first context message.
+    #[track_caller]
+    pub fn with_context(error: Error, message: &'static str) -> Self {
+        Self::new(error).context(message)
+    }
+
+    /// Attaches a context message at the location of the caller, which wraps the chain so far.
+    #[track_caller]
+    pub fn context(mut self, message: &'static str) -> Self {
+        let frame = Frame {
+            message,
+            location: Location::caller(),
+        };
+
+        if self.len < MAX_CONTEXT_FRAMES {
+            self.frames[self.len] = Some(frame);
+            self.len += 1;
+        } else {
+            self.dropped += 1;
+        }
+
+        self
+    }
+
+    /// Returns the underlying error.
+    pub fn error(&self) -> Error {
+        self.error
+    }
+
+    /// Returns the underlying [NTSTATUS].
+    pub fn to_ntstatus(&self) -> NTSTATUS {
+        self.error.to_ntstatus()
+    }
+
+    /// Returns the location where the error was raised.
+    pub fn location(&self) -> &'static Location<'static> {
+        self.location
+    }
+
+    /// Returns the outermost context message, if any.
+    pub fn message(&self) -> Option<&'static str> {
+        self.frames().next().map(|frame| frame.message)
+    }
+
+    /// Returns an iterator over the frames, from the outermost to the innermost.
+    pub fn frames(&self) -> impl Iterator<Item = &Frame> {
+        self.frames[..self.len].iter().rev().flatten()
+    }
+
+    /// Returns the number of frames that were dropped because the chain was full.
+    pub fn dropped_frames(&self) -> usize {
+        self.dropped
+    }
+
+    /// Prints the whole chain through [`println!`](crate::println), one line per frame, such
+    /// that no line is truncated by the size of the print buffer.
+    pub fn log(&self) {
+        let mut frames = self.frames();
+
+        match frames.next() {
+            Some(frame) => crate::println!("error: {}", frame),
+            None => crate::println!("error: {}", self.error),
+        }
+
+        for frame in frames {
+            crate::println!("  caused by: {}", frame);
+        }
+
+        if self.dropped != 0 {
+            crate::println!("  ({} more frames)", self.dropped);
+        }
+
+        if self.len != 0 {
+            crate::println!("  caused by: {}", self.error);
+        }
+
+        crate::println!(
+            "  raised at {}:{}:{}",
+            self.location.file(),
+            self.location.line(),
+            self.location.column()
+        );
+    }
+}
+
+impl core::fmt::Display for ContextError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for frame in self.frames() {
+            write!(f, "{}: ", frame.message)?;
+        }
+
+        write!(f, "{}", self.error)
+    }
+}
+
+impl core::error::Error for ContextError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl From<Error> for ContextError {
+    #[track_caller]
+    fn from(error: Error) -> Self {
+        Self::new(error)
+    }
+}
+
+impl From<ContextError> for Error {
+    fn from(error: ContextError) -> Self {
+        error.error
+    }
+}
+
+impl From<ContextError> for NTSTATUS {
+    fn from(error: ContextError) -> Self {
+        error.to_ntstatus()
+    }
+}
+
+/// Attaches context messages to results.
+pub trait ResultExt<T> {
+    /// Attaches the message to the error, if any, at the location of the caller.
+    fn context(self, message: &'static str) -> Result<T, ContextError>;
+}
+
+impl<T> ResultExt<T> for Result<T, Error> {
+    #[track_caller]
+    fn context(self, message: &'static str) -> Result<T, ContextError> {
+        match self {
+            Ok(value) => Ok(value),
+            Err(error) => Err(ContextError::with_context(error, message)),
+        }
+    }
+}
+
+impl<T> ResultExt<T> for Result<T, ContextError> {
+    #[track_caller]
+    fn context(self, message: &'static str) -> Result<T, ContextError> {
+        match self {
+            Ok(value) => Ok(value),
+            Err(error) => Err(error.context(message)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use alloc::format;
+    use alloc::vec::Vec;
+
+    const MESSAGES: [&str; 10] = ["0", "1", "2", "3", "4", "5", "6", "7", "8", "9"];
+
+    fn messages(error: &ContextError) -> Vec<&'static str> {
+        error.frames().map(|frame| frame.message).collect()
+    }
+
+    #[test]
+    fn records_the_callers() {
+        let line = line!();
+        let error = ContextError::new(Error::INVALID_PARAMETER).context("outer");
+
+        assert_eq!(error.location().file(), file!());
+        assert_eq!(error.location().line(), line + 1);
+
+        let frame = error.frames().next().unwrap();
+        assert_eq!(frame.location.line(), line + 1);
+        assert!(frame.location.column() > error.location().column());
+    }
+
+    #[test]
+    fn frames_run_from_outermost_to_innermost() {
+        let error = ContextError::with_context(Error::INVALID_PARAMETER, "inner")
+            .context("middle")
+            .context("outer");
+
+        assert_eq!(messages(&error), ["outer", "middle", "inner"]);
+        assert_eq!(error.message(), Some("outer"));
+        assert_eq!(error.dropped_frames(), 0);
+
+        let error = ContextError::new(Error::INVALID_PARAMETER);
+
+        assert_eq!(error.frames().count(), 0);
+        assert_eq!(error.message(), None);
+    }
+
+    #[test]
+    fn drops_the_outermost_frames_once_full() {
+        let mut error = ContextError::new(Error::INVALID_PARAMETER);
+
+        for message in MESSAGES {
+            error = error.context(message);
+        }
+
+        assert_eq!(error.frames().count(), MAX_CONTEXT_FRAMES);
+        assert_eq!(error.dropped_frames(), MESSAGES.len() - MAX_CONTEXT_FRAMES);
+        assert_eq!(messages(&error), ["7", "6", "5", "4", "3", "2", "1", "0"]);
+    }
+
+    #[test]
+    fn displays_the_chain() {
+        let error = ContextError::with_context(Error::INVALID_PARAMETER, "failed to parse")
+            .context("failed to load the configuration");
+
+        assert_eq!(
+            format!("{}", error),
+            "failed to load the configuration: failed to parse: \
+             STATUS_INVALID_PARAMETER (0xC000000D)"
+        );
+        assert_eq!(
+            format!("{}", ContextError::new(Error::INVALID_PARAMETER)),
+            "STATUS_INVALID_PARAMETER (0xC000000D)"
+        );
+    }
+
+    #[test]
+    fn converts_back_to_the_status() {
+        let error = ContextError::with_context(Error::INVALID_PARAMETER, "context");
+
+        assert_eq!(error.error(), Error::INVALID_PARAMETER);
+        assert_eq!(error.to_ntstatus(), Error::INVALID_PARAMETER.to_ntstatus());
+        assert_eq!(Error::from(error), Error::INVALID_PARAMETER);
+        assert_eq!(
+            NTSTATUS::from(error),
+            Error::INVALID_PARAMETER.to_ntstatus()
+        );
+    }
+
+    #[test]
+    fn attaches_context_to_results() {
+        let ok: Result<u32, Error> = Ok(1);
+        assert_eq!(ok.context("unused").unwrap(), 1);
+
+        let result: Result<(), Error> = Err(Error::INVALID_PARAMETER);
+        let error = result.context("inner").context("outer").unwrap_err();
+
+        assert_eq!(messages(&error), ["outer", "inner"]);
+        assert_eq!(error.error(), Error::INVALID_PARAMETER);
+    }
+}
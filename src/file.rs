@@ -0,0 +1,712 @@
+//! file mod
+//!
+//! Directory enumeration and file metadata on top of the `Zw*File` family of functions. Decoding
+//! of the buffers returned by [`ZwQueryDirectoryFile`] is kept separate from the system calls, so
+//! that it only depends on a byte slice.
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use bitflags::bitflags;
+
+use win_kernel_sys::base::_FILE_INFORMATION_CLASS::{
+    FileBasicInformation, FileDirectoryInformation, FileDispositionInformation,
+    FileRenameInformation, FileStandardInformation,
+};
+use win_kernel_sys::base::{
+    FILE_BASIC_INFORMATION, FILE_DISPOSITION_INFORMATION, FILE_INFORMATION_CLASS,
+    FILE_RENAME_INFORMATION, FILE_STANDARD_INFORMATION, HANDLE, IO_STATUS_BLOCK, OBJECT_ATTRIBUTES,
+    STATUS_NO_MORE_FILES,
+};
+use win_kernel_sys::ntoskrnl::{
+    ZwClose, ZwCreateFile, ZwQueryDirectoryFile, ZwQueryInformationFile, ZwSetInformationFile,
+};
+
+use crate::error::{Error, IntoResult};
+use crate::section::ObjectFlags;
+use crate::string::OwnedUnicodeString;
+
+bitflags! {
+    /// FileAttributes
+    pub struct FileAttributes: u32 {
+        const READONLY            = win_kernel_sys::base::FILE_ATTRIBUTE_READONLY;
+        const HIDDEN              = win_kernel_sys::base::FILE_ATTRIBUTE_HIDDEN;
+        const SYSTEM              = win_kernel_sys::base::FILE_ATTRIBUTE_SYSTEM;
+        const DIRECTORY           = win_kernel_sys::base::FILE_ATTRIBUTE_DIRECTORY;
+        const ARCHIVE             = win_kernel_sys::base::FILE_ATTRIBUTE_ARCHIVE;
+        const DEVICE              = win_kernel_sys::base::FILE_ATTRIBUTE_DEVICE;
+        const NORMAL              = win_kernel_sys::base::FILE_ATTRIBUTE_NORMAL;
+        const TEMPORARY           = win_kernel_sys::base::FILE_ATTRIBUTE_TEMPORARY;
+        const SPARSE_FILE         = win_kernel_sys::base::FILE_ATTRIBUTE_SPARSE_FILE;
+        const REPARSE_POINT       = win_kernel_sys::base::FILE_ATTRIBUTE_REPARSE_POINT;
+        const COMPRESSED          = win_kernel_sys::base::FILE_ATTRIBUTE_COMPRESSED;
+        const OFFLINE             = win_kernel_sys::base::FILE_ATTRIBUTE_OFFLINE;
+        const NOT_CONTENT_INDEXED = win_kernel_sys::base::FILE_ATTRIBUTE_NOT_CONTENT_INDEXED;
+        const ENCRYPTED           = win_kernel_sys::base::FILE_ATTRIBUTE_ENCRYPTED;
+    }
+}
+
+/// A timestamp as stored by the file system: the number of 100-nanosecond intervals since
+/// January 1, 1601 (UTC).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FileTime(i64);
+
+impl FileTime {
+    /// The difference between the Windows epoch (1601) and the Unix epoch (1970) in 100-nanosecond
+    /// intervals.
+    const UNIX_EPOCH: i64 = 116_444_736_000_000_000;
+
+    /// Creates a [`FileTime`] from the raw 100-nanosecond interval count.
+    pub const fn from_raw(value: i64) -> Self {
+        Self(value)
+    }
+
+    /// Returns the raw 100-nanosecond interval count.
+    pub const fn as_raw(&self) -> i64 {
+        self.0
+    }
+
+    /// Returns the number of seconds since the Unix epoch, or `None` if the timestamp lies before
+    /// it.
+    pub fn unix_seconds(&self) -> Option<u64> {
+        self.0
+            .checked_sub(Self::UNIX_EPOCH)
+            .filter(|value| *value >= 0)
+            .map(|value| value as u64 / 10_000_000)
+    }
+}
+
+/// An owned directory entry, decoded from a `FILE_DIRECTORY_INFORMATION` record.
+#[derive(Clone, Debug)]
+pub struct DirEntry {
+    /// The name of the entry, relative to the directory.
+    pub name: String,
+    /// The file index, only meaningful on file systems that support it.
+    pub file_index: u32,
+    /// The creation time.
+    pub creation_time: FileTime,
+    /// The last access time.
+    pub last_access_time: FileTime,
+    /// The last write time.
+    pub last_write_time: FileTime,
+    /// The last change time.
+    pub change_time: FileTime,
+    /// The size of the file in bytes.
+    pub end_of_file: u64,
+    /// The number of bytes allocated for the file.
+    pub allocation_size: u64,
+    /// The file attributes.
+    pub attributes: FileAttributes,
+}
+
+impl DirEntry {
+    /// Returns whether the entry is a directory.
+    pub fn is_dir(&self) -> bool {
+        self.attributes.contains(FileAttributes::DIRECTORY)
+    }
+
+    /// Returns whether the entry is `.` or `..`.
+    pub fn is_dot(&self) -> bool {
+        self.name == "." || self.name == ".."
+    }
+}
+
+/// Iterates the `FILE_DIRECTORY_INFORMATION` records packed in a buffer returned by
+/// [`ZwQueryDirectoryFile`], following the `NextEntryOffset` chain. Malformed records end the
+/// iteration with [`Error::INVALID_PARAMETER`].
+pub struct DirEntryDecoder<'a> {
+    buffer: &'a [u8],
+    offset: Option<usize>,
+}
+
+impl<'a> DirEntryDecoder<'a> {
+    const NEXT_ENTRY_OFFSET: usize = 0;
+    const FILE_INDEX: usize = 4;
+    const CREATION_TIME: usize = 8;
+    const LAST_ACCESS_TIME: usize = 16;
+    const LAST_WRITE_TIME: usize = 24;
+    const CHANGE_TIME: usize = 32;
+    const END_OF_FILE: usize = 40;
+    const ALLOCATION_SIZE: usize = 48;
+    const FILE_ATTRIBUTES: usize = 56;
+    const FILE_NAME_LENGTH: usize = 60;
+    const FILE_NAME: usize = 64;
+
+    /// Creates a decoder over the valid bytes of a directory query buffer.
+    pub fn new(buffer: &'a [u8]) -> Self {
+        let offset = if buffer.is_empty() { None } else { Some(0) };
+
+        Self { buffer, offset }
+    }
+
+    fn read_u32(record: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(record[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn read_i64(record: &[u8], offset: usize) -> i64 {
+        i64::from_le_bytes(record[offset..offset + 8].try_into().unwrap())
+    }
+
+    fn decode(record: &[u8]) -> Result<DirEntry, Error> {
+        if record.len() < Self::FILE_NAME {
+            return Err(Error::INVALID_PARAMETER);
+        }
+
+        let name_len = Self::read_u32(record, Self::FILE_NAME_LENGTH) as usize;
+
+        if name_len % 2 != 0 || record.len() - Self::FILE_NAME < name_len {
+            return Err(Error::INVALID_PARAMETER);
+        }
+
+        let name = record[Self::FILE_NAME..Self::FILE_NAME + name_len]
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]));
+
+        Ok(DirEntry {
+            name: char::decode_utf16(name)
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect(),
+            file_index: Self::read_u32(record, Self::FILE_INDEX),
+            creation_time: FileTime(Self::read_i64(record, Self::CREATION_TIME)),
+            last_access_time: FileTime(Self::read_i64(record, Self::LAST_ACCESS_TIME)),
+            last_write_time: FileTime(Self::read_i64(record, Self::LAST_WRITE_TIME)),
+            change_time: FileTime(Self::read_i64(record, Self::CHANGE_TIME)),
+            end_of_file: Self::read_i64(record, Self::END_OF_FILE) as u64,
+            allocation_size: Self::read_i64(record, Self::ALLOCATION_SIZE) as u64,
+            attributes: FileAttributes::from_bits_truncate(Self::read_u32(
+                record,
+                Self::FILE_ATTRIBUTES,
+            )),
+        })
+    }
+}
+
+impl<'a> Iterator for DirEntryDecoder<'a> {
+    type Item = Result<DirEntry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let offset = self.offset.take()?;
+        let record = &self.buffer[offset..];
+
+        if record.len() < Self::FILE_NAME {
+            return Some(Err(Error::INVALID_PARAMETER));
+        }
+
+        let next = Self::read_u32(record, Self::NEXT_ENTRY_OFFSET) as usize;
+
+        let record = if next == 0 {
+            record
+        } else if next < Self::FILE_NAME || next > record.len() {
+            return Some(Err(Error::INVALID_PARAMETER));
+        } else {
+            self.offset = Some(offset + next);
+            &record[..next]
+        };
+
+        Some(Self::decode(record))
+    }
+}
+
+/// Opens a file handle with the given access, relying on [`ZwCreateFile`] with `FILE_OPEN`.
+fn open_handle(path: &str, access: u32, options: u32) -> Result<HANDLE, Error> {
+    let name = OwnedUnicodeString::try_from(path)?;
+    let mut name = name.as_unicode_str();
+
+    let mut attrs = OBJECT_ATTRIBUTES {
+        Length: core::mem::size_of::<OBJECT_ATTRIBUTES>() as u32,
+        RootDirectory: core::ptr::null_mut(),
+        ObjectName: name.as_mut_ptr(),
+        Attributes: (ObjectFlags::CASE_INSENSITIVE | ObjectFlags::KERNEL_HANDLE).bits(),
+        SecurityDescriptor: core::ptr::null_mut(),
+        SecurityQualityOfService: core::ptr::null_mut(),
+    };
+
+    let mut io_status: IO_STATUS_BLOCK = unsafe { core::mem::zeroed() };
+    let mut handle: HANDLE = core::ptr::null_mut();
+
+    unsafe {
+        ZwCreateFile(
+            &mut handle,
+            access | win_kernel_sys::base::SYNCHRONIZE,
+            &mut attrs,
+            &mut io_status,
+            core::ptr::null_mut(),
+            0,
+            win_kernel_sys::base::FILE_SHARE_READ
+                | win_kernel_sys::base::FILE_SHARE_WRITE
+                | win_kernel_sys::base::FILE_SHARE_DELETE,
+            win_kernel_sys::base::FILE_OPEN,
+            options | win_kernel_sys::base::FILE_SYNCHRONOUS_IO_NONALERT,
+            core::ptr::null_mut(),
+            0,
+        )
+    }
+    .into_result()?;
+
+    Ok(handle)
+}
+
+/// An open file handle, closed on drop.
+pub struct File {
+    handle: HANDLE,
+}
+
+unsafe impl Send for File {}
+
+unsafe impl Sync for File {}
+
+impl File {
+    /// Opens an existing file or directory to query its attributes.
+    pub fn open(path: &str) -> Result<Self, Error> {
+        let handle = open_handle(path, win_kernel_sys::base::FILE_READ_ATTRIBUTES, 0)?;
+
+        Ok(Self { handle })
+    }
+
+    /// Returns the raw handle.
+    pub fn handle(&self) -> HANDLE {
+        self.handle
+    }
+
+    fn query_information<T>(&self, class: FILE_INFORMATION_CLASS) -> Result<T, Error> {
+        let mut io_status: IO_STATUS_BLOCK = unsafe { core::mem::zeroed() };
+        let mut info: T = unsafe { core::mem::zeroed() };
+
+        unsafe {
+            ZwQueryInformationFile(
+                self.handle,
+                &mut io_status,
+                &mut info as *mut T as _,
+                core::mem::size_of::<T>() as _,
+                class,
+            )
+        }
+        .into_result()?;
+
+        Ok(info)
+    }
+
+    fn set_information(&self, buffer: &[u8], class: FILE_INFORMATION_CLASS) -> Result<(), Error> {
+        let mut io_status: IO_STATUS_BLOCK = unsafe { core::mem::zeroed() };
+
+        unsafe {
+            ZwSetInformationFile(
+                self.handle,
+                &mut io_status,
+                buffer.as_ptr() as _,
+                buffer.len() as _,
+                class,
+            )
+        }
+        .into_result()
+    }
+
+    /// Queries the metadata of the file using `FileBasicInformation` and
+    /// `FileStandardInformation`.
+    pub fn metadata(&self) -> Result<Metadata, Error> {
+        let basic: FILE_BASIC_INFORMATION = self.query_information(FileBasicInformation)?;
+        let standard: FILE_STANDARD_INFORMATION =
+            self.query_information(FileStandardInformation)?;
+
+        Ok(Metadata::from_information(&basic, &standard))
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        unsafe {
+            ZwClose(self.handle);
+        }
+    }
+}
+
+/// The metadata of a file, built from `FILE_BASIC_INFORMATION` and `FILE_STANDARD_INFORMATION`.
+#[derive(Clone, Copy, Debug)]
+pub struct Metadata {
+    /// The creation time.
+    pub creation_time: FileTime,
+    /// The last access time.
+    pub last_access_time: FileTime,
+    /// The last write time.
+    pub last_write_time: FileTime,
+    /// The last change time.
+    pub change_time: FileTime,
+    /// The file attributes.
+    pub attributes: FileAttributes,
+    /// The size of the file in bytes.
+    pub end_of_file: u64,
+    /// The number of bytes allocated for the file.
+    pub allocation_size: u64,
+    /// The number of hard links to the file.
+    pub number_of_links: u32,
+    /// Whether a delete is pending for the file.
+    pub delete_pending: bool,
+}
+
+impl Metadata {
+    /// Builds the metadata from the raw information classes.
+    pub fn from_information(
+        basic: &FILE_BASIC_INFORMATION,
+        standard: &FILE_STANDARD_INFORMATION,
+    ) -> Self {
+        unsafe {
+            Self {
+                creation_time: FileTime(basic.CreationTime.QuadPart),
+                last_access_time: FileTime(basic.LastAccessTime.QuadPart),
+                last_write_time: FileTime(basic.LastWriteTime.QuadPart),
+                change_time: FileTime(basic.ChangeTime.QuadPart),
+                attributes: FileAttributes::from_bits_truncate(basic.FileAttributes),
+                end_of_file: standard.EndOfFile.QuadPart as u64,
+                allocation_size: standard.AllocationSize.QuadPart as u64,
+                number_of_links: standard.NumberOfLinks,
+                delete_pending: standard.DeletePending != 0,
+            }
+        }
+    }
+
+    /// Returns whether the file is a directory.
+    pub fn is_dir(&self) -> bool {
+        self.attributes.contains(FileAttributes::DIRECTORY)
+    }
+
+    /// Returns the size of the file in bytes.
+    pub fn len(&self) -> u64 {
+        self.end_of_file
+    }
+}
+
+/// Queries the metadata of the file or directory at the given path.
+pub fn metadata(path: &str) -> Result<Metadata, Error> {
+    File::open(path)?.metadata()
+}
+
+/// Deletes the file or empty directory at the given path using `FileDispositionInformation`.
+pub fn remove_file(path: &str) -> Result<(), Error> {
+    let file = File {
+        handle: open_handle(path, win_kernel_sys::base::DELETE, 0)?,
+    };
+
+    let mut info: FILE_DISPOSITION_INFORMATION = unsafe { core::mem::zeroed() };
+    info.DeleteFile = true as _;
+
+    let buffer = unsafe {
+        core::slice::from_raw_parts(
+            &info as *const _ as *const u8,
+            core::mem::size_of::<FILE_DISPOSITION_INFORMATION>(),
+        )
+    };
+
+    file.set_information(buffer, FileDispositionInformation)
+}
+
+/// Encodes a `FILE_RENAME_INFORMATION` record for the given target name. The record is never
+/// smaller than the structure itself, which the file system may check for short names.
+pub fn encode_rename_information(target: &[u16], replace_if_exists: bool) -> Vec<u8> {
+    // ReplaceIfExists (padded to 8 bytes), RootDirectory, FileNameLength, FileName.
+    const FILE_NAME: usize = 20;
+
+    let name_len = target.len() * 2;
+    let len = core::cmp::max(
+        core::mem::size_of::<FILE_RENAME_INFORMATION>(),
+        FILE_NAME + name_len,
+    );
+    let mut buffer = vec![0u8; len];
+
+    buffer[0] = replace_if_exists as u8;
+    buffer[16..20].copy_from_slice(&(name_len as u32).to_le_bytes());
+
+    for (i, c) in target.iter().enumerate() {
+        buffer[FILE_NAME + i * 2..FILE_NAME + i * 2 + 2].copy_from_slice(&c.to_le_bytes());
+    }
+
+    buffer
+}
+
+/// Renames the file or directory at `from` to the fully qualified path `to`.
+pub fn rename(from: &str, to: &str, replace_if_exists: bool) -> Result<(), Error> {
+    let file = File {
+        handle: open_handle(from, win_kernel_sys::base::DELETE, 0)?,
+    };
+
+    let target = OwnedUnicodeString::try_from(to)?;
+    let info = encode_rename_information(target.as_slice(), replace_if_exists);
+
+    // The record contains a HANDLE, hence copy it into a pointer-aligned buffer.
+    let mut aligned = vec![0u64; (info.len() + 7) / 8];
+    let bytes = unsafe {
+        core::slice::from_raw_parts_mut(aligned.as_mut_ptr() as *mut u8, info.len())
+    };
+    bytes.copy_from_slice(&info);
+
+    file.set_information(bytes, FileRenameInformation)
+}
+
+/// An iterator over the entries of a directory, backed by [`ZwQueryDirectoryFile`].
+pub struct ReadDir {
+    file: File,
+    pattern: Option<OwnedUnicodeString>,
+    buffer: Vec<u64>,
+    entries: Vec<DirEntry>,
+    restart: bool,
+    done: bool,
+}
+
+impl ReadDir {
+    /// The default size of the query buffer in bytes.
+    const BUFFER_SIZE: usize = 4096;
+
+    /// Fetches the next batch of entries, returns `false` once the directory is exhausted.
+    fn fill(&mut self) -> Result<bool, Error> {
+        let mut io_status: IO_STATUS_BLOCK = unsafe { core::mem::zeroed() };
+
+        let mut pattern = self
+            .pattern
+            .as_ref()
+            .map(|pattern| pattern.as_unicode_str());
+
+        let status = unsafe {
+            ZwQueryDirectoryFile(
+                self.file.handle,
+                core::ptr::null_mut(),
+                None,
+                core::ptr::null_mut(),
+                &mut io_status,
+                self.buffer.as_mut_ptr() as _,
+                (self.buffer.len() * 8) as _,
+                FileDirectoryInformation,
+                false as _,
+                match pattern {
+                    Some(ref mut pattern) => pattern.as_mut_ptr(),
+                    _ => core::ptr::null_mut(),
+                },
+                self.restart as _,
+            )
+        };
+
+        self.restart = false;
+
+        if status == STATUS_NO_MORE_FILES {
+            return Ok(false);
+        }
+
+        status.into_result()?;
+
+        let len = core::cmp::min(io_status.Information as usize, self.buffer.len() * 8);
+        let bytes = unsafe { core::slice::from_raw_parts(self.buffer.as_ptr() as *const u8, len) };
+
+        for entry in DirEntryDecoder::new(bytes) {
+            self.entries.push(entry?);
+        }
+
+        // Entries are popped from the back.
+        self.entries.reverse();
+
+        Ok(true)
+    }
+}
+
+impl Iterator for ReadDir {
+    type Item = Result<DirEntry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.entries.pop() {
+                return Some(Ok(entry));
+            }
+
+            if self.done {
+                return None;
+            }
+
+            match self.fill() {
+                Ok(true) => continue,
+                Ok(false) => {
+                    self.done = true;
+                    return None;
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// Opens the directory at the given path and returns an iterator over its entries. If a pattern
+/// is given, such as `*.sys`, only the entries that match it are returned.
+pub fn read_dir(path: &str, pattern: Option<&str>) -> Result<ReadDir, Error> {
+    let pattern = match pattern {
+        Some(pattern) => Some(OwnedUnicodeString::try_from(pattern)?),
+        None => None,
+    };
+
+    let file = File {
+        handle: open_handle(
+            path,
+            win_kernel_sys::base::FILE_LIST_DIRECTORY,
+            win_kernel_sys::base::FILE_DIRECTORY_FILE,
+        )?,
+    };
+
+    Ok(ReadDir {
+        file,
+        pattern,
+        buffer: vec![0u64; ReadDir::BUFFER_SIZE / 8],
+        entries: Vec::new(),
+        restart: true,
+        done: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encodes a `FILE_DIRECTORY_INFORMATION` record with the given name, padded to 8 bytes.
+    fn record(name: &str, attributes: u32, end_of_file: i64, last: bool) -> Vec<u8> {
+        let name: Vec<u16> = name.encode_utf16().collect();
+        let len = (64 + name.len() * 2 + 7) & !7;
+        let mut record = vec![0u8; len];
+
+        if !last {
+            record[0..4].copy_from_slice(&(len as u32).to_le_bytes());
+        }
+
+        record[4..8].copy_from_slice(&7u32.to_le_bytes());
+        record[8..16].copy_from_slice(&1i64.to_le_bytes());
+        record[16..24].copy_from_slice(&2i64.to_le_bytes());
+        record[24..32].copy_from_slice(&3i64.to_le_bytes());
+        record[32..40].copy_from_slice(&4i64.to_le_bytes());
+        record[40..48].copy_from_slice(&end_of_file.to_le_bytes());
+        record[48..56].copy_from_slice(&4096i64.to_le_bytes());
+        record[56..60].copy_from_slice(&attributes.to_le_bytes());
+        record[60..64].copy_from_slice(&((name.len() * 2) as u32).to_le_bytes());
+
+        for (i, c) in name.iter().enumerate() {
+            record[64 + i * 2..66 + i * 2].copy_from_slice(&c.to_le_bytes());
+        }
+
+        record
+    }
+
+    #[test]
+    fn decodes_chained_records() {
+        let mut buffer = record(".", FileAttributes::DIRECTORY.bits(), 0, false);
+        buffer.extend(record("file.txt", FileAttributes::ARCHIVE.bits(), 1234, false));
+        buffer.extend(record("sub", FileAttributes::DIRECTORY.bits(), 0, true));
+
+        let entries: Vec<DirEntry> = DirEntryDecoder::new(&buffer)
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(entries.len(), 3);
+        assert!(entries[0].is_dot() && entries[0].is_dir());
+        assert_eq!(entries[1].name, "file.txt");
+        assert_eq!(entries[1].end_of_file, 1234);
+        assert_eq!(entries[1].allocation_size, 4096);
+        assert_eq!(entries[1].file_index, 7);
+        assert_eq!(entries[1].creation_time, FileTime::from_raw(1));
+        assert_eq!(entries[1].change_time, FileTime::from_raw(4));
+        assert!(!entries[1].is_dir());
+        assert_eq!(entries[2].name, "sub");
+        assert!(entries[2].is_dir());
+    }
+
+    #[test]
+    fn empty_buffer_has_no_entries() {
+        assert!(DirEntryDecoder::new(&[]).next().is_none());
+    }
+
+    #[test]
+    fn rejects_truncated_record() {
+        let buffer = record("file.txt", 0, 0, true);
+        let mut decoder = DirEntryDecoder::new(&buffer[..40]);
+
+        assert_eq!(decoder.next().unwrap().unwrap_err(), Error::INVALID_PARAMETER);
+        assert!(decoder.next().is_none());
+    }
+
+    #[test]
+    fn rejects_name_past_the_record() {
+        let mut buffer = record("file.txt", 0, 0, true);
+        buffer[60..64].copy_from_slice(&1000u32.to_le_bytes());
+
+        assert_eq!(
+            DirEntryDecoder::new(&buffer).next().unwrap().unwrap_err(),
+            Error::INVALID_PARAMETER
+        );
+    }
+
+    #[test]
+    fn rejects_next_offset_past_the_buffer() {
+        let mut buffer = record("file.txt", 0, 0, true);
+        let len = buffer.len() as u32;
+        buffer[0..4].copy_from_slice(&(len + 8).to_le_bytes());
+
+        assert_eq!(
+            DirEntryDecoder::new(&buffer).next().unwrap().unwrap_err(),
+            Error::INVALID_PARAMETER
+        );
+    }
+
+    #[test]
+    fn replaces_invalid_utf16() {
+        let mut buffer = record("ab", 0, 0, true);
+        buffer[64..66].copy_from_slice(&0xD800u16.to_le_bytes());
+
+        let entry = DirEntryDecoder::new(&buffer).next().unwrap().unwrap();
+
+        assert_eq!(entry.name, "\u{FFFD}b");
+    }
+
+    #[test]
+    fn converts_file_time_to_unix_seconds() {
+        assert_eq!(FileTime::from_raw(116_444_736_000_000_000).unix_seconds(), Some(0));
+        assert_eq!(
+            FileTime::from_raw(116_444_736_000_000_000 + 15_000_000).unix_seconds(),
+            Some(1)
+        );
+        assert_eq!(FileTime::from_raw(0).unix_seconds(), None);
+    }
+
+    #[test]
+    fn encodes_rename_information() {
+        let buffer = encode_rename_information(&[b'a' as u16, b'b' as u16], true);
+
+        assert_eq!(buffer.len(), 24);
+        assert_eq!(buffer[0], 1);
+        assert_eq!(&buffer[16..20], &4u32.to_le_bytes());
+        assert_eq!(&buffer[20..24], &[b'a', 0, b'b', 0]);
+    }
+
+    #[test]
+    fn rename_information_is_at_least_the_structure() {
+        let min = core::mem::size_of::<FILE_RENAME_INFORMATION>();
+
+        let buffer = encode_rename_information(&[], true);
+        assert_eq!(buffer.len(), min);
+        assert_eq!(buffer[0], 1);
+        assert_eq!(&buffer[16..20], &0u32.to_le_bytes());
+
+        let buffer = encode_rename_information(&[b'a' as u16], false);
+        assert_eq!(buffer.len(), min);
+        assert_eq!(buffer[0], 0);
+        assert_eq!(&buffer[16..20], &2u32.to_le_bytes());
+        assert_eq!(&buffer[20..22], &b'a' as u16.to_le_bytes());
+    }
+
+    #[test]
+    fn rename_information_holds_the_whole_name() {
+        let name: Vec<u16> = "\\??\\C:\\target.txt".encode_utf16().collect();
+        let buffer = encode_rename_information(&name, false);
+
+        assert_eq!(buffer.len(), 20 + name.len() * 2);
+        assert_eq!(&buffer[16..20], &((name.len() * 2) as u32).to_le_bytes());
+        assert!(buffer[20..]
+            .chunks(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .eq(name.iter().copied()));
+    }
+}
@@ -11,8 +11,8 @@ use bitflags::bitflags;
 
 use win_kernel_sys::base::{
     BOOLEAN, CHAR, CLIENT_ID, GUID, HANDLE, KAPC, KINTERRUPT_MODE, KINTERRUPT_POLARITY, KPRIORITY,
-    KPROCESSOR_MODE, LARGE_INTEGER, LONG, LPSTR, NTSTATUS, PCONTEXT, PEPROCESS, PKAPC, PKTHREAD,
-    POBJECT_TYPE, POHANDLE__, PROCESSINFOCLASS, PVOID, RTL_BALANCED_NODE, SIZE_T,
+    KPROCESSOR_MODE, LARGE_INTEGER, LONG, LPSTR, NTSTATUS, OBJECT_ATTRIBUTES, PCONTEXT, PEPROCESS,
+    PKAPC, PKTHREAD, POBJECT_TYPE, POHANDLE__, PROCESSINFOCLASS, PVOID, RTL_BALANCED_NODE, SIZE_T,
     SYSTEM_POWER_STATE, TRACEHANDLE, UCHAR, ULONG, ULONG64, ULONGLONG, UNICODE_STRING, USHORT,
     WCHAR, XSAVE_AREA_HEADER, XSAVE_FORMAT, _BUS_HANDLER, _CLIENT_ID, _CM_RESOURCE_LIST,
     _DEBUG_DEVICE_DESCRIPTOR, _DEVICE_OBJECT, _DMA_ADAPTER, _DMA_IOMMU_INTERFACE,
@@ -110,6 +110,42 @@ extern "system" {
         SystemInformation: PVOID,
         SystemInformationLength: ULONG,
     ) -> NTSTATUS;
+    pub fn ZwOpenDirectoryObject(
+        DirectoryHandle: *mut HANDLE,
+        DesiredAccess: ULONG,
+        ObjectAttributes: *mut OBJECT_ATTRIBUTES,
+    ) -> NTSTATUS;
+    pub fn ZwQueryDirectoryObject(
+        DirectoryHandle: HANDLE,
+        Buffer: PVOID,
+        Length: ULONG,
+        ReturnSingleEntry: BOOLEAN,
+        RestartScan: BOOLEAN,
+        Context: *mut ULONG,
+        ReturnLength: *mut ULONG,
+    ) -> NTSTATUS;
+    pub fn ExInitializeLookasideListEx(
+        Lookaside: *mut LOOKASIDE_LIST_EX,
+        Allocate: PALLOCATE_FUNCTION_EX,
+        Free: PFREE_FUNCTION_EX,
+        PoolType: ULONG,
+        Flags: ULONG,
+        Size: SIZE_T,
+        Tag: ULONG,
+        Depth: USHORT,
+    ) -> NTSTATUS;
+    pub fn ExDeleteLookasideListEx(Lookaside: *mut LOOKASIDE_LIST_EX);
+    pub fn ExpInterlockedPopEntrySList(ListHead: *mut SLIST_HEADER) -> *mut SLIST_ENTRY;
+    pub fn ExpInterlockedPushEntrySList(
+        ListHead: *mut SLIST_HEADER,
+        ListEntry: *mut SLIST_ENTRY,
+    ) -> *mut SLIST_ENTRY;
+    pub fn RtlCaptureStackBackTrace(
+        FramesToSkip: ULONG,
+        FramesToCapture: ULONG,
+        BackTrace: *mut PVOID,
+        BackTraceHash: *mut ULONG,
+    ) -> USHORT;
     pub fn RtlLookupFunctionEntry(
         ControlPc: ULONG64,
         ImageBase: *mut ULONG64,
@@ -125,6 +161,108 @@ extern "system" {
         EstablisherFrame: *mut ULONG64,
         ContextPointers: PVOID,
     ) -> PVOID;
+    pub fn PsSetCreateProcessNotifyRoutineEx(
+        NotifyRoutine: PCREATE_PROCESS_NOTIFY_ROUTINE_EX,
+        Remove: BOOLEAN,
+    ) -> NTSTATUS;
+}
+
+extern "C" {
+    /// `MmProbeAndLockPages` in a `__try`/`__except` block, which returns the exception code
+    /// instead of raising it, like the SEH wrappers of `win_kernel_sys::intrin`. It is defined in
+    /// `src/seh.c`, which the build script compiles.
+    pub fn probe_and_lock_pages_safe(
+        MemoryDescriptorList: *mut _MDL,
+        AccessMode: KPROCESSOR_MODE,
+        Operation: i32,
+    ) -> NTSTATUS;
+
+    /// `MmMapLockedPagesSpecifyCache` in a `__try`/`__except` block, for user mode mappings, which
+    /// raise an exception on failure instead of returning null. Stores the mapping in
+    /// `MappedAddress` and returns `STATUS_INSUFFICIENT_RESOURCES` if the mapping is null.
+    pub fn map_locked_pages_safe(
+        MemoryDescriptorList: *mut _MDL,
+        AccessMode: KPROCESSOR_MODE,
+        CacheType: i32,
+        RequestedAddress: PVOID,
+        Priority: ULONG,
+        MappedAddress: *mut PVOID,
+    ) -> NTSTATUS;
+}
+
+pub const DIRECTORY_QUERY: ULONG = 0x0001;
+pub const DIRECTORY_TRAVERSE: ULONG = 0x0002;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct OBJECT_DIRECTORY_INFORMATION {
+    pub Name: UNICODE_STRING,
+    pub TypeName: UNICODE_STRING,
+}
+
+#[repr(C, align(16))]
+#[derive(Copy, Clone)]
+pub struct SLIST_ENTRY {
+    pub Next: *mut SLIST_ENTRY,
+}
+
+#[repr(C, align(16))]
+#[derive(Copy, Clone)]
+pub struct SLIST_HEADER {
+    pub Alignment: ULONGLONG,
+    pub Region: ULONGLONG,
+}
+
+impl SLIST_HEADER {
+    /// `ExQueryDepthSList`, the depth is stored in the low 16 bits on x64.
+    #[inline]
+    pub fn depth(&self) -> USHORT {
+        unsafe { core::ptr::read_volatile(&self.Alignment) as USHORT }
+    }
+}
+
+pub type PALLOCATE_FUNCTION_EX = Option<
+    unsafe extern "system" fn(
+        PoolType: ULONG,
+        NumberOfBytes: SIZE_T,
+        Tag: ULONG,
+        Lookaside: *mut LOOKASIDE_LIST_EX,
+    ) -> PVOID,
+>;
+
+/// `CreateInfo` points to a `PS_CREATE_NOTIFY_INFO` when the process is created, and is null when
+/// it exits.
+pub type PCREATE_PROCESS_NOTIFY_ROUTINE_EX =
+    Option<unsafe extern "system" fn(Process: PEPROCESS, ProcessId: HANDLE, CreateInfo: PVOID)>;
+
+pub type PFREE_FUNCTION_EX =
+    Option<unsafe extern "system" fn(Buffer: PVOID, Lookaside: *mut LOOKASIDE_LIST_EX)>;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct GENERAL_LOOKASIDE_POOL {
+    pub ListHead: SLIST_HEADER,
+    pub Depth: USHORT,
+    pub MaximumDepth: USHORT,
+    pub TotalAllocates: ULONG,
+    pub AllocateMisses: ULONG,
+    pub TotalFrees: ULONG,
+    pub FreeMisses: ULONG,
+    pub Type: ULONG,
+    pub Tag: ULONG,
+    pub Size: ULONG,
+    pub AllocateEx: PALLOCATE_FUNCTION_EX,
+    pub FreeEx: PFREE_FUNCTION_EX,
+    pub ListEntry: _LIST_ENTRY,
+    pub LastTotalAllocates: ULONG,
+    pub LastAllocateMisses: ULONG,
+    pub Future: [ULONG; 2],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct LOOKASIDE_LIST_EX {
+    pub L: GENERAL_LOOKASIDE_POOL,
 }
 
 #[inline]
@@ -0,0 +1,168 @@
+//! Optional kernel exports that are resolved at runtime.
+//!
+//! A driver that imports a routine the running kernel does not export fails to load, so routines
+//! that only exist on some of the supported releases, e.g. [`ExAllocatePool2`] on Windows 10 2004
+//! and later, are declared with [`optional_import!`](crate::optional_import) instead. The routine
+//! is looked up with [`MmGetSystemRoutineAddress`] on first use and the result is cached, such
+//! that the availability of a routine can be checked where a version check would be used
+//! otherwise.
+//!
+//! ```ignore
+//! win_kernel::optional_import! {
+//!     /// Available on Windows 10 2004 and later.
+//!     pub fn ExAllocatePool2(Flags: u64, NumberOfBytes: SIZE_T, Tag: ULONG) -> PVOID;
+//! }
+//!
+//! let ptr = match ExAllocatePool2.get() {
+//!     Some(allocate) => unsafe { allocate(POOL_FLAG_NON_PAGED, size, tag) },
+//!     None => unsafe { ExAllocatePoolWithTag(NonPagedPoolNx, size, tag) },
+//! };
+//! ```
+
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use win_kernel_sys::base::{PVOID, SIZE_T, ULONG, UNICODE_STRING};
+use win_kernel_sys::ntoskrnl::MmGetSystemRoutineAddress;
+
+use crate::error::Error;
+use crate::intrin::current_irql;
+
+/// The cached state of an import that has not been looked up yet.
+const UNRESOLVED: usize = 0;
+
+/// The cached state of an import that the running kernel does not export.
+const MISSING: usize = 1;
+
+/// The longest routine name that can be looked up, in characters.
+const MAX_NAME_LEN: usize = 128;
+
+/// A kernel export that may be missing, declared with [`optional_import!`](crate::optional_import).
+/// `F` is the `unsafe extern "system" fn` type of the routine.
+pub struct OptionalImport<F> {
+    name: &'static str,
+    address: AtomicUsize,
+    marker: PhantomData<F>,
+}
+
+impl<F: Copy> OptionalImport<F> {
+    /// Declares the export with the given name, which is not looked up until first use.
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            address: AtomicUsize::new(UNRESOLVED),
+            marker: PhantomData,
+        }
+    }
+
+    /// Returns the name of the export.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns the address of the export, looking it up on first use.
+    ///
+    /// [`MmGetSystemRoutineAddress`] may only be called at `PASSIVE_LEVEL`. Above that, an export
+    /// that has not been looked up yet is reported as missing, but the result is not cached, such
+    /// that the next call at `PASSIVE_LEVEL` looks it up.
+    pub fn address(&self) -> Option<usize> {
+        match self.address.load(Ordering::Acquire) {
+            MISSING => None,
+            UNRESOLVED => {
+                if current_irql() != 0 {
+                    return None;
+                }
+
+                let address = resolve(self.name);
+
+                // Racing lookups all find the same address, so the last store wins harmlessly.
+                self.address
+                    .store(address.unwrap_or(MISSING), Ordering::Release);
+
+                address
+            }
+            address => Some(address),
+        }
+    }
+
+    /// Returns the export, or `None` if the running kernel does not export it.
+    pub fn get(&self) -> Option<F> {
+        const {
+            assert!(core::mem::size_of::<F>() == core::mem::size_of::<usize>());
+        }
+
+        self.address()
+            .map(|address| unsafe { core::mem::transmute_copy::<usize, F>(&address) })
+    }
+
+    /// Returns the export, or [`Error::NOT_IMPLEMENTED`] if the running kernel does not export
+    /// it.
+    pub fn try_get(&self) -> Result<F, Error> {
+        self.get().ok_or(Error::NOT_IMPLEMENTED)
+    }
+
+    /// Returns whether the running kernel exports the routine.
+    pub fn is_available(&self) -> bool {
+        self.address().is_some()
+    }
+}
+
+/// Looks up the export with the given name. The name is converted on the stack, as imports are
+/// also used by the allocator itself.
+fn resolve(name: &str) -> Option<usize> {
+    let mut buffer = [0u16; MAX_NAME_LEN];
+    let mut len = 0;
+
+    for c in name.encode_utf16() {
+        *buffer.get_mut(len)? = c;
+        len += 1;
+    }
+
+    let mut name = UNICODE_STRING {
+        Length: (len * 2) as u16,
+        MaximumLength: (MAX_NAME_LEN * 2) as u16,
+        Buffer: buffer.as_mut_ptr(),
+    };
+
+    let address = unsafe { MmGetSystemRoutineAddress(&mut name) };
+
+    match address as usize {
+        0 => None,
+        address => Some(address),
+    }
+}
+
+/// Declares optional kernel exports with their signatures as statics of type
+/// [`OptionalImport`](crate::import::OptionalImport), which are looked up on first use.
+///
+/// ```ignore
+/// win_kernel::optional_import! {
+///     pub fn PsSetCreateProcessNotifyRoutineEx2(
+///         NotifyType: i32,
+///         NotifyInformation: PVOID,
+///         Remove: BOOLEAN,
+///     ) -> NTSTATUS;
+/// }
+/// ```
+#[macro_export]
+macro_rules! optional_import {
+    (
+        $(
+            $(#[$meta:meta])*
+            $vis:vis fn $name:ident($($arg:ident : $ty:ty),* $(,)?) $(-> $ret:ty)?;
+        )*
+    ) => {
+        $(
+            $(#[$meta])*
+            #[allow(non_upper_case_globals)]
+            $vis static $name: $crate::import::OptionalImport<
+                unsafe extern "system" fn($($arg: $ty),*) $(-> $ret)?
+            > = $crate::import::OptionalImport::new(::core::stringify!($name));
+        )*
+    };
+}
+
+crate::optional_import! {
+    /// Allocates pool memory, available on Windows 10 2004 and later.
+    pub fn ExAllocatePool2(Flags: u64, NumberOfBytes: SIZE_T, Tag: ULONG) -> PVOID;
+}
@@ -24,3 +24,37 @@ pub fn write_msr(register: u32, value: u64) -> Result<(), Error> {
 
     Ok(())
 }
+
+/// Returns the current IRQL of the processor, which is what `KeGetCurrentIrql` reads on x64.
+pub fn current_irql() -> u8 {
+    let irql: u64;
+
+    unsafe {
+        core::arch::asm!("mov {}, cr8", out(reg) irql, options(nomem, nostack, preserves_flags));
+    }
+
+    irql as u8
+}
+
+/// Returns the value of CR3, i.e. the physical address of the top-level page table of the current
+/// address space in bits 12 to 51, and the PCID or the cache flags in the low bits.
+pub fn read_cr3() -> u64 {
+    let cr3: u64;
+
+    unsafe {
+        core::arch::asm!("mov {}, cr3", out(reg) cr3, options(nomem, nostack, preserves_flags));
+    }
+
+    cr3
+}
+
+/// Returns the value of CR4.
+pub fn read_cr4() -> u64 {
+    let cr4: u64;
+
+    unsafe {
+        core::arch::asm!("mov {}, cr4", out(reg) cr4, options(nomem, nostack, preserves_flags));
+    }
+
+    cr4
+}
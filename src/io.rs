@@ -1,10 +1,11 @@
 //! lib sys print
 //!
 
+use core::fmt::Write;
 use core::result;
-use win_kernel_sys::base::ANSI_STRING;
 use win_kernel_sys::ntoskrnl::DbgPrint;
 
+use crate::string::{AnsiStringBuf, UnicodeStringBuf};
 use crate::Error;
 
 /// like print!
@@ -21,22 +22,23 @@ macro_rules! println {
     
 }
 
-/// Format the string using the `alloc::format!` as this is guaranteed to return a `String`
-/// instead of a `Result` that we would have to `unwrap`. This ensures that this code stays
-/// panic-free.
+/// The size of the stack buffer the `print!` family formats into. `DbgPrint` truncates its output
+/// at 512 bytes, so there is no point in formatting more than that.
+const PRINT_BUF_SIZE: usize = 512;
+
+/// Format the string into a buffer on the stack, such that printing never allocates and stays
+/// usable when the pool is exhausted. Output that does not fit is truncated.
 /// Print the string. We must make sure to not pass this user-supplied string as the format
 /// string, as `DbgPrint` may then format any format specifiers it contains. This could
 /// potentially be an attack vector.
 #[doc(hidden)]
 pub fn _print(args: core::fmt::Arguments) {
+    let mut buf = AnsiStringBuf::<PRINT_BUF_SIZE>::new();
 
-    let s = alloc::format!("{}", args);
+    // A formatting error only means that the output was truncated.
+    let _ = buf.write_fmt(args);
 
-    let s = ANSI_STRING {
-        Length: s.len() as u16,
-        MaximumLength: s.len() as u16,
-        Buffer: s.as_ptr() as _,
-    };
+    let s = buf.as_ansi_string();
 
     unsafe { DbgPrint("%Z\0".as_ptr() as _, &s) };
 }
@@ -57,16 +59,15 @@ macro_rules! printlnw {
 
 #[doc(hidden)]
 pub fn _printw(args: core::fmt::Arguments) {
-    // Format the string using the `alloc::format!` as this is guaranteed to return a `String`
-    // instead of a `Result` that we would have to `unwrap`. This ensures that this code stays
-    // panic-free.
-    let s = alloc::format!("{}", args);
+    // Format the string into a buffer on the stack, such that printing never allocates. A
+    // formatting error only means that the output was truncated.
+    let mut buf = UnicodeStringBuf::<{ PRINT_BUF_SIZE / 2 }>::new();
+    let _ = buf.write_fmt(args);
 
     // Print the string. We must make sure to not pass this user-supplied string as the format
     // string, as `DbgPrint` may then format any format specifiers it contains. This could
     // potentially be an attack vector.
-    let s = crate::string::create_unicode_from_str(&s);
-    unsafe { DbgPrint("%wZ\0".as_ptr() as _, &s) };
+    unsafe { DbgPrint("%wZ\0".as_ptr() as _, buf.as_unicode_str().as_raw()) };
 }
 
 /// the mod [`Result<T>`]
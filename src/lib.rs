@@ -34,26 +34,40 @@ pub use crate::device::{
 pub use crate::driver::Driver;
 pub use crate::error::Error;
 pub use crate::ioctl::{ControlCode, RequiredAccess, TransferMethod};
+pub use crate::object::ObjectDirectory;
+pub use crate::registry::{RegistryKey, RegistryValue};
 pub use crate::request::{IoControlRequest, IoRequest, ReadRequest, WriteRequest};
-pub use crate::symbolic_link::SymbolicLink;
+pub use crate::string::{OwnedUnicodeString, UnicodeStr};
+pub use crate::symbolic_link::{LinkScope, SymbolicLink};
+pub use crate::user_buffer::{LockedUserBuffer, ProcessExitRelease};
 pub use crate::user_ptr::UserPtr;
+pub use crate::version::{VersionInfo, WindowsRelease};
 
 pub mod affinity;
 pub mod allocator;
+pub mod config;
 
 pub mod device;
 pub mod driver;
 pub mod error;
+pub mod file;
+pub mod import;
 pub mod intrin;
 pub mod io;
 pub mod ioctl;
 pub mod mdl;
 pub mod memory;
+pub mod mmio;
+pub mod object;
+pub mod paging;
+pub mod path;
 pub mod process;
+pub mod registry;
 pub mod request;
 pub mod section;
 pub mod string;
 pub mod symbolic_link;
+pub mod user_buffer;
 pub mod user_ptr;
 pub mod version;
 pub mod headers;
@@ -1,9 +1,19 @@
 //! mod mdl
+//!
+//! Memory descriptor lists describe the physical pages behind a virtual buffer. An MDL is either
+//! built for non-paged memory, or probed and locked, before its pages can be accessed, mapped or
+//! described by a partial MDL.
 
+use core::marker::PhantomData;
+use core::ptr::NonNull;
 
-use crate::error::Error;
-use crate::memory::MemoryCaching;
+use bitflags::bitflags;
+use win_kernel_sys::base::MDL;
 
+use crate::allocator::PAGE_SIZE;
+use crate::error::{Error, IntoResult};
+use crate::headers::{map_locked_pages_safe, probe_and_lock_pages_safe};
+use crate::memory::{MemoryCaching, PhysicalAddress};
 
 /// AccessMode
 #[repr(i32)]
@@ -13,10 +23,108 @@ pub enum AccessMode {
     UserMode = win_kernel_sys::base::_MODE::UserMode,
 }
 
+/// The access the pages are locked for, see [`MemoryDescriptorList::probe_and_lock`].
+#[repr(i32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockOperation {
+    /// The pages are only read.
+    IoReadAccess = win_kernel_sys::base::_LOCK_OPERATION::IoReadAccess,
+    /// The pages are written to, e.g. by a device reading into them.
+    IoWriteAccess = win_kernel_sys::base::_LOCK_OPERATION::IoWriteAccess,
+    /// The pages are read and written.
+    IoModifyAccess = win_kernel_sys::base::_LOCK_OPERATION::IoModifyAccess,
+}
+
+/// `MDL_PAGES_LOCKED`
+const MDL_PAGES_LOCKED: i16 = 0x0002;
+
+/// `MDL_SOURCE_IS_NONPAGED_POOL`
+const MDL_SOURCE_IS_NONPAGED_POOL: i16 = 0x0004;
+
+/// `MDL_PARTIAL`
+const MDL_PARTIAL: i16 = 0x0010;
+
+/// `HighPagePriority`, mappings of pages that the driver owns should not fail under pressure.
+const HIGH_PAGE_PRIORITY: u32 = 32;
+
+/// `MdlMappingNoExecute`, combined with the page priority.
+const MDL_MAPPING_NO_EXECUTE: u32 = 0x4000_0000;
+
+/// Returns the number of pages the MDL spans, `ADDRESS_AND_SIZE_TO_SPAN_PAGES`.
+unsafe fn page_count(raw: *const MDL) -> usize {
+    let offset = (*raw).ByteOffset as usize & (PAGE_SIZE - 1);
+    let size = (*raw).ByteCount as usize;
+
+    (offset + size).div_ceil(PAGE_SIZE)
+}
+
+/// Returns the page frame numbers that follow the MDL, `MmGetMdlPfnArray`.
+unsafe fn page_frames<'a>(raw: *const MDL) -> &'a [u64] {
+    core::slice::from_raw_parts(raw.add(1) as *const u64, page_count(raw))
+}
+
+/// Returns whether the MDL describes resident pages, i.e. it has been built for non-paged pool
+/// or its pages are locked.
+unsafe fn describes_pages(raw: *const MDL) -> bool {
+    (*raw).MdlFlags & (MDL_PAGES_LOCKED | MDL_SOURCE_IS_NONPAGED_POOL | MDL_PARTIAL) != 0
+}
+
+/// Maps the pages of the MDL with [`MmMapLockedPagesSpecifyCache`]. Kernel mode mappings fail
+/// with [`Error::INSUFFICIENT_RESOURCES`] instead of bug checking if there are no free system page
+/// table entries. User mode mappings raise an exception on failure, so they go through
+/// [`map_locked_pages_safe`], which returns the exception code instead.
+unsafe fn map_pages(
+    raw: *mut MDL,
+    access: AccessMode,
+    caching: MemoryCaching,
+    desired_addr: *mut core::ffi::c_void,
+    priority: u32,
+) -> Result<NonNull<u8>, Error> {
+    use win_kernel_sys::ntoskrnl::MmMapLockedPagesSpecifyCache;
+
+    if access == AccessMode::UserMode {
+        let mut ptr = core::ptr::null_mut();
+
+        map_locked_pages_safe(
+            raw,
+            access as _,
+            caching as _,
+            desired_addr,
+            priority,
+            &mut ptr,
+        )
+        .into_result()?;
+
+        return NonNull::new(ptr as *mut u8).ok_or(Error::INSUFFICIENT_RESOURCES);
+    }
+
+    let ptr = MmMapLockedPagesSpecifyCache(
+        raw,
+        access as _,
+        caching as _,
+        desired_addr,
+        false as _,
+        priority,
+    );
+
+    NonNull::new(ptr as *mut u8).ok_or(Error::INSUFFICIENT_RESOURCES)
+}
+
+/// Unmaps a mapping made with [`map_pages`] for the given access mode. A kernel mode mapping of
+/// an MDL that was built for non-paged pool is the pool address itself, which must not be
+/// unmapped, whereas a user mode mapping of the same MDL is a mapping of its own.
+unsafe fn unmap_pages(ptr: NonNull<u8>, raw: *mut MDL, access: AccessMode) {
+    use win_kernel_sys::ntoskrnl::MmUnmapLockedPages;
+
+    if access == AccessMode::UserMode || (*raw).MdlFlags & MDL_SOURCE_IS_NONPAGED_POOL == 0 {
+        MmUnmapLockedPages(ptr.as_ptr() as _, raw);
+    }
+}
 
 /// MemoryDescriptorList
 pub struct MemoryDescriptorList {
-    raw: *mut win_kernel_sys::base::MDL,
+    raw: *mut MDL,
+    locked: bool,
 }
 
 unsafe impl Send for MemoryDescriptorList {}
@@ -28,6 +136,10 @@ impl MemoryDescriptorList {
     pub fn new(addr: *mut core::ffi::c_void, size: usize) -> Result<Self, Error> {
         use win_kernel_sys::ntoskrnl::IoAllocateMdl;
 
+        if size == 0 || size > u32::MAX as usize {
+            return Err(Error::INVALID_PARAMETER);
+        }
+
         let raw = unsafe {
             IoAllocateMdl(
                 addr,
@@ -42,9 +154,14 @@ impl MemoryDescriptorList {
             return Err(Error::INSUFFICIENT_RESOURCES);
         }
 
-        Ok(Self { raw })
+        Ok(Self { raw, locked: false })
     }
-    
+
+    /// Returns the raw MDL.
+    pub fn as_raw(&self) -> *mut MDL {
+        self.raw
+    }
+
     /// build_for_non_paged_pool
     pub fn build_for_non_paged_pool(&mut self) {
         use win_kernel_sys::ntoskrnl::MmBuildMdlForNonPagedPool;
@@ -54,44 +171,243 @@ impl MemoryDescriptorList {
         }
     }
 
+    /// Probes the buffer for the given access and locks its pages into memory with
+    /// `MmProbeAndLockPages`, which are unlocked again on drop or with [`Self::unlock_pages`].
+    /// An invalid buffer, e.g. a user buffer that has been freed, is reported as an error rather
+    /// than as an exception.
+    pub fn probe_and_lock(
+        &mut self,
+        access: AccessMode,
+        operation: LockOperation,
+    ) -> Result<(), Error> {
+        if self.locked || unsafe { describes_pages(self.raw) } {
+            return Err(Error::INVALID_PARAMETER);
+        }
+
+        unsafe { probe_and_lock_pages_safe(self.raw, access as _, operation as _) }
+            .into_result()?;
+
+        self.locked = true;
+
+        Ok(())
+    }
+
+    /// Unlocks the pages that were locked with [`Self::probe_and_lock`], if any.
+    pub fn unlock_pages(&mut self) {
+        use win_kernel_sys::ntoskrnl::MmUnlockPages;
+
+        if self.locked {
+            unsafe { MmUnlockPages(self.raw) };
+            self.locked = false;
+        }
+    }
+
+    /// Returns whether the pages have been locked with [`Self::probe_and_lock`].
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Returns the virtual address of the buffer, `MmGetMdlVirtualAddress`.
+    pub fn virtual_address(&self) -> *mut core::ffi::c_void {
+        unsafe { ((*self.raw).StartVa as *mut u8).add(self.byte_offset() as usize) as _ }
+    }
+
+    /// Returns the offset of the buffer into its first page, `MmGetMdlByteOffset`.
+    pub fn byte_offset(&self) -> u32 {
+        unsafe { (*self.raw).ByteOffset }
+    }
+
+    /// Returns the size of the buffer in bytes, `MmGetMdlByteCount`.
+    pub fn byte_count(&self) -> u32 {
+        unsafe { (*self.raw).ByteCount }
+    }
+
+    /// Returns the page frame numbers of the buffer, or [`Error::INVALID_PARAMETER`] if the MDL
+    /// has neither been built for non-paged pool nor locked, as it does not describe any pages
+    /// yet.
+    pub fn page_frame_numbers(&self) -> Result<&[u64], Error> {
+        if !unsafe { describes_pages(self.raw) } {
+            return Err(Error::INVALID_PARAMETER);
+        }
+
+        Ok(unsafe { page_frames(self.raw) })
+    }
+
+    /// Returns an iterator over the physical addresses of the pages of the buffer. The first
+    /// page is only used from [`Self::byte_offset`] on.
+    pub fn physical_addresses(
+        &self,
+    ) -> Result<impl Iterator<Item = PhysicalAddress> + '_, Error> {
+        Ok(self
+            .page_frame_numbers()?
+            .iter()
+            .map(|&pfn| PhysicalAddress::from(pfn * PAGE_SIZE as u64)))
+    }
+
+    /// Builds an MDL for `size` bytes at `offset` into the buffer with `IoBuildPartialMdl`,
+    /// which shares the pages of this MDL and cannot outlive it.
+    pub fn build_partial(&self, offset: usize, size: usize) -> Result<PartialMdl<'_>, Error> {
+        use win_kernel_sys::ntoskrnl::{IoAllocateMdl, IoBuildPartialMdl};
+
+        let end = offset.checked_add(size).ok_or(Error::INVALID_PARAMETER)?;
+
+        if size == 0 || end > self.byte_count() as usize || !unsafe { describes_pages(self.raw) } {
+            return Err(Error::INVALID_PARAMETER);
+        }
+
+        let addr = unsafe { (self.virtual_address() as *mut u8).add(offset) } as _;
+
+        let raw = unsafe {
+            IoAllocateMdl(
+                addr,
+                size as _,
+                false as _,
+                false as _,
+                core::ptr::null_mut(),
+            )
+        };
+
+        if raw.is_null() {
+            return Err(Error::INSUFFICIENT_RESOURCES);
+        }
+
+        unsafe { IoBuildPartialMdl(self.raw, raw, addr, size as _) };
+
+        Ok(PartialMdl {
+            mdl: MemoryDescriptorList { raw, locked: false },
+            source: PhantomData,
+        })
+    }
+
+    /// Maps the pages into system space, unmapped when the mapping is dropped.
+    pub fn map(&mut self, caching: MemoryCaching) -> Result<MdlMapping<'_>, Error> {
+        if !unsafe { describes_pages(self.raw) } {
+            return Err(Error::INVALID_PARAMETER);
+        }
+
+        let ptr = unsafe {
+            map_pages(
+                self.raw,
+                AccessMode::KernelMode,
+                caching,
+                core::ptr::null_mut(),
+                HIGH_PAGE_PRIORITY | MDL_MAPPING_NO_EXECUTE,
+            )
+        }?;
+
+        Ok(MdlMapping { mdl: self, ptr })
+    }
+
     /// map_locked_pages
+    ///
+    /// The MDL must have been built for non-paged pool or its pages locked, otherwise
+    /// [`Error::INVALID_PARAMETER`] is returned.
     pub fn map_locked_pages(
         self,
         access: AccessMode,
         caching: MemoryCaching,
         desired_addr: Option<*mut core::ffi::c_void>,
     ) -> Result<LockedMapping, Error> {
-        use win_kernel_sys::ntoskrnl::MmMapLockedPagesSpecifyCache;
+        if !unsafe { describes_pages(self.raw) } {
+            return Err(Error::INVALID_PARAMETER);
+        }
 
         let ptr = unsafe {
-            MmMapLockedPagesSpecifyCache(
+            map_pages(
                 self.raw,
-                access as _,
-                caching as _,
+                access,
+                caching,
                 desired_addr.unwrap_or(core::ptr::null_mut()),
-                false as _,
                 0,
             )
-        };
+        }?;
 
-        Ok(LockedMapping { raw: self.raw, ptr })
+        Ok(LockedMapping {
+            mdl: self,
+            ptr,
+            access,
+        })
     }
 }
 
 impl Drop for MemoryDescriptorList {
     fn drop(&mut self) {
-        use win_kernel_sys::ntoskrnl::IoFreeMdl;
+        use win_kernel_sys::ntoskrnl::{IoFreeMdl, MmPrepareMdlForReuse};
+
+        self.unlock_pages();
 
         unsafe {
+            // A partial MDL that has been mapped keeps the mapping until it is prepared for reuse.
+            if (*self.raw).MdlFlags & MDL_PARTIAL != 0 {
+                MmPrepareMdlForReuse(self.raw);
+            }
+
             IoFreeMdl(self.raw);
         }
     }
 }
 
-/// LockedMapping
+/// An MDL that describes a part of the buffer of another MDL, see
+/// [`MemoryDescriptorList::build_partial`].
+pub struct PartialMdl<'a> {
+    mdl: MemoryDescriptorList,
+    source: PhantomData<&'a MemoryDescriptorList>,
+}
+
+impl PartialMdl<'_> {
+    /// Maps the pages into system space, unmapped when the mapping is dropped.
+    pub fn map(&mut self, caching: MemoryCaching) -> Result<MdlMapping<'_>, Error> {
+        self.mdl.map(caching)
+    }
+}
+
+impl core::ops::Deref for PartialMdl<'_> {
+    type Target = MemoryDescriptorList;
+
+    fn deref(&self) -> &MemoryDescriptorList {
+        &self.mdl
+    }
+}
+
+/// A system space mapping of a [`MemoryDescriptorList`], unmapped on drop.
+pub struct MdlMapping<'a> {
+    mdl: &'a mut MemoryDescriptorList,
+    ptr: NonNull<u8>,
+}
+
+unsafe impl Send for MdlMapping<'_> {}
+
+unsafe impl Sync for MdlMapping<'_> {}
+
+impl MdlMapping<'_> {
+    /// Returns the virtual address of the mapping, at the byte offset of the buffer.
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.ptr.as_ptr()
+    }
+
+    /// Returns the mapping as a slice.
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.ptr.as_ptr(), self.mdl.byte_count() as _) }
+    }
+
+    /// Returns the mapping as a mutable slice.
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.mdl.byte_count() as _) }
+    }
+}
+
+impl Drop for MdlMapping<'_> {
+    fn drop(&mut self) {
+        unsafe { unmap_pages(self.ptr, self.mdl.raw, AccessMode::KernelMode) }
+    }
+}
+
+/// A mapping that owns its [`MemoryDescriptorList`]. Dropping it unmaps the pages and frees the
+/// MDL, while [`LockedMapping::unlock`] unmaps the pages and hands the MDL back.
 pub struct LockedMapping {
-    raw: *mut win_kernel_sys::base::MDL,
-    ptr: *mut core::ffi::c_void,
+    mdl: MemoryDescriptorList,
+    ptr: NonNull<u8>,
+    access: AccessMode,
 }
 
 unsafe impl Send for LockedMapping {}
@@ -101,28 +417,239 @@ unsafe impl Sync for LockedMapping {}
 impl LockedMapping {
     /// out slef.ptr
     pub fn ptr(&self) -> *mut core::ffi::c_void {
-        self.ptr
+        self.ptr.as_ptr() as _
+    }
+
+    /// Returns the MDL that is mapped.
+    pub fn mdl(&self) -> &MemoryDescriptorList {
+        &self.mdl
+    }
+
+    /// Returns the access mode the pages were mapped for.
+    pub fn access(&self) -> AccessMode {
+        self.access
     }
 
     /// unlock
     pub fn unlock(self) -> MemoryDescriptorList {
-        use win_kernel_sys::ntoskrnl::MmUnmapLockedPages;
+        let this = core::mem::ManuallyDrop::new(self);
 
         unsafe {
-            MmUnmapLockedPages(self.ptr, self.raw);
-        }
+            unmap_pages(this.ptr, this.mdl.raw, this.access);
 
-        MemoryDescriptorList { raw: self.raw }
+            core::ptr::read(&this.mdl)
+        }
     }
 }
 
 impl Drop for LockedMapping {
     fn drop(&mut self) {
-        use win_kernel_sys::ntoskrnl::{IoFreeMdl, MmUnmapLockedPages};
+        // The MDL is freed when the field is dropped.
+        unsafe { unmap_pages(self.ptr, self.mdl.raw, self.access) }
+    }
+}
+
+bitflags! {
+    /// The flags of [`PhysicalPages::allocate`], see `MM_ALLOCATE_*`.
+    pub struct PageAllocationFlags: u32 {
+        /// The pages are not zeroed, which is faster.
+        const DONT_ZERO              = 0x0001;
+        /// Only pages of the NUMA node of the calling thread are used.
+        const LOCAL_NODE_ONLY        = 0x0002;
+        /// Either all of the pages are allocated or none.
+        const FULLY_REQUIRED         = 0x0004;
+        /// Do not wait for pages to become available.
+        const NO_WAIT                = 0x0008;
+        /// Prefer contiguous pages, though they are not guaranteed.
+        const PREFER_CONTIGUOUS      = 0x0010;
+        /// The pages come in contiguous chunks of the size of the skip bytes.
+        const REQUIRE_CONTIGUOUS_CHUNKS = 0x0020;
+    }
+}
+
+/// Where and how the pages of [`PhysicalPages`] are allocated. The default allows any physical
+/// address and cached memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PageAllocationOptions {
+    /// The lowest physical address a page may start at.
+    pub lowest: PhysicalAddress,
+    /// The highest physical address a page may end at.
+    pub highest: PhysicalAddress,
+    /// If not zero, the stride at which the range between `lowest` and `highest` is searched
+    /// again once it is exhausted. A multiple of the page size.
+    pub skip_bytes: u64,
+    /// The caching the pages are allocated for, which is also used to map them.
+    pub caching: MemoryCaching,
+    /// The flags of the allocation.
+    pub flags: PageAllocationFlags,
+}
+
+impl Default for PageAllocationOptions {
+    fn default() -> Self {
+        Self {
+            lowest: PhysicalAddress::from(0),
+            highest: PhysicalAddress::from(u64::MAX),
+            skip_bytes: 0,
+            caching: MemoryCaching::Cached,
+            flags: PageAllocationFlags::empty(),
+        }
+    }
+}
+
+/// Physical pages that are allocated with [`MmAllocatePagesForMdlEx`] and described by an MDL that
+/// the pages are freed from on drop. The pages are not necessarily contiguous, nor mapped. The
+/// allocation may hold fewer bytes than requested, see [`PhysicalPages::is_partial`].
+pub struct PhysicalPages {
+    raw: *mut MDL,
+    requested: usize,
+    caching: MemoryCaching,
+}
+
+unsafe impl Send for PhysicalPages {}
+
+unsafe impl Sync for PhysicalPages {}
+
+impl PhysicalPages {
+    /// Allocates pages for `size` bytes. Unless [`PageAllocationFlags::FULLY_REQUIRED`] is set,
+    /// the system may allocate fewer pages than requested. Fails with
+    /// [`Error::INSUFFICIENT_RESOURCES`] if no page could be allocated at all.
+    pub fn allocate(size: usize, options: &PageAllocationOptions) -> Result<Self, Error> {
+        use win_kernel_sys::ntoskrnl::{ExFreePool, MmAllocatePagesForMdlEx};
+
+        if size == 0 {
+            return Err(Error::INVALID_PARAMETER);
+        }
+
+        let skip_bytes = PhysicalAddress::from(options.skip_bytes);
+
+        let raw = unsafe {
+            MmAllocatePagesForMdlEx(
+                options.lowest.into(),
+                options.highest.into(),
+                skip_bytes.into(),
+                size as _,
+                options.caching as _,
+                options.flags.bits(),
+            )
+        };
+
+        if raw.is_null() {
+            return Err(Error::INSUFFICIENT_RESOURCES);
+        }
+
+        if unsafe { (*raw).ByteCount } == 0 {
+            unsafe { ExFreePool(raw as _) };
+
+            return Err(Error::INSUFFICIENT_RESOURCES);
+        }
+
+        Ok(Self {
+            raw,
+            requested: size,
+            caching: options.caching,
+        })
+    }
+
+    /// Returns the raw MDL.
+    pub fn as_raw(&self) -> *mut MDL {
+        self.raw
+    }
+
+    /// Returns the number of bytes that were allocated.
+    pub fn len(&self) -> usize {
+        unsafe { (*self.raw).ByteCount as usize }
+    }
+
+    /// Returns whether no bytes were allocated, which is never the case.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of bytes that were requested.
+    pub fn requested(&self) -> usize {
+        self.requested
+    }
+
+    /// Returns whether fewer bytes were allocated than requested.
+    pub fn is_partial(&self) -> bool {
+        self.len() < self.requested
+    }
+
+    /// Returns the caching the pages were allocated for.
+    pub fn caching(&self) -> MemoryCaching {
+        self.caching
+    }
+
+    /// Returns the page frame numbers of the pages, in the order they are mapped.
+    pub fn page_frame_numbers(&self) -> &[u64] {
+        unsafe { page_frames(self.raw) }
+    }
+
+    /// Returns an iterator over the physical addresses of the pages.
+    pub fn physical_addresses(&self) -> impl Iterator<Item = PhysicalAddress> + '_ {
+        self.page_frame_numbers()
+            .iter()
+            .map(|&pfn| PhysicalAddress::from(pfn * PAGE_SIZE as u64))
+    }
+
+    /// Maps the pages into system space with the caching they were allocated for. The mapping
+    /// is virtually contiguous and unmapped on drop.
+    pub fn map(&mut self) -> Result<PageMapping<'_>, Error> {
+        let ptr = unsafe {
+            map_pages(
+                self.raw,
+                AccessMode::KernelMode,
+                self.caching,
+                core::ptr::null_mut(),
+                HIGH_PAGE_PRIORITY | MDL_MAPPING_NO_EXECUTE,
+            )
+        }?;
+
+        Ok(PageMapping { pages: self, ptr })
+    }
+}
+
+impl Drop for PhysicalPages {
+    fn drop(&mut self) {
+        use win_kernel_sys::ntoskrnl::{ExFreePool, MmFreePagesFromMdl};
 
+        // The MDL itself is allocated from pool and has to be freed separately.
         unsafe {
-            MmUnmapLockedPages(self.ptr, self.raw);
-            IoFreeMdl(self.raw);
+            MmFreePagesFromMdl(self.raw);
+            ExFreePool(self.raw as _);
         }
     }
 }
+
+/// A system space mapping of [`PhysicalPages`], unmapped on drop.
+pub struct PageMapping<'a> {
+    pages: &'a mut PhysicalPages,
+    ptr: NonNull<u8>,
+}
+
+unsafe impl Send for PageMapping<'_> {}
+
+unsafe impl Sync for PageMapping<'_> {}
+
+impl PageMapping<'_> {
+    /// Returns the virtual address of the mapping.
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.ptr.as_ptr()
+    }
+
+    /// Returns the mapping as a slice.
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.ptr.as_ptr(), self.pages.len()) }
+    }
+
+    /// Returns the mapping as a mutable slice.
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.pages.len()) }
+    }
+}
+
+impl Drop for PageMapping<'_> {
+    fn drop(&mut self) {
+        unsafe { unmap_pages(self.ptr, self.pages.raw, AccessMode::KernelMode) }
+    }
+}
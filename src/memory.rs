@@ -1,11 +1,16 @@
 //! memory mod
 
+use alloc::vec::Vec;
+
 use win_kernel_sys::base::_MEMORY_CACHING_TYPE as MEMORY_CACHING_TYPE;
 use win_kernel_sys::base::{
     MM_COPY_ADDRESS, MM_COPY_MEMORY_PHYSICAL, MM_COPY_MEMORY_VIRTUAL, PHYSICAL_ADDRESS,
+    PHYSICAL_MEMORY_RANGE,
 };
 
+use crate::allocator::try_push;
 use crate::error::{Error, IntoResult};
+use crate::paging::{PhysicalMemorySource, SystemPhysicalMemory};
 
 #[repr(i32)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -145,7 +150,8 @@ pub fn get_physical_for_virtual(addr: u64) -> PhysicalAddress {
 }
 
 
-/// read_memory
+/// read_memory, returns the number of bytes copied, which is less than the size of the buffer if
+/// only part of the memory could be read (`STATUS_PARTIAL_COPY`).
 pub fn read_memory(buffer: &mut [u8], source: CopyAddress) -> Result<usize, Error> {
     use win_kernel_sys::ntoskrnl::MmCopyMemory;
 
@@ -161,7 +167,7 @@ pub fn read_memory(buffer: &mut [u8], source: CopyAddress) -> Result<usize, Erro
             &mut bytes,
         )
     }
-    .into_result()?;
+    .into_result_allow_warnings()?;
 
     Ok(bytes as _)
 }
@@ -193,3 +199,437 @@ pub fn write_memory(target: CopyAddress, buffer: &[u8]) -> Result<usize, Error>
 
     Ok(bytes as _)
 }
+
+/// A range of physical memory, see [`PhysicalMemoryRanges`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PhysicalMemoryRange {
+    /// The first address of the range.
+    pub start: PhysicalAddress,
+    /// The size of the range in bytes.
+    pub size: u64,
+}
+
+impl PhysicalMemoryRange {
+    /// Returns the address right after the range.
+    pub fn end(&self) -> PhysicalAddress {
+        PhysicalAddress(self.start.0 + self.size)
+    }
+
+    /// Returns whether the range contains the address.
+    pub fn contains(&self, addr: PhysicalAddress) -> bool {
+        self.start <= addr && addr < self.end()
+    }
+}
+
+/// An iterator over the ranges of physical memory that are backed by RAM, in ascending order,
+/// backed by `MmGetPhysicalMemoryRanges`. Everything in between, e.g. device memory, is a gap.
+pub struct PhysicalMemoryRanges {
+    ranges: *mut PHYSICAL_MEMORY_RANGE,
+    index: usize,
+}
+
+unsafe impl Send for PhysicalMemoryRanges {}
+
+impl PhysicalMemoryRanges {
+    /// Takes a snapshot of the physical memory ranges. Must be called at `PASSIVE_LEVEL`.
+    pub fn new() -> Result<Self, Error> {
+        use win_kernel_sys::ntoskrnl::MmGetPhysicalMemoryRanges;
+
+        let ranges = unsafe { MmGetPhysicalMemoryRanges() };
+
+        if ranges.is_null() {
+            return Err(Error::INSUFFICIENT_RESOURCES);
+        }
+
+        Ok(Self { ranges, index: 0 })
+    }
+}
+
+impl Iterator for PhysicalMemoryRanges {
+    type Item = PhysicalMemoryRange;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // The array is terminated by a range with neither an address nor a size.
+        let range = unsafe { &*self.ranges.add(self.index) };
+        let start = unsafe { range.BaseAddress.QuadPart } as u64;
+        let size = unsafe { range.NumberOfBytes.QuadPart } as u64;
+
+        if start == 0 && size == 0 {
+            return None;
+        }
+
+        self.index += 1;
+
+        Some(PhysicalMemoryRange {
+            start: PhysicalAddress(start),
+            size,
+        })
+    }
+}
+
+impl Drop for PhysicalMemoryRanges {
+    fn drop(&mut self) {
+        use win_kernel_sys::ntoskrnl::ExFreePool;
+
+        unsafe {
+            ExFreePool(self.ranges as _);
+        }
+    }
+}
+
+/// What [`PhysicalMemoryReader::read`] does when it reaches a gap in physical memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GapPolicy {
+    /// Return a [`ReadChunk::Gap`] for the gap, and continue behind it with the next read.
+    Report,
+    /// Skip the gap, and continue reading at the start of the next range.
+    Skip,
+    /// Fill the gap with zeroes, such that offsets into a dump match physical addresses.
+    Zero,
+}
+
+/// The result of a read of a [`PhysicalMemoryReader`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReadChunk {
+    /// `len` bytes starting at `address` were written to the start of the buffer. With
+    /// [`GapPolicy::Zero`], this includes the zeroes for gaps.
+    Data { address: PhysicalAddress, len: usize },
+    /// The `len` bytes starting at `address` are not backed by RAM or could not be read, and
+    /// nothing was written to the buffer.
+    Gap { address: PhysicalAddress, len: u64 },
+    /// The reader is past the last range of physical memory.
+    End,
+}
+
+/// Reads physical memory from a [`PhysicalMemorySource`], [`SystemPhysicalMemory`] by default,
+/// page by page and only within the ranges that are backed by RAM, see [`PhysicalMemoryRanges`].
+/// Pages that fail to read are treated as gaps too.
+///
+/// ```ignore
+/// let mut reader = PhysicalMemoryReader::new()?;
+/// let mut buffer = vec![0u8; 0x10000];
+///
+/// loop {
+///     match reader.read(&mut buffer)? {
+///         ReadChunk::Data { address, len } => dump.write_at(address.into(), &buffer[..len])?,
+///         ReadChunk::Gap { .. } => {}
+///         ReadChunk::End => break,
+///     }
+/// }
+/// ```
+pub struct PhysicalMemoryReader<M = SystemPhysicalMemory> {
+    memory: M,
+    ranges: Vec<PhysicalMemoryRange>,
+    position: u64,
+    gap_policy: GapPolicy,
+}
+
+impl PhysicalMemoryReader {
+    /// Creates a reader of the physical memory of the system at physical address zero that
+    /// reports gaps. Must be called at `PASSIVE_LEVEL`.
+    pub fn new() -> Result<Self, Error> {
+        let mut ranges = Vec::new();
+
+        for range in PhysicalMemoryRanges::new()? {
+            try_push(&mut ranges, range)?;
+        }
+
+        Ok(Self::with_ranges(SystemPhysicalMemory, ranges))
+    }
+}
+
+impl<M: PhysicalMemorySource> PhysicalMemoryReader<M> {
+    /// The size of the chunks that are read, reads never cross a page.
+    const PAGE_SIZE: u64 = 0x1000;
+
+    /// Creates a reader of `memory` at physical address zero that reports gaps, which only reads
+    /// within the given ranges. The ranges must be sorted and must not overlap.
+    pub fn with_ranges(memory: M, ranges: Vec<PhysicalMemoryRange>) -> Self {
+        Self {
+            memory,
+            ranges,
+            position: 0,
+            gap_policy: GapPolicy::Report,
+        }
+    }
+
+    /// Returns the ranges of physical memory the reader reads from.
+    pub fn ranges(&self) -> &[PhysicalMemoryRange] {
+        &self.ranges
+    }
+
+    /// Sets what to do when a read reaches a gap.
+    pub fn set_gap_policy(&mut self, gap_policy: GapPolicy) {
+        self.gap_policy = gap_policy;
+    }
+
+    /// Returns the physical address the next read starts at.
+    pub fn position(&self) -> PhysicalAddress {
+        PhysicalAddress(self.position)
+    }
+
+    /// Moves the reader to the given physical address.
+    pub fn seek(&mut self, addr: PhysicalAddress) {
+        self.position = addr.0;
+    }
+
+    /// Returns the range that contains the address, or the first range after it.
+    fn range_at(&self, addr: u64) -> Option<&PhysicalMemoryRange> {
+        self.ranges.iter().find(|range| addr < range.end().0)
+    }
+
+    /// Reads from the given physical address into the buffer, up to the end of the range that
+    /// contains the address. Returns a [`ReadChunk::Gap`] if the address is not in a range, or if
+    /// its page cannot be read. Does not apply the gap policy.
+    pub fn read_at(&self, addr: PhysicalAddress, buffer: &mut [u8]) -> Result<ReadChunk, Error> {
+        let range = match self.range_at(addr.0) {
+            Some(range) => range,
+            None => return Ok(ReadChunk::End),
+        };
+
+        if addr < range.start {
+            return Ok(ReadChunk::Gap {
+                address: addr,
+                len: range.start.0 - addr.0,
+            });
+        }
+
+        let available = core::cmp::min(buffer.len() as u64, range.end().0 - addr.0) as usize;
+        let mut read = 0;
+
+        while read < available {
+            let current = addr.0 + read as u64;
+            let page_left = Self::PAGE_SIZE - (current & (Self::PAGE_SIZE - 1));
+            let len = core::cmp::min(page_left, (available - read) as u64) as usize;
+
+            let chunk = &mut buffer[read..read + len];
+
+            match self.memory.read_bytes(PhysicalAddress(current), chunk) {
+                Ok(copied) if copied == len => read += len,
+                // A page that cannot be read ends the data, and is a gap on its own next time.
+                _ if read != 0 => break,
+                _ => {
+                    return Ok(ReadChunk::Gap {
+                        address: addr,
+                        len: page_left,
+                    })
+                }
+            }
+        }
+
+        Ok(ReadChunk::Data {
+            address: addr,
+            len: read,
+        })
+    }
+
+    /// Reads from the current position into the buffer and advances the position, handling gaps
+    /// according to the [`GapPolicy`]. An empty buffer reads nothing.
+    pub fn read(&mut self, buffer: &mut [u8]) -> Result<ReadChunk, Error> {
+        if buffer.is_empty() {
+            return Ok(ReadChunk::Data {
+                address: self.position(),
+                len: 0,
+            });
+        }
+
+        loop {
+            let chunk = self.read_at(self.position(), buffer)?;
+
+            match chunk {
+                ReadChunk::Data { len, .. } => {
+                    self.position += len as u64;
+
+                    return Ok(chunk);
+                }
+                ReadChunk::Gap { address, len } => match self.gap_policy {
+                    GapPolicy::Report => {
+                        self.position += len;
+
+                        return Ok(chunk);
+                    }
+                    GapPolicy::Skip => self.position += len,
+                    GapPolicy::Zero => {
+                        let len = core::cmp::min(len, buffer.len() as u64) as usize;
+
+                        buffer[..len].fill(0);
+                        self.position += len as u64;
+
+                        return Ok(ReadChunk::Data { address, len });
+                    }
+                },
+                ReadChunk::End => return Ok(chunk),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use alloc::vec;
+
+    /// RAM at `0x1000..0x3000` and `0x4000..0x6000`, where the image ends at `0x5000`, such that
+    /// the last page of the second range cannot be read.
+    fn reader(image: &[u8]) -> PhysicalMemoryReader<&[u8]> {
+        let ranges = vec![
+            PhysicalMemoryRange {
+                start: PhysicalAddress(0x1000),
+                size: 0x2000,
+            },
+            PhysicalMemoryRange {
+                start: PhysicalAddress(0x4000),
+                size: 0x2000,
+            },
+        ];
+
+        PhysicalMemoryReader::with_ranges(image, ranges)
+    }
+
+    fn image() -> Vec<u8> {
+        (0..0x5000u32).map(|addr| (addr % 251) as u8).collect()
+    }
+
+    fn data(address: u64, len: usize) -> ReadChunk {
+        ReadChunk::Data {
+            address: PhysicalAddress(address),
+            len,
+        }
+    }
+
+    fn gap(address: u64, len: u64) -> ReadChunk {
+        ReadChunk::Gap {
+            address: PhysicalAddress(address),
+            len,
+        }
+    }
+
+    #[test]
+    fn reads_across_pages() {
+        let image = image();
+        let reader = reader(&image);
+        let mut buffer = vec![0u8; 0x1800];
+
+        assert_eq!(
+            reader.read_at(PhysicalAddress(0x1800), &mut buffer),
+            Ok(data(0x1800, 0x1800))
+        );
+        assert_eq!(buffer, &image[0x1800..0x3000]);
+    }
+
+    #[test]
+    fn stops_at_the_end_of_a_range() {
+        let image = image();
+        let reader = reader(&image);
+        let mut buffer = vec![0u8; 0x1000];
+
+        assert_eq!(
+            reader.read_at(PhysicalAddress(0x2800), &mut buffer),
+            Ok(data(0x2800, 0x800))
+        );
+        assert_eq!(&buffer[..0x800], &image[0x2800..0x3000]);
+    }
+
+    #[test]
+    fn reports_gaps_and_the_end() {
+        let image = image();
+        let reader = reader(&image);
+        let mut buffer = vec![0u8; 0x1000];
+
+        assert_eq!(reader.read_at(PhysicalAddress(0), &mut buffer), Ok(gap(0, 0x1000)));
+        assert_eq!(
+            reader.read_at(PhysicalAddress(0x3800), &mut buffer),
+            Ok(gap(0x3800, 0x800))
+        );
+        assert_eq!(
+            reader.read_at(PhysicalAddress(0x6000), &mut buffer),
+            Ok(ReadChunk::End)
+        );
+    }
+
+    #[test]
+    fn treats_unreadable_pages_as_gaps() {
+        let image = image();
+        let reader = reader(&image);
+        let mut buffer = vec![0u8; 0x1000];
+
+        assert_eq!(
+            reader.read_at(PhysicalAddress(0x4800), &mut buffer),
+            Ok(data(0x4800, 0x800))
+        );
+        assert_eq!(
+            reader.read_at(PhysicalAddress(0x5000), &mut buffer),
+            Ok(gap(0x5000, 0x1000))
+        );
+    }
+
+    #[test]
+    fn reports_gaps_with_report_policy() {
+        let image = image();
+        let mut reader = reader(&image);
+        let mut buffer = vec![0u8; 0x2000];
+
+        assert_eq!(reader.read(&mut buffer), Ok(gap(0, 0x1000)));
+        assert_eq!(reader.read(&mut buffer), Ok(data(0x1000, 0x2000)));
+        assert_eq!(buffer, &image[0x1000..0x3000]);
+        assert_eq!(reader.read(&mut buffer), Ok(gap(0x3000, 0x1000)));
+        assert_eq!(reader.read(&mut buffer), Ok(data(0x4000, 0x1000)));
+        assert_eq!(reader.read(&mut buffer), Ok(gap(0x5000, 0x1000)));
+        assert_eq!(reader.read(&mut buffer), Ok(ReadChunk::End));
+        assert_eq!(reader.position(), PhysicalAddress(0x6000));
+    }
+
+    #[test]
+    fn skips_gaps_with_skip_policy() {
+        let image = image();
+        let mut reader = reader(&image);
+        reader.set_gap_policy(GapPolicy::Skip);
+        let mut buffer = vec![0u8; 0x2000];
+
+        assert_eq!(reader.read(&mut buffer), Ok(data(0x1000, 0x2000)));
+        assert_eq!(reader.read(&mut buffer), Ok(data(0x4000, 0x1000)));
+        assert_eq!(&buffer[..0x1000], &image[0x4000..0x5000]);
+        assert_eq!(reader.read(&mut buffer), Ok(ReadChunk::End));
+    }
+
+    #[test]
+    fn fills_gaps_with_zero_policy() {
+        let image = image();
+        let mut reader = reader(&image);
+        reader.set_gap_policy(GapPolicy::Zero);
+        let mut buffer = vec![0xFFu8; 0x2000];
+        let mut dump = Vec::new();
+
+        loop {
+            match reader.read(&mut buffer).unwrap() {
+                ReadChunk::Data { address, len } => {
+                    assert_eq!(address, PhysicalAddress(dump.len() as u64));
+                    dump.extend_from_slice(&buffer[..len]);
+                }
+                ReadChunk::Gap { .. } => panic!("gaps are filled"),
+                ReadChunk::End => break,
+            }
+        }
+
+        let mut expected = image.clone();
+        expected[..0x1000].fill(0);
+        expected[0x3000..0x4000].fill(0);
+        expected.resize(0x6000, 0);
+
+        assert_eq!(dump, expected);
+    }
+
+    #[test]
+    fn seeks_and_reads_nothing_into_an_empty_buffer() {
+        let image = image();
+        let mut reader = reader(&image);
+
+        reader.seek(PhysicalAddress(0x2000));
+        assert_eq!(reader.read(&mut []), Ok(data(0x2000, 0)));
+
+        let mut buffer = vec![0u8; 0x10];
+        assert_eq!(reader.read(&mut buffer), Ok(data(0x2000, 0x10)));
+        assert_eq!(reader.position(), PhysicalAddress(0x2010));
+    }
+}
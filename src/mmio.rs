@@ -0,0 +1,573 @@
+//! mmio mod
+//!
+//! Typed access to memory-mapped device registers. A register block is declared once with
+//! [`register_block!`](crate::register_block), which gives every register its offset, width and
+//! access, and [`Registers`] is a bounds-checked view of the block over an [`IoMapping`] that only
+//! performs volatile accesses. Bitfields are described by [`Field`]s, whose values are integers,
+//! `bool`s or enums declared with [`field_enum!`](crate::field_enum).
+//!
+//! The view can be created over a plain byte buffer as well, such that declarations and bitfield
+//! logic can be exercised without hardware.
+//!
+//! ```ignore
+//! win_kernel::register_block! {
+//!     /// The controller registers.
+//!     pub struct Controller {
+//!         /// Controller capabilities.
+//!         pub CAP: u64, ReadOnly @ 0x00;
+//!         /// Controller configuration.
+//!         pub CC: u32, ReadWrite @ 0x14;
+//!         /// Controller status.
+//!         pub CSTS: u32, ReadOnly @ 0x1C;
+//!     }
+//! }
+//!
+//! win_kernel::field_enum! {
+//!     pub enum Shutdown: u32 {
+//!         None = 0,
+//!         Normal = 1,
+//!         Abrupt = 2,
+//!     }
+//! }
+//!
+//! const CC_EN: Field<u32, bool> = Field::new(0, 1);
+//! const CC_SHN: Field<u32, Shutdown> = Field::new(14, 2);
+//!
+//! let regs = Registers::<Controller>::new(&mapping)?;
+//!
+//! regs.write_field(Controller::CC, CC_SHN, Shutdown::Normal);
+//! regs.modify(Controller::CC, |cc| CC_EN.set(cc, false));
+//! ```
+
+use core::marker::PhantomData;
+
+use crate::error::Error;
+use crate::memory::IoMapping;
+
+mod private {
+    pub trait Sealed {}
+}
+
+/// The integer types a register can have.
+pub trait RegisterValue: Copy + private::Sealed {
+    /// Widens the value.
+    fn to_bits(self) -> u64;
+    /// Truncates the bits to the width of the register.
+    fn from_bits(bits: u64) -> Self;
+}
+
+macro_rules! impl_register_value {
+    ($($ty:ty),*) => {
+        $(
+            impl private::Sealed for $ty {}
+
+            impl RegisterValue for $ty {
+                fn to_bits(self) -> u64 {
+                    self as u64
+                }
+
+                fn from_bits(bits: u64) -> Self {
+                    bits as $ty
+                }
+            }
+        )*
+    };
+}
+
+impl_register_value!(u8, u16, u32, u64);
+
+/// A register that can be read.
+pub trait Readable {}
+
+/// A register that can be written.
+pub trait Writable {}
+
+/// A register that can only be read.
+pub struct ReadOnly;
+
+/// A register that can only be written, e.g. a doorbell.
+pub struct WriteOnly;
+
+/// A register that can be read and written.
+pub struct ReadWrite;
+
+impl Readable for ReadOnly {}
+
+impl Writable for WriteOnly {}
+
+impl Readable for ReadWrite {}
+
+impl Writable for ReadWrite {}
+
+/// A block of registers, implemented by [`register_block!`](crate::register_block).
+pub trait RegisterBlock {
+    /// The size of the block in bytes, i.e. the end of the register with the highest offset.
+    const SIZE: usize;
+
+    /// The alignment the base of the block needs, i.e. the width of its widest register, such
+    /// that every register is naturally aligned.
+    const ALIGNMENT: usize;
+}
+
+/// A register of the block `B`, with the value type `T` and the access `A`.
+pub struct Register<B, T, A> {
+    offset: usize,
+    marker: PhantomData<fn() -> (B, T, A)>,
+}
+
+impl<B, T, A> Clone for Register<B, T, A> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<B, T, A> Copy for Register<B, T, A> {}
+
+impl<B, T: RegisterValue, A> Register<B, T, A> {
+    /// Declares a register at the given offset, which has to be aligned to its width.
+    pub const fn new(offset: usize) -> Self {
+        assert!(offset % core::mem::size_of::<T>() == 0, "misaligned register");
+
+        Self {
+            offset,
+            marker: PhantomData,
+        }
+    }
+
+    /// Returns the offset of the register into its block.
+    pub const fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+/// The values a [`Field`] can have.
+pub trait FieldValue: Sized {
+    /// Converts the bits of the field, or returns `None` if they are not a valid value.
+    fn from_field_bits(bits: u64) -> Option<Self>;
+    /// Converts the value into the bits of the field.
+    fn into_field_bits(self) -> u64;
+}
+
+macro_rules! impl_field_value {
+    ($($ty:ty),*) => {
+        $(
+            impl FieldValue for $ty {
+                fn from_field_bits(bits: u64) -> Option<Self> {
+                    Some(bits as $ty)
+                }
+
+                fn into_field_bits(self) -> u64 {
+                    self as u64
+                }
+            }
+        )*
+    };
+}
+
+impl_field_value!(u8, u16, u32, u64);
+
+impl FieldValue for bool {
+    fn from_field_bits(bits: u64) -> Option<Self> {
+        Some(bits != 0)
+    }
+
+    fn into_field_bits(self) -> u64 {
+        self as u64
+    }
+}
+
+/// A bitfield of `width` bits starting at bit `shift` of a register of type `T`, whose value is
+/// of type `V`.
+pub struct Field<T, V = T> {
+    shift: u32,
+    width: u32,
+    marker: PhantomData<fn() -> (T, V)>,
+}
+
+impl<T, V> Clone for Field<T, V> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T, V> Copy for Field<T, V> {}
+
+impl<T: RegisterValue, V: FieldValue> Field<T, V> {
+    /// Declares a field, which has to fit into the register.
+    pub const fn new(shift: u32, width: u32) -> Self {
+        assert!(width != 0, "empty field");
+        assert!(
+            shift + width <= core::mem::size_of::<T>() as u32 * 8,
+            "field exceeds the register"
+        );
+
+        Self {
+            shift,
+            width,
+            marker: PhantomData,
+        }
+    }
+
+    /// Returns the mask of the field within the register.
+    pub fn mask(&self) -> u64 {
+        (u64::MAX >> (64 - self.width)) << self.shift
+    }
+
+    /// Returns the raw bits of the field in the register value.
+    pub fn bits(&self, value: T) -> u64 {
+        (value.to_bits() & self.mask()) >> self.shift
+    }
+
+    /// Returns the value of the field in the register value, or `None` if the bits are not a
+    /// valid value, e.g. a reserved encoding of an enum.
+    pub fn get(&self, value: T) -> Option<V> {
+        V::from_field_bits(self.bits(value))
+    }
+
+    /// Returns the register value with the field replaced. Bits of the field value that do not
+    /// fit into the field are dropped.
+    pub fn set(&self, value: T, field: V) -> T {
+        let bits = (field.into_field_bits() << self.shift) & self.mask();
+
+        T::from_bits((value.to_bits() & !self.mask()) | bits)
+    }
+}
+
+/// A bounds-checked view of the register block `B` over mapped device memory, or over a plain
+/// buffer. Every access is volatile and has the width of the register.
+pub struct Registers<'a, B> {
+    base: *mut u8,
+    marker: PhantomData<(&'a mut [u8], B)>,
+}
+
+unsafe impl<B> Send for Registers<'_, B> {}
+
+impl<'a, B: RegisterBlock> Registers<'a, B> {
+    /// Creates a view over the mapping, which has to hold the whole block.
+    pub fn new(mapping: &'a IoMapping) -> Result<Self, Error> {
+        unsafe { Self::from_raw(mapping.ptr() as _, mapping.size()) }
+    }
+
+    /// Creates a view over a buffer, e.g. to exercise register logic without hardware.
+    pub fn from_slice(buffer: &'a mut [u8]) -> Result<Self, Error> {
+        unsafe { Self::from_raw(buffer.as_mut_ptr(), buffer.len()) }
+    }
+
+    /// Creates a view over `len` bytes at `base`. Fails with [`Error::BUFFER_OVERFLOW`] if the
+    /// block does not fit, and with [`Error::DATATYPE_MISALIGNMENT`] if `base` is not aligned to
+    /// [`RegisterBlock::ALIGNMENT`].
+    ///
+    /// # Safety
+    ///
+    /// `base` has to be valid for volatile reads and writes of `len` bytes for `'a`.
+    pub unsafe fn from_raw(base: *mut u8, len: usize) -> Result<Self, Error> {
+        if len < B::SIZE {
+            return Err(Error::BUFFER_OVERFLOW);
+        }
+
+        if base as usize % B::ALIGNMENT != 0 {
+            return Err(Error::DATATYPE_MISALIGNMENT);
+        }
+
+        Ok(Self {
+            base,
+            marker: PhantomData,
+        })
+    }
+
+    /// Returns the address of the register, which is within the block.
+    fn ptr<T: RegisterValue, A>(&self, register: Register<B, T, A>) -> *mut T {
+        unsafe { self.base.add(register.offset) as *mut T }
+    }
+
+    /// Reads the register.
+    pub fn read<T: RegisterValue, A: Readable>(&self, register: Register<B, T, A>) -> T {
+        unsafe { core::ptr::read_volatile(self.ptr(register)) }
+    }
+
+    /// Writes the register.
+    pub fn write<T: RegisterValue, A: Writable>(&self, register: Register<B, T, A>, value: T) {
+        unsafe { core::ptr::write_volatile(self.ptr(register), value) }
+    }
+
+    /// Reads the register, passes the value to `f` and writes back the result. This is not
+    /// atomic with respect to the device or other processors.
+    pub fn modify<T, A, F>(&self, register: Register<B, T, A>, f: F)
+    where
+        T: RegisterValue,
+        A: Readable + Writable,
+        F: FnOnce(T) -> T,
+    {
+        self.write(register, f(self.read(register)));
+    }
+
+    /// Reads a field of the register, see [`Field::get`].
+    pub fn read_field<T, A, V>(&self, register: Register<B, T, A>, field: Field<T, V>) -> Option<V>
+    where
+        T: RegisterValue,
+        A: Readable,
+        V: FieldValue,
+    {
+        field.get(self.read(register))
+    }
+
+    /// Replaces a field of the register with a read-modify-write, leaving the other bits as they
+    /// were read.
+    pub fn write_field<T, A, V>(&self, register: Register<B, T, A>, field: Field<T, V>, value: V)
+    where
+        T: RegisterValue,
+        A: Readable + Writable,
+        V: FieldValue,
+    {
+        self.modify(register, |current| field.set(current, value));
+    }
+}
+
+/// Declares a register block, i.e. a unit struct that implements
+/// [`RegisterBlock`](crate::mmio::RegisterBlock) and has an associated constant for every
+/// register. Each register is written as `NAME: type, access @ offset;`, where the type is one
+/// of `u8`, `u16`, `u32` and `u64`, and the access is one of `ReadOnly`, `WriteOnly` and
+/// `ReadWrite`.
+#[macro_export]
+macro_rules! register_block {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $(
+                $(#[$register_meta:meta])*
+                $register_vis:vis $register:ident : $ty:ty, $access:ident @ $offset:expr;
+            )*
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $name;
+
+        impl $name {
+            $(
+                $(#[$register_meta])*
+                $register_vis const $register: $crate::mmio::Register<
+                    $name,
+                    $ty,
+                    $crate::mmio::$access,
+                > = $crate::mmio::Register::new($offset);
+            )*
+        }
+
+        impl $crate::mmio::RegisterBlock for $name {
+            const SIZE: usize = {
+                #[allow(unused_mut)]
+                let mut size = 0;
+
+                $(
+                    let end = $offset + ::core::mem::size_of::<$ty>();
+
+                    if end > size {
+                        size = end;
+                    }
+                )*
+
+                size
+            };
+
+            const ALIGNMENT: usize = {
+                #[allow(unused_mut)]
+                let mut alignment = 1;
+
+                $(
+                    if ::core::mem::size_of::<$ty>() > alignment {
+                        alignment = ::core::mem::size_of::<$ty>();
+                    }
+                )*
+
+                alignment
+            };
+        }
+    };
+}
+
+/// Declares an enum that is the value of a [`Field`](crate::mmio::Field). Bits that match none
+/// of the variants read as `None`.
+#[macro_export]
+macro_rules! field_enum {
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $name:ident : $repr:ty {
+            $(
+                $(#[$variant_meta:meta])*
+                $variant:ident = $value:expr
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        $vis enum $name {
+            $(
+                $(#[$variant_meta])*
+                $variant,
+            )*
+        }
+
+        impl $crate::mmio::FieldValue for $name {
+            fn from_field_bits(bits: u64) -> ::core::option::Option<Self> {
+                $(
+                    if bits == ($value as $repr) as u64 {
+                        return ::core::option::Option::Some($name::$variant);
+                    }
+                )*
+
+                ::core::option::Option::None
+            }
+
+            fn into_field_bits(self) -> u64 {
+                match self {
+                    $($name::$variant => ($value as $repr) as u64,)*
+                }
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    crate::register_block! {
+        struct Wide {
+            STATUS: u8, ReadOnly @ 0x00;
+            CONTROL: u32, ReadWrite @ 0x04;
+            DOORBELL: u16, WriteOnly @ 0x08;
+            BASE: u64, ReadWrite @ 0x10;
+        }
+    }
+
+    crate::register_block! {
+        struct Narrow {
+            DATA: u8, ReadWrite @ 0x00;
+            CONTROL: u16, ReadWrite @ 0x02;
+        }
+    }
+
+    crate::field_enum! {
+        enum Mode: u32 {
+            Off = 0,
+            On = 1,
+            Auto = 3,
+        }
+    }
+
+    /// A buffer that is aligned for every register width.
+    #[repr(C, align(8))]
+    struct Buffer([u8; 0x20]);
+
+    #[test]
+    fn block_size_and_alignment() {
+        assert_eq!(Wide::SIZE, 0x18);
+        assert_eq!(Wide::ALIGNMENT, 8);
+        assert_eq!(Narrow::SIZE, 4);
+        assert_eq!(Narrow::ALIGNMENT, 2);
+
+        assert_eq!(Wide::CONTROL.offset(), 4);
+        assert_eq!(Wide::BASE.offset(), 0x10);
+    }
+
+    #[test]
+    fn field_masks() {
+        assert_eq!(Field::<u8, bool>::new(0, 1).mask(), 0x01);
+        assert_eq!(Field::<u8, bool>::new(7, 1).mask(), 0x80);
+        assert_eq!(Field::<u32>::new(4, 4).mask(), 0xF0);
+        assert_eq!(Field::<u32>::new(0, 32).mask(), 0xFFFF_FFFF);
+        assert_eq!(Field::<u64>::new(0, 64).mask(), u64::MAX);
+        assert_eq!(Field::<u64>::new(63, 1).mask(), 1 << 63);
+    }
+
+    #[test]
+    fn single_bit_fields() {
+        let enable = Field::<u32, bool>::new(31, 1);
+
+        assert_eq!(enable.get(0x8000_0000), Some(true));
+        assert_eq!(enable.get(0x7FFF_FFFF), Some(false));
+        assert_eq!(enable.set(0x1234, true), 0x8000_1234);
+        assert_eq!(enable.set(0xFFFF_FFFF, false), 0x7FFF_FFFF);
+    }
+
+    #[test]
+    fn full_width_fields() {
+        let all = Field::<u64>::new(0, 64);
+
+        assert_eq!(all.get(u64::MAX), Some(u64::MAX));
+        assert_eq!(all.set(0, 0x0123_4567_89AB_CDEF), 0x0123_4567_89AB_CDEF);
+    }
+
+    #[test]
+    fn set_drops_bits_outside_the_field() {
+        let nibble = Field::<u16>::new(4, 4);
+
+        assert_eq!(nibble.set(0xF00F, 0xFF), 0xF0FF);
+        assert_eq!(nibble.set(0xFFFF, 0x10), 0xFF0F);
+        assert_eq!(nibble.bits(0xABCD), 0xC);
+    }
+
+    #[test]
+    fn enum_fields() {
+        let mode = Field::<u32, Mode>::new(8, 2);
+
+        for value in [Mode::Off, Mode::On, Mode::Auto] {
+            assert_eq!(mode.get(mode.set(0xFFFF_FFFF, value)), Some(value));
+        }
+
+        assert_eq!(mode.set(0, Mode::Auto), 0x300);
+
+        // The encoding 2 is reserved.
+        assert_eq!(mode.get(0x200), None);
+    }
+
+    #[test]
+    fn view_checks_bounds_and_alignment() {
+        let mut buffer = Buffer([0; 0x20]);
+
+        assert!(Registers::<Wide>::from_slice(&mut buffer.0[..0x18]).is_ok());
+        assert_eq!(
+            Registers::<Wide>::from_slice(&mut buffer.0[..0x17]).err(),
+            Some(Error::BUFFER_OVERFLOW)
+        );
+        assert_eq!(
+            Registers::<Wide>::from_slice(&mut buffer.0[4..]).err(),
+            Some(Error::DATATYPE_MISALIGNMENT)
+        );
+
+        // Blocks without 64-bit registers only need the alignment of their widest register.
+        assert!(Registers::<Narrow>::from_slice(&mut buffer.0[2..]).is_ok());
+        assert_eq!(
+            Registers::<Narrow>::from_slice(&mut buffer.0[1..]).err(),
+            Some(Error::DATATYPE_MISALIGNMENT)
+        );
+    }
+
+    #[test]
+    fn reads_and_writes_registers() {
+        let mut buffer = Buffer([0; 0x20]);
+        buffer.0[0] = 0x5A;
+
+        {
+            let regs = Registers::<Wide>::from_slice(&mut buffer.0).unwrap();
+
+            assert_eq!(regs.read(Wide::STATUS), 0x5A);
+
+            regs.write(Wide::CONTROL, 0x1122_3344);
+            regs.write(Wide::DOORBELL, 0xBEEF);
+            regs.write(Wide::BASE, u64::MAX);
+            regs.write_field(Wide::CONTROL, Field::<u32, Mode>::new(0, 2), Mode::Auto);
+            regs.modify(Wide::BASE, |base| base & !0xFFF);
+
+            assert_eq!(regs.read(Wide::CONTROL), 0x1122_3347);
+            assert_eq!(
+                regs.read_field(Wide::CONTROL, Field::<u32, Mode>::new(0, 2)),
+                Some(Mode::Auto)
+            );
+        }
+
+        assert_eq!(buffer.0[4..8], 0x1122_3347u32.to_ne_bytes());
+        assert_eq!(buffer.0[8..10], 0xBEEFu16.to_ne_bytes());
+        assert_eq!(buffer.0[0x10..0x18], (!0xFFFu64).to_ne_bytes());
+    }
+}
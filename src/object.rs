@@ -0,0 +1,215 @@
+//! object mod
+//!
+//! Enumeration of object manager directories such as `\Device`, `\Driver` or
+//! `\BaseNamedObjects`, on top of [`ZwOpenDirectoryObject`] and [`ZwQueryDirectoryObject`].
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use win_kernel_sys::base::{
+    HANDLE, OBJECT_ATTRIBUTES, STATUS_BUFFER_TOO_SMALL, STATUS_MORE_ENTRIES,
+    STATUS_NO_MORE_ENTRIES,
+};
+use win_kernel_sys::ntoskrnl::ZwClose;
+
+use crate::error::{Error, IntoResult};
+use crate::headers::{
+    ZwOpenDirectoryObject, ZwQueryDirectoryObject, DIRECTORY_QUERY, DIRECTORY_TRAVERSE,
+    OBJECT_DIRECTORY_INFORMATION,
+};
+use crate::section::ObjectFlags;
+use crate::string::{OwnedUnicodeString, UnicodeStr};
+
+/// An entry of an object manager directory.
+#[derive(Debug)]
+pub struct ObjectEntry {
+    /// The name of the object, relative to the directory.
+    pub name: OwnedUnicodeString,
+    /// The name of the type of the object, e.g. `Device`, `Driver` or `SymbolicLink`.
+    pub type_name: OwnedUnicodeString,
+}
+
+impl ObjectEntry {
+    /// Returns whether the object is a directory itself.
+    pub fn is_directory(&self) -> bool {
+        self.type_name
+            .as_slice()
+            .iter()
+            .copied()
+            .eq("Directory".encode_utf16())
+    }
+}
+
+/// An open object manager directory, closed on drop.
+pub struct ObjectDirectory {
+    handle: HANDLE,
+}
+
+unsafe impl Send for ObjectDirectory {}
+
+unsafe impl Sync for ObjectDirectory {}
+
+impl ObjectDirectory {
+    /// Opens the object manager directory at the given path, e.g. `\Device`.
+    pub fn open(path: &str) -> Result<Self, Error> {
+        let path = OwnedUnicodeString::try_from(path)?;
+        let mut path_ptr = path.as_unicode_str();
+
+        let mut attrs = OBJECT_ATTRIBUTES {
+            Length: core::mem::size_of::<OBJECT_ATTRIBUTES>() as u32,
+            RootDirectory: core::ptr::null_mut(),
+            ObjectName: path_ptr.as_mut_ptr(),
+            Attributes: (ObjectFlags::CASE_INSENSITIVE | ObjectFlags::KERNEL_HANDLE).bits(),
+            SecurityDescriptor: core::ptr::null_mut(),
+            SecurityQualityOfService: core::ptr::null_mut(),
+        };
+
+        let mut handle: HANDLE = core::ptr::null_mut();
+
+        unsafe {
+            ZwOpenDirectoryObject(&mut handle, DIRECTORY_QUERY | DIRECTORY_TRAVERSE, &mut attrs)
+        }
+        .into_result()?;
+
+        Ok(Self { handle })
+    }
+
+    /// Returns the handle of the directory.
+    pub fn handle(&self) -> HANDLE {
+        self.handle
+    }
+
+    /// Returns an iterator over the entries of the directory.
+    pub fn entries(&self) -> ObjectEntries<'_> {
+        ObjectEntries {
+            directory: self,
+            buffer: vec![0u64; ObjectEntries::BUFFER_SIZE / 8],
+            entries: Vec::new(),
+            context: 0,
+            restart: true,
+            done: false,
+        }
+    }
+}
+
+impl Drop for ObjectDirectory {
+    fn drop(&mut self) {
+        unsafe {
+            ZwClose(self.handle);
+        }
+    }
+}
+
+/// An iterator over the entries of an [`ObjectDirectory`], backed by [`ZwQueryDirectoryObject`].
+pub struct ObjectEntries<'a> {
+    directory: &'a ObjectDirectory,
+    buffer: Vec<u64>,
+    entries: Vec<ObjectEntry>,
+    context: u32,
+    restart: bool,
+    done: bool,
+}
+
+impl ObjectEntries<'_> {
+    /// The default size of the query buffer in bytes.
+    const BUFFER_SIZE: usize = 4096;
+
+    /// The largest query buffer in bytes, a single entry never comes close to this.
+    const MAX_BUFFER_SIZE: usize = 0x10000;
+
+    /// Fetches the next batch of entries, returns `false` once the directory is exhausted. The
+    /// context is only advanced by the system on success, so a query that did not fit even a
+    /// single entry is retried with a larger buffer.
+    fn fill(&mut self) -> Result<bool, Error> {
+        loop {
+            let mut len = 0;
+
+            let status = unsafe {
+                ZwQueryDirectoryObject(
+                    self.directory.handle,
+                    self.buffer.as_mut_ptr() as _,
+                    (self.buffer.len() * 8) as _,
+                    false as _,
+                    self.restart as _,
+                    &mut self.context,
+                    &mut len,
+                )
+            };
+
+            match status {
+                STATUS_NO_MORE_ENTRIES => return Ok(false),
+                STATUS_BUFFER_TOO_SMALL if self.buffer.len() * 8 < Self::MAX_BUFFER_SIZE => {
+                    let size = core::cmp::max(len as usize, self.buffer.len() * 16);
+                    self.buffer.resize((size + 7) / 8, 0);
+                    continue;
+                }
+                STATUS_MORE_ENTRIES => {}
+                status => status.into_result()?,
+            }
+
+            self.restart = false;
+
+            // The buffer holds an array of entries terminated by an empty one, followed by the
+            // strings the entries point to.
+            let count =
+                self.buffer.len() * 8 / core::mem::size_of::<OBJECT_DIRECTORY_INFORMATION>();
+            let infos = self.buffer.as_ptr() as *const OBJECT_DIRECTORY_INFORMATION;
+
+            for i in 0..count {
+                let info = unsafe { &*infos.add(i) };
+
+                if info.Name.Buffer.is_null() {
+                    break;
+                }
+
+                self.entries.push(ObjectEntry {
+                    name: unsafe { UnicodeStr::from_raw(&info.Name) }.to_unicode_string(),
+                    type_name: unsafe { UnicodeStr::from_raw(&info.TypeName) }.to_unicode_string(),
+                });
+            }
+
+            // Entries are popped from the back.
+            self.entries.reverse();
+
+            // Everything that was left fit into the buffer.
+            if status != STATUS_MORE_ENTRIES {
+                self.done = true;
+            }
+
+            return Ok(true);
+        }
+    }
+}
+
+impl Iterator for ObjectEntries<'_> {
+    type Item = Result<ObjectEntry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.entries.pop() {
+                return Some(Ok(entry));
+            }
+
+            if self.done {
+                return None;
+            }
+
+            match self.fill() {
+                Ok(true) => continue,
+                Ok(false) => {
+                    self.done = true;
+                    return None;
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// Returns the entries of the object manager directory at the given path.
+pub fn read_directory(path: &str) -> Result<Vec<ObjectEntry>, Error> {
+    ObjectDirectory::open(path)?.entries().collect()
+}
@@ -0,0 +1,523 @@
+//! paging mod
+//!
+//! A walker for x86-64 page tables with 4 or 5 levels, which translates virtual addresses of any
+//! address space given its directory table base, i.e. its CR3. The page tables are read through a
+//! [`PhysicalMemorySource`], which is [`SystemPhysicalMemory`] in the kernel, and can be a
+//! synthetic page table image elsewhere.
+//!
+//! ```ignore
+//! let walker = PageTableWalker::new(SystemPhysicalMemory, PagingMode::current());
+//!
+//! if let Some(translation) = walker.translate(read_cr3(), address)? {
+//!     println!("{:#x} -> {:?}", address, translation.physical_address);
+//! }
+//! ```
+
+use bitflags::bitflags;
+
+use crate::error::Error;
+use crate::intrin::read_cr4;
+use crate::memory::{read_memory, CopyAddress, PhysicalAddress};
+
+/// The bits of an entry that hold the physical address of a table or a 4 KiB page.
+const ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+/// `CR4.LA57`, set when 5-level paging is enabled.
+const CR4_LA57: u64 = 1 << 12;
+
+/// Physical memory that page tables can be read from.
+pub trait PhysicalMemorySource {
+    /// Reads the 8-byte aligned page table entry at the physical address.
+    fn read_u64(&self, addr: PhysicalAddress) -> Result<u64, Error>;
+
+    /// Reads the physical memory at the address into the buffer, and returns the number of bytes
+    /// read, which is less than the size of the buffer if only part of it could be read. By
+    /// default, every byte is read through [`PhysicalMemorySource::read_u64`].
+    fn read_bytes(&self, addr: PhysicalAddress, buffer: &mut [u8]) -> Result<usize, Error> {
+        let start: u64 = addr.into();
+
+        for (i, byte) in buffer.iter_mut().enumerate() {
+            let current = start + i as u64;
+            let entry = self.read_u64(PhysicalAddress::from(current & !7))?;
+
+            *byte = entry.to_le_bytes()[(current & 7) as usize];
+        }
+
+        Ok(buffer.len())
+    }
+}
+
+/// The physical memory of the system, read with [`read_memory`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemPhysicalMemory;
+
+impl PhysicalMemorySource for SystemPhysicalMemory {
+    fn read_u64(&self, addr: PhysicalAddress) -> Result<u64, Error> {
+        let mut bytes = [0u8; 8];
+
+        if read_memory(&mut bytes, CopyAddress::Physical(addr))? != bytes.len() {
+            return Err(Error::IN_PAGE_ERROR);
+        }
+
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn read_bytes(&self, addr: PhysicalAddress, buffer: &mut [u8]) -> Result<usize, Error> {
+        read_memory(buffer, CopyAddress::Physical(addr))
+    }
+}
+
+/// An image of physical memory that starts at physical address zero, e.g. a synthetic set of
+/// page tables or a dump.
+impl PhysicalMemorySource for [u8] {
+    fn read_u64(&self, addr: PhysicalAddress) -> Result<u64, Error> {
+        let start: u64 = addr.into();
+        let start = start as usize;
+        let bytes = start
+            .checked_add(8)
+            .and_then(|end| self.get(start..end))
+            .ok_or(Error::ACCESS_VIOLATION)?;
+
+        let mut entry = [0u8; 8];
+        entry.copy_from_slice(bytes);
+
+        Ok(u64::from_le_bytes(entry))
+    }
+
+    fn read_bytes(&self, addr: PhysicalAddress, buffer: &mut [u8]) -> Result<usize, Error> {
+        let start: u64 = addr.into();
+        let bytes = usize::try_from(start)
+            .ok()
+            .and_then(|start| self.get(start..))
+            .filter(|bytes| !bytes.is_empty())
+            .ok_or(Error::ACCESS_VIOLATION)?;
+
+        let len = core::cmp::min(bytes.len(), buffer.len());
+        buffer[..len].copy_from_slice(&bytes[..len]);
+
+        Ok(len)
+    }
+}
+
+impl<M: PhysicalMemorySource + ?Sized> PhysicalMemorySource for &M {
+    fn read_u64(&self, addr: PhysicalAddress) -> Result<u64, Error> {
+        (**self).read_u64(addr)
+    }
+
+    fn read_bytes(&self, addr: PhysicalAddress, buffer: &mut [u8]) -> Result<usize, Error> {
+        (**self).read_bytes(addr, buffer)
+    }
+}
+
+/// The number of levels of the page tables.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PagingMode {
+    /// PML4, PDPT, PD and PT, with 48-bit virtual addresses.
+    FourLevel,
+    /// PML5 on top of the four levels, with 57-bit virtual addresses.
+    FiveLevel,
+}
+
+impl PagingMode {
+    /// Returns the paging mode of the processor.
+    pub fn current() -> Self {
+        if read_cr4() & CR4_LA57 != 0 {
+            PagingMode::FiveLevel
+        } else {
+            PagingMode::FourLevel
+        }
+    }
+
+    /// Returns the number of levels.
+    pub fn levels(&self) -> u32 {
+        match self {
+            PagingMode::FourLevel => 4,
+            PagingMode::FiveLevel => 5,
+        }
+    }
+
+    /// Returns the number of significant bits of a virtual address.
+    pub fn address_bits(&self) -> u32 {
+        12 + 9 * self.levels()
+    }
+
+    /// Returns whether the upper bits of the virtual address are a sign extension of the highest
+    /// significant bit, which every valid virtual address satisfies.
+    pub fn is_canonical(&self, addr: u64) -> bool {
+        let shift = 64 - self.address_bits();
+
+        (((addr << shift) as i64) >> shift) as u64 == addr
+    }
+}
+
+bitflags! {
+    /// The flags of a page table entry.
+    pub struct PageFlags: u64 {
+        /// The entry maps a table or a page.
+        const PRESENT       = 1 << 0;
+        /// Writes are allowed.
+        const WRITABLE      = 1 << 1;
+        /// User mode accesses are allowed.
+        const USER          = 1 << 2;
+        /// Write-through caching.
+        const WRITE_THROUGH = 1 << 3;
+        /// Caching is disabled.
+        const CACHE_DISABLE = 1 << 4;
+        /// The entry has been used for a translation.
+        const ACCESSED      = 1 << 5;
+        /// The page has been written to, only in entries that map a page.
+        const DIRTY         = 1 << 6;
+        /// The entry maps a large page rather than a table, in a PDPT or PD. In a PT, the same
+        /// bit is the PAT bit of the page.
+        const LARGE_PAGE    = 1 << 7;
+        /// The translation is not flushed on address space switches.
+        const GLOBAL        = 1 << 8;
+        /// Instruction fetches are not allowed.
+        const NO_EXECUTE    = 1 << 63;
+    }
+}
+
+/// The size of a mapped page.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PageSize {
+    /// A 4 KiB page, mapped by a PT.
+    Size4K,
+    /// A 2 MiB page, mapped by a PD.
+    Size2M,
+    /// A 1 GiB page, mapped by a PDPT.
+    Size1G,
+}
+
+impl PageSize {
+    /// Returns the size in bytes.
+    pub fn bytes(&self) -> u64 {
+        match self {
+            PageSize::Size4K => 0x1000,
+            PageSize::Size2M => 0x20_0000,
+            PageSize::Size1G => 0x4000_0000,
+        }
+    }
+}
+
+/// The translation of a virtual address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Translation {
+    /// The physical address the virtual address maps to.
+    pub physical_address: PhysicalAddress,
+    /// The physical address of the page.
+    pub page: PhysicalAddress,
+    /// The size of the page.
+    pub page_size: PageSize,
+    /// The flags of the entry that maps the page. The PAT bit of a 4 KiB page shares its bit with
+    /// [`PageFlags::LARGE_PAGE`], which is therefore left out for 4 KiB pages.
+    pub flags: PageFlags,
+    /// The access the page is mapped with across all the levels: [`PageFlags::WRITABLE`] and
+    /// [`PageFlags::USER`] only if every level allows it, and [`PageFlags::NO_EXECUTE`] if any
+    /// level sets it.
+    pub effective: PageFlags,
+}
+
+/// Translates virtual addresses by walking the page tables in a [`PhysicalMemorySource`].
+pub struct PageTableWalker<M> {
+    memory: M,
+    mode: PagingMode,
+}
+
+impl<M: PhysicalMemorySource> PageTableWalker<M> {
+    /// Creates a walker that reads page tables of the given mode from `memory`.
+    pub fn new(memory: M, mode: PagingMode) -> Self {
+        Self { memory, mode }
+    }
+
+    /// Returns the paging mode of the walker.
+    pub fn mode(&self) -> PagingMode {
+        self.mode
+    }
+
+    /// Translates the virtual address in the address space with the given directory table base,
+    /// e.g. the CR3 of a process. Returns `None` if the address is not mapped, and fails with
+    /// [`Error::INVALID_PARAMETER`] if it is not canonical, or with the error of the memory
+    /// source if a page table cannot be read.
+    ///
+    /// Pages that are not present, e.g. paged out or in transition, count as not mapped.
+    pub fn translate(
+        &self,
+        directory_table_base: u64,
+        virtual_address: u64,
+    ) -> Result<Option<Translation>, Error> {
+        if !self.mode.is_canonical(virtual_address) {
+            return Err(Error::INVALID_PARAMETER);
+        }
+
+        let mut table = directory_table_base & ADDRESS_MASK;
+        let mut effective = PageFlags::WRITABLE | PageFlags::USER;
+
+        for level in (1..=self.mode.levels()).rev() {
+            let shift = 12 + 9 * (level - 1);
+            let index = (virtual_address >> shift) & 0x1FF;
+
+            let entry = self
+                .memory
+                .read_u64(PhysicalAddress::from(table + index * 8))?;
+            let flags = PageFlags::from_bits_truncate(entry);
+
+            if !flags.contains(PageFlags::PRESENT) {
+                return Ok(None);
+            }
+
+            effective &= flags | PageFlags::NO_EXECUTE;
+            effective |= flags & PageFlags::NO_EXECUTE;
+
+            let page_size = match level {
+                3 if flags.contains(PageFlags::LARGE_PAGE) => Some(PageSize::Size1G),
+                2 if flags.contains(PageFlags::LARGE_PAGE) => Some(PageSize::Size2M),
+                1 => Some(PageSize::Size4K),
+                _ => None,
+            };
+
+            match page_size {
+                Some(page_size) => {
+                    let offset_mask = page_size.bytes() - 1;
+
+                    // The low bits of a large page address hold the PAT bit and reserved bits.
+                    let page = entry & ADDRESS_MASK & !offset_mask;
+
+                    let flags = match page_size {
+                        PageSize::Size4K => flags - PageFlags::LARGE_PAGE,
+                        _ => flags,
+                    };
+
+                    return Ok(Some(Translation {
+                        physical_address: PhysicalAddress::from(
+                            page | (virtual_address & offset_mask),
+                        ),
+                        page: PhysicalAddress::from(page),
+                        page_size,
+                        flags,
+                        effective,
+                    }));
+                }
+                None => table = entry & ADDRESS_MASK,
+            }
+        }
+
+        unreachable!("the last level always maps a page")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    const PML5: u64 = 0x5000;
+    const PML4: u64 = 0x1000;
+    const PDPT: u64 = 0x2000;
+    const PD: u64 = 0x3000;
+    const PT: u64 = 0x4000;
+
+    const TABLE: u64 =
+        PageFlags::PRESENT.bits() | PageFlags::WRITABLE.bits() | PageFlags::USER.bits();
+
+    /// Builds the virtual address with the given table indices, from the PML4 down to the PT.
+    fn address(pml4: u64, pdpt: u64, pd: u64, pt: u64, offset: u64) -> u64 {
+        (pml4 << 39) | (pdpt << 30) | (pd << 21) | (pt << 12) | offset
+    }
+
+    fn set(image: &mut [u8], table: u64, index: u64, entry: u64) {
+        let start = (table + index * 8) as usize;
+
+        image[start..start + 8].copy_from_slice(&entry.to_le_bytes());
+    }
+
+    /// Builds an image with a 4 KiB page, a 2 MiB page and a 1 GiB page, all mapped through the
+    /// first entry of the PML4, which the PML5 points to as well.
+    fn image() -> Vec<u8> {
+        let mut image = vec![0u8; 0x10000];
+
+        set(&mut image, PML5, 0x100, PML4 | TABLE);
+        set(&mut image, PML4, 1, PDPT | TABLE);
+        set(&mut image, PDPT, 2, PD | TABLE);
+        set(&mut image, PD, 3, PT | TABLE);
+
+        // A 4 KiB page with the PAT bit set.
+        set(
+            &mut image,
+            PT,
+            4,
+            0x7000_0000 | TABLE | PageFlags::LARGE_PAGE.bits(),
+        );
+
+        // A 2 MiB page with the PAT bit of large pages, bit 12, set.
+        let large = PageFlags::LARGE_PAGE.bits();
+        set(&mut image, PD, 5, 0x4020_0000 | (1 << 12) | TABLE | large);
+
+        // A 1 GiB page.
+        set(&mut image, PDPT, 6, 0x8000_0000 | TABLE | large);
+
+        image
+    }
+
+    #[test]
+    fn translates_4k_page() {
+        let image = image();
+        let walker = PageTableWalker::new(&image[..], PagingMode::FourLevel);
+
+        let translation = walker
+            .translate(PML4, address(1, 2, 3, 4, 0x123))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            translation.physical_address,
+            PhysicalAddress::from(0x7000_0123)
+        );
+        assert_eq!(translation.page, PhysicalAddress::from(0x7000_0000));
+        assert_eq!(translation.page_size, PageSize::Size4K);
+        assert_eq!(
+            translation.flags,
+            PageFlags::PRESENT | PageFlags::WRITABLE | PageFlags::USER
+        );
+        assert_eq!(translation.effective, PageFlags::WRITABLE | PageFlags::USER);
+    }
+
+    #[test]
+    fn translates_2m_page() {
+        let image = image();
+        let walker = PageTableWalker::new(&image[..], PagingMode::FourLevel);
+
+        let translation = walker
+            .translate(PML4, address(1, 2, 5, 0x12, 0x345))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            translation.physical_address,
+            PhysicalAddress::from(0x4021_2345)
+        );
+        assert_eq!(translation.page, PhysicalAddress::from(0x4020_0000));
+        assert_eq!(translation.page_size, PageSize::Size2M);
+        assert!(translation.flags.contains(PageFlags::LARGE_PAGE));
+    }
+
+    #[test]
+    fn translates_1g_page() {
+        let image = image();
+        let walker = PageTableWalker::new(&image[..], PagingMode::FourLevel);
+
+        let translation = walker
+            .translate(PML4, address(1, 6, 0x91, 0xA2, 0x678))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            translation.physical_address,
+            PhysicalAddress::from(0x922A_2678)
+        );
+        assert_eq!(translation.page, PhysicalAddress::from(0x8000_0000));
+        assert_eq!(translation.page_size, PageSize::Size1G);
+    }
+
+    #[test]
+    fn translates_with_five_levels() {
+        let image = image();
+        let walker = PageTableWalker::new(&image[..], PagingMode::FiveLevel);
+
+        // The PML5 index 0x100 sets bit 56, which is sign extended.
+        let virtual_address = 0xFF00_0000_0000_0000 | address(1, 2, 3, 4, 0x123);
+
+        let translation = walker.translate(PML5, virtual_address).unwrap().unwrap();
+
+        assert_eq!(
+            translation.physical_address,
+            PhysicalAddress::from(0x7000_0123)
+        );
+
+        // The same address is not canonical with four levels.
+        let walker = PageTableWalker::new(&image[..], PagingMode::FourLevel);
+
+        assert_eq!(
+            walker.translate(PML4, virtual_address),
+            Err(Error::INVALID_PARAMETER)
+        );
+    }
+
+    #[test]
+    fn non_present_entries_are_not_mapped() {
+        let image = image();
+        let walker = PageTableWalker::new(&image[..], PagingMode::FourLevel);
+
+        assert_eq!(walker.translate(PML4, address(1, 2, 3, 5, 0)), Ok(None));
+        assert_eq!(walker.translate(PML4, address(1, 3, 0, 0, 0)), Ok(None));
+        assert_eq!(walker.translate(PML4, address(0, 0, 0, 0, 0)), Ok(None));
+    }
+
+    #[test]
+    fn rejects_non_canonical_addresses() {
+        let image = image();
+        let walker = PageTableWalker::new(&image[..], PagingMode::FourLevel);
+
+        assert!(PagingMode::FourLevel.is_canonical(0x0000_7FFF_FFFF_FFFF));
+        assert!(PagingMode::FourLevel.is_canonical(0xFFFF_8000_0000_0000));
+        assert!(!PagingMode::FourLevel.is_canonical(0x0000_8000_0000_0000));
+        assert!(PagingMode::FiveLevel.is_canonical(0x0000_8000_0000_0000));
+        assert!(!PagingMode::FiveLevel.is_canonical(0x0100_0000_0000_0000));
+
+        assert_eq!(
+            walker.translate(PML4, 0x0000_8000_0000_0000),
+            Err(Error::INVALID_PARAMETER)
+        );
+    }
+
+    #[test]
+    fn tables_outside_the_image_fail() {
+        let mut image = image();
+        set(&mut image, PML4, 2, 0x10_0000 | TABLE);
+
+        let walker = PageTableWalker::new(&image[..], PagingMode::FourLevel);
+
+        assert_eq!(
+            walker.translate(PML4, address(2, 0, 0, 0, 0)),
+            Err(Error::ACCESS_VIOLATION)
+        );
+    }
+
+    #[test]
+    fn merges_access_across_levels() {
+        let mut image = image();
+        let present = PageFlags::PRESENT.bits();
+        let writable = PageFlags::WRITABLE.bits();
+        let no_execute = PageFlags::NO_EXECUTE.bits();
+
+        // Only the PDPT entry denies user mode accesses, and only the PD entry denies execution.
+        set(&mut image, PML4, 3, 0x6000 | TABLE);
+        set(&mut image, 0x6000, 0, 0x8000 | present | writable);
+        set(&mut image, 0x8000, 0, 0x9000 | TABLE | no_execute);
+        set(&mut image, 0x9000, 0, 0xA000_0000 | TABLE);
+
+        let walker = PageTableWalker::new(&image[..], PagingMode::FourLevel);
+        let translation = walker
+            .translate(PML4, address(3, 0, 0, 0, 0))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(translation.flags, PageFlags::from_bits_truncate(TABLE));
+        assert_eq!(
+            translation.effective,
+            PageFlags::WRITABLE | PageFlags::NO_EXECUTE
+        );
+
+        // A read-only PT entry removes write access as well.
+        set(&mut image, 0x9000, 0, 0xA000_0000 | present);
+
+        let walker = PageTableWalker::new(&image[..], PagingMode::FourLevel);
+        let translation = walker
+            .translate(PML4, address(3, 0, 0, 0, 0))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(translation.effective, PageFlags::NO_EXECUTE);
+    }
+}
@@ -0,0 +1,498 @@
+//! path mod
+//!
+//! Parsing and conversion between DOS paths (`C:\foo`), NT paths (`\??\C:\foo`,
+//! `\DosDevices\C:\foo`) and device paths (`\Device\HarddiskVolume3\foo`). Everything but
+//! [`resolve_drive`] and [`to_device_path`] is pure, and only works on strings.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::error::Error;
+use crate::symbolic_link::SymbolicLink;
+
+/// The prefixes under which the object manager exposes the DOS device names.
+const DOS_DEVICES_PREFIXES: [&str; 4] = ["\\??\\", "\\DosDevices\\", "\\GLOBAL??\\", "\\\\?\\"];
+
+/// The prefix of a device path.
+const DEVICE_PREFIX: &str = "\\Device\\";
+
+/// The name of the symbolic link to the Windows directory.
+const SYSTEM_ROOT: &str = "\\SystemRoot";
+
+/// The root of a path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Prefix<'a> {
+    /// A drive letter, either as a DOS path (`C:\foo`) or behind one of the DOS device
+    /// prefixes (`\??\C:\foo`, `\DosDevices\C:\foo`, `\GLOBAL??\C:\foo`). The letter is
+    /// upper case.
+    Drive(char),
+    /// A UNC path, either as a DOS path (`\\server\share\foo`) or behind one of the DOS device
+    /// prefixes (`\??\UNC\server\share\foo`).
+    Unc { server: &'a str, share: &'a str },
+    /// Any other DOS device name, e.g. `\??\PhysicalDrive0` yields `PhysicalDrive0`.
+    DosDevice(&'a str),
+    /// A device path, e.g. `\Device\HarddiskVolume3\foo` yields `HarddiskVolume3`.
+    Device(&'a str),
+    /// A path relative to `\SystemRoot`.
+    SystemRoot,
+    /// Any other absolute path in the object manager namespace, e.g. `\Registry\Machine`.
+    Root,
+    /// A relative path.
+    Relative,
+}
+
+/// A path split into its [`Prefix`] and the remainder after the prefix.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Path<'a> {
+    /// The root of the path.
+    pub prefix: Prefix<'a>,
+    /// The remainder of the path, without leading separators.
+    pub rest: &'a str,
+}
+
+fn is_separator(c: char) -> bool {
+    c == '\\' || c == '/'
+}
+
+/// Strips `prefix` from `path` ignoring ASCII case.
+fn strip_prefix_ignore_case<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    let head = path.get(..prefix.len())?;
+
+    if head.eq_ignore_ascii_case(prefix) {
+        Some(&path[prefix.len()..])
+    } else {
+        None
+    }
+}
+
+/// Splits the next component off a path, returns the component and the remainder without leading
+/// separators.
+fn split_component(path: &str) -> (&str, &str) {
+    match path.find(is_separator) {
+        Some(i) => (&path[..i], path[i..].trim_start_matches(is_separator)),
+        None => (path, ""),
+    }
+}
+
+/// Parses a drive letter followed by a colon, and either the end of the path or a separator.
+fn parse_drive(path: &str) -> Option<(char, &str)> {
+    let mut chars = path.chars();
+
+    let letter = chars.next().filter(char::is_ascii_alphabetic)?;
+
+    if chars.next() != Some(':') {
+        return None;
+    }
+
+    let rest = chars.as_str();
+
+    match rest.chars().next() {
+        None => Some((letter.to_ascii_uppercase(), rest)),
+        Some(c) if is_separator(c) => {
+            Some((letter.to_ascii_uppercase(), rest.trim_start_matches(is_separator)))
+        }
+        _ => None,
+    }
+}
+
+fn parse_unc(path: &str) -> Path<'_> {
+    let (server, rest) = split_component(path);
+    let (share, rest) = split_component(rest);
+
+    Path {
+        prefix: Prefix::Unc { server, share },
+        rest,
+    }
+}
+
+/// Splits a path into its prefix and the remainder. DOS device prefixes and `\Device\` are
+/// recognized regardless of case.
+pub fn parse(path: &str) -> Path<'_> {
+    for dos_devices in DOS_DEVICES_PREFIXES {
+        if let Some(rest) = strip_prefix_ignore_case(path, dos_devices) {
+            if let Some((letter, rest)) = parse_drive(rest) {
+                return Path {
+                    prefix: Prefix::Drive(letter),
+                    rest,
+                };
+            }
+
+            if let Some(rest) = strip_prefix_ignore_case(rest, "UNC\\") {
+                return parse_unc(rest);
+            }
+
+            let (device, rest) = split_component(rest);
+
+            return Path {
+                prefix: Prefix::DosDevice(device),
+                rest,
+            };
+        }
+    }
+
+    if let Some((letter, rest)) = parse_drive(path) {
+        return Path {
+            prefix: Prefix::Drive(letter),
+            rest,
+        };
+    }
+
+    if let Some(rest) = path.strip_prefix("\\\\") {
+        return parse_unc(rest);
+    }
+
+    if let Some(rest) = strip_prefix_ignore_case(path, DEVICE_PREFIX) {
+        let (device, rest) = split_component(rest);
+
+        return Path {
+            prefix: Prefix::Device(device),
+            rest,
+        };
+    }
+
+    if let Some(rest) = strip_prefix_ignore_case(path, SYSTEM_ROOT) {
+        if rest.is_empty() || rest.starts_with(is_separator) {
+            return Path {
+                prefix: Prefix::SystemRoot,
+                rest: rest.trim_start_matches(is_separator),
+            };
+        }
+    }
+
+    if path.starts_with(is_separator) {
+        return Path {
+            prefix: Prefix::Root,
+            rest: path.trim_start_matches(is_separator),
+        };
+    }
+
+    Path {
+        prefix: Prefix::Relative,
+        rest: path,
+    }
+}
+
+/// Returns an iterator over the components of a path, without the prefix. Empty components and
+/// repeated separators are skipped.
+pub fn components(path: &str) -> impl Iterator<Item = &str> {
+    parse(path)
+        .rest
+        .split(is_separator)
+        .filter(|component| !component.is_empty())
+}
+
+/// Resolves `.` and `..` in the components, fails with [`Error::OBJECT_PATH_SYNTAX_BAD`] if `..`
+/// would leave the root.
+fn normalize_components(rest: &str) -> Result<Vec<&str>, Error> {
+    let mut components = Vec::new();
+
+    for component in rest.split(is_separator) {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop().ok_or(Error::OBJECT_PATH_SYNTAX_BAD)?;
+            }
+            component => components.push(component),
+        }
+    }
+
+    Ok(components)
+}
+
+fn join(root: &str, components: &[&str]) -> String {
+    let mut path = String::from(root);
+
+    for (i, component) in components.iter().enumerate() {
+        if i != 0 || !(root.is_empty() || root.ends_with('\\')) {
+            path.push('\\');
+        }
+
+        path.push_str(component);
+    }
+
+    path
+}
+
+/// Formats the prefix in NT form.
+fn nt_root(prefix: Prefix<'_>) -> String {
+    match prefix {
+        Prefix::Drive(letter) => format!("\\??\\{}:\\", letter),
+        Prefix::Unc { server, share } => format!("\\??\\UNC\\{}\\{}", server, share),
+        Prefix::DosDevice(device) => format!("\\??\\{}", device),
+        Prefix::Device(device) => format!("{}{}", DEVICE_PREFIX, device),
+        Prefix::SystemRoot => String::from(SYSTEM_ROOT),
+        Prefix::Root => String::from("\\"),
+        Prefix::Relative => String::new(),
+    }
+}
+
+/// Normalizes a path: separators are collapsed into a single `\`, `.` components are removed and
+/// `..` components remove the preceding component. The prefix is kept in its original form.
+pub fn normalize(path: &str) -> Result<String, Error> {
+    let parsed = parse(path);
+    let components = normalize_components(parsed.rest)?;
+
+    let root = match parsed.prefix {
+        Prefix::Relative => String::new(),
+        _ => {
+            let prefix_len = path.len() - parsed.rest.len();
+            let mut root = String::from(path[..prefix_len].trim_end_matches(is_separator));
+
+            if matches!(parsed.prefix, Prefix::Drive(_) | Prefix::Root) {
+                root.push('\\');
+            }
+
+            root.replace('/', "\\")
+        }
+    };
+
+    Ok(join(&root, &components))
+}
+
+/// Converts a DOS path into an NT path, e.g. `C:\foo` into `\??\C:\foo` and `\\server\share` into
+/// `\??\UNC\server\share`. The path is normalized, and relative paths are rejected with
+/// [`Error::OBJECT_PATH_SYNTAX_BAD`].
+pub fn to_nt_path(path: &str) -> Result<String, Error> {
+    let parsed = parse(path);
+
+    if parsed.prefix == Prefix::Relative {
+        return Err(Error::OBJECT_PATH_SYNTAX_BAD);
+    }
+
+    let components = normalize_components(parsed.rest)?;
+
+    Ok(join(&nt_root(parsed.prefix), &components))
+}
+
+/// Converts a path with a drive letter or a UNC prefix into a DOS path, e.g. `\??\C:\foo` into
+/// `C:\foo`. Returns `None` for paths that have no DOS equivalent, such as device paths.
+pub fn to_dos_path(path: &str) -> Option<String> {
+    let parsed = parse(path);
+    let components = normalize_components(parsed.rest).ok()?;
+
+    let root = match parsed.prefix {
+        Prefix::Drive(letter) => format!("{}:\\", letter),
+        Prefix::Unc { server, share } => format!("\\\\{}\\{}", server, share),
+        _ => return None,
+    };
+
+    Some(join(&root, &components))
+}
+
+/// Maps a UTF-16 code unit to upper case with the Unicode upper case mapping of the standard
+/// library: every code unit maps to exactly one code unit, and characters whose upper case is not
+/// a single code unit, as well as surrogates, are left unchanged. This approximates
+/// `RtlUpcaseUnicodeChar`, whose table comes with the running system and can differ for a few
+/// characters, without its IRQL restriction.
+pub fn upcase(c: u16) -> u16 {
+    let c = match char::from_u32(c as u32) {
+        Some(ch) => ch,
+        None => return c,
+    };
+
+    let mut upper = c.to_uppercase();
+
+    match (upper.next(), upper.next()) {
+        (Some(u), None) if (u as u32) <= 0xFFFF => u as u16,
+        _ => c as u16,
+    }
+}
+
+/// Compares two UTF-16 strings code unit by code unit after [`upcase`], similar to
+/// `RtlEqualUnicodeString` with `CaseInSensitive` set.
+pub fn eq_ignore_case_utf16(a: &[u16], b: &[u16]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| upcase(*a) == upcase(*b))
+}
+
+/// Compares two strings with NT case-insensitive semantics, see [`eq_ignore_case_utf16`].
+pub fn eq_ignore_case(a: &str, b: &str) -> bool {
+    let mut a = a.encode_utf16();
+    let mut b = b.encode_utf16();
+
+    loop {
+        match (a.next(), b.next()) {
+            (None, None) => return true,
+            (Some(a), Some(b)) if upcase(a) == upcase(b) => {}
+            _ => return false,
+        }
+    }
+}
+
+/// Returns whether `path` lies within `base`, comparing whole components with NT
+/// case-insensitive semantics. Both paths are normalized first.
+pub fn starts_with_ignore_case(path: &str, base: &str) -> Result<bool, Error> {
+    let path = normalize(path)?;
+    let base = normalize(base)?;
+    let base = base.trim_end_matches('\\');
+
+    if path.len() < base.len() || !path.is_char_boundary(base.len()) {
+        return Ok(false);
+    }
+
+    let (head, tail) = path.split_at(base.len());
+
+    Ok(eq_ignore_case(head, base) && (tail.is_empty() || tail.starts_with('\\')))
+}
+
+/// Resolves a drive letter to the device it refers to by querying the `\??\X:` symbolic link,
+/// e.g. `C` resolves to `\Device\HarddiskVolume3`.
+pub fn resolve_drive(letter: char) -> Result<String, Error> {
+    if !letter.is_ascii_alphabetic() {
+        return Err(Error::INVALID_PARAMETER);
+    }
+
+    let link = format!("\\??\\{}:", letter.to_ascii_uppercase());
+
+    SymbolicLink::query_target(&link)?.to_string()
+}
+
+/// Converts a DOS or NT path with a drive letter, or a path relative to `\SystemRoot`, into a
+/// device path, e.g. `C:\foo` into `\Device\HarddiskVolume3\foo`. Other paths are only
+/// normalized.
+pub fn to_device_path(path: &str) -> Result<String, Error> {
+    let parsed = parse(path);
+    let components = normalize_components(parsed.rest)?;
+
+    let root = match parsed.prefix {
+        Prefix::Drive(letter) => resolve_drive(letter)?,
+        Prefix::SystemRoot => SymbolicLink::query_target(SYSTEM_ROOT)?.to_string()?,
+        _ => return normalize(path),
+    };
+
+    Ok(join(&root, &components))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(path: &str) -> (Prefix<'_>, &str) {
+        let path = parse(path);
+
+        (path.prefix, path.rest)
+    }
+
+    #[test]
+    fn parses_drive_paths() {
+        assert_eq!(parsed("C:\\foo\\bar"), (Prefix::Drive('C'), "foo\\bar"));
+        assert_eq!(parsed("c:"), (Prefix::Drive('C'), ""));
+        assert_eq!(parsed("\\??\\c:\\foo"), (Prefix::Drive('C'), "foo"));
+        assert_eq!(parsed("\\DosDevices\\D:\\foo"), (Prefix::Drive('D'), "foo"));
+        assert_eq!(parsed("\\global??\\E:/foo"), (Prefix::Drive('E'), "foo"));
+        assert_eq!(parsed("\\\\?\\C:\\foo"), (Prefix::Drive('C'), "foo"));
+        assert_eq!(parsed("C:foo"), (Prefix::Relative, "C:foo"));
+    }
+
+    #[test]
+    fn parses_unc_paths() {
+        let unc = Prefix::Unc {
+            server: "server",
+            share: "share",
+        };
+
+        assert_eq!(parsed("\\\\server\\share\\foo"), (unc, "foo"));
+        assert_eq!(parsed("\\??\\UNC\\server\\share\\foo"), (unc, "foo"));
+    }
+
+    #[test]
+    fn parses_object_manager_paths() {
+        assert_eq!(
+            parsed("\\??\\PhysicalDrive0"),
+            (Prefix::DosDevice("PhysicalDrive0"), "")
+        );
+        assert_eq!(
+            parsed("\\device\\HarddiskVolume3\\foo"),
+            (Prefix::Device("HarddiskVolume3"), "foo")
+        );
+        assert_eq!(
+            parsed("\\SystemRoot\\System32"),
+            (Prefix::SystemRoot, "System32")
+        );
+        assert_eq!(parsed("\\SystemRootX"), (Prefix::Root, "SystemRootX"));
+        assert_eq!(
+            parsed("\\Registry\\Machine"),
+            (Prefix::Root, "Registry\\Machine")
+        );
+        assert_eq!(parsed("foo\\bar"), (Prefix::Relative, "foo\\bar"));
+    }
+
+    #[test]
+    fn splits_components() {
+        let components: Vec<&str> = components("\\??\\C:\\a\\\\b/c\\").collect();
+
+        assert_eq!(components, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn normalizes_paths() {
+        assert_eq!(normalize("C:\\a\\.\\b\\..\\c").unwrap(), "C:\\a\\c");
+        assert_eq!(normalize("C:/a//b").unwrap(), "C:\\a\\b");
+        assert_eq!(normalize("C:\\").unwrap(), "C:\\");
+        assert_eq!(normalize("a\\..\\b").unwrap(), "b");
+        assert_eq!(
+            normalize("\\Device\\HarddiskVolume3\\foo\\..").unwrap(),
+            "\\Device\\HarddiskVolume3"
+        );
+        assert_eq!(
+            normalize("\\??\\C:\\.."),
+            Err(Error::OBJECT_PATH_SYNTAX_BAD)
+        );
+    }
+
+    #[test]
+    fn converts_to_nt_paths() {
+        assert_eq!(to_nt_path("c:\\foo\\.\\bar").unwrap(), "\\??\\C:\\foo\\bar");
+        assert_eq!(to_nt_path("C:").unwrap(), "\\??\\C:\\");
+        assert_eq!(
+            to_nt_path("\\\\server\\share\\foo").unwrap(),
+            "\\??\\UNC\\server\\share\\foo"
+        );
+        assert_eq!(
+            to_nt_path("\\DosDevices\\C:\\foo").unwrap(),
+            "\\??\\C:\\foo"
+        );
+        assert_eq!(to_nt_path("foo"), Err(Error::OBJECT_PATH_SYNTAX_BAD));
+    }
+
+    #[test]
+    fn converts_to_dos_paths() {
+        assert_eq!(to_dos_path("\\??\\C:\\foo").as_deref(), Some("C:\\foo"));
+        assert_eq!(
+            to_dos_path("\\??\\UNC\\server\\share\\foo").as_deref(),
+            Some("\\\\server\\share\\foo")
+        );
+        assert_eq!(to_dos_path("\\Device\\HarddiskVolume3\\foo"), None);
+    }
+
+    #[test]
+    fn upcases_code_units() {
+        assert_eq!(upcase(b'a' as u16), b'A' as u16);
+        assert_eq!(upcase(0xE9), 0xC9);
+        // `ß` upper cases to two characters, which `RtlUpcaseUnicodeChar` cannot do.
+        assert_eq!(upcase(0xDF), 0xDF);
+        assert_eq!(upcase(0xD800), 0xD800);
+    }
+
+    #[test]
+    fn compares_ignoring_case() {
+        assert!(eq_ignore_case("\\Device\\Foo", "\\DEVICE\\foo"));
+        assert!(eq_ignore_case("café", "CAFÉ"));
+        assert!(!eq_ignore_case("straße", "STRASSE"));
+        assert!(!eq_ignore_case("abc", "abcd"));
+
+        let a: Vec<u16> = "abc".encode_utf16().collect();
+        let b: Vec<u16> = "ABC".encode_utf16().collect();
+
+        assert!(eq_ignore_case_utf16(&a, &b));
+    }
+
+    #[test]
+    fn compares_whole_components() {
+        assert!(starts_with_ignore_case("C:\\Windows\\System32", "c:\\windows").unwrap());
+        assert!(starts_with_ignore_case("C:\\Windows", "C:\\Windows\\").unwrap());
+        assert!(starts_with_ignore_case("C:\\foo", "C:\\").unwrap());
+        assert!(!starts_with_ignore_case("C:\\WindowsApps", "C:\\Windows").unwrap());
+        assert!(!starts_with_ignore_case("C:\\Win", "C:\\Windows").unwrap());
+    }
+}
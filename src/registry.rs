@@ -0,0 +1,1034 @@
+//! registry mod
+//!
+//! Registry access on top of the `Zw*Key` family of functions. Decoding of the value and
+//! enumeration buffers is kept separate from the system calls, so that it only depends on a byte
+//! slice.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::task::{Context, Poll};
+
+use bitflags::bitflags;
+use futures::task::AtomicWaker;
+use futures::Stream;
+
+use win_kernel_sys::base::_EVENT_TYPE::{NotificationEvent, SynchronizationEvent};
+use win_kernel_sys::base::_KEY_INFORMATION_CLASS::KeyBasicInformation;
+use win_kernel_sys::base::_KEY_VALUE_INFORMATION_CLASS::{
+    KeyValueFullInformation, KeyValuePartialInformation,
+};
+use win_kernel_sys::base::_KWAIT_REASON::Executive;
+use win_kernel_sys::base::_MODE::KernelMode;
+use win_kernel_sys::base::_WAIT_TYPE::WaitAny;
+use win_kernel_sys::base::{
+    EVENT_ALL_ACCESS, HANDLE, IO_STATUS_BLOCK, KEVENT, NTSTATUS, OBJECT_ATTRIBUTES, REG_BINARY,
+    REG_CREATED_NEW_KEY, REG_DWORD, REG_EXPAND_SZ, REG_MULTI_SZ, REG_OPTION_NON_VOLATILE,
+    REG_OPTION_VOLATILE, REG_QWORD, REG_SZ, STATUS_BUFFER_OVERFLOW, STATUS_BUFFER_TOO_SMALL,
+    STATUS_NO_MORE_ENTRIES, STATUS_PENDING, STATUS_SUCCESS, STATUS_WAIT_0, SYNCHRONIZE,
+};
+use win_kernel_sys::ntoskrnl::{
+    KeInitializeEvent, KeSetEvent, KeWaitForMultipleObjects, KeWaitForSingleObject,
+};
+use win_kernel_sys::ntoskrnl::{ObDereferenceObject, ObReferenceObjectByHandle};
+use win_kernel_sys::ntoskrnl::{PsCreateSystemThread, PsTerminateSystemThread};
+use win_kernel_sys::ntoskrnl::{
+    ZwClose, ZwCreateEvent, ZwCreateKey, ZwDeleteKey, ZwEnumerateKey, ZwEnumerateValueKey,
+    ZwNotifyChangeKey, ZwOpenKey, ZwQueryValueKey, ZwSetValueKey, ZwWaitForSingleObject,
+};
+
+use crate::error::{Error, IntoResult};
+use crate::section::ObjectFlags;
+use crate::string::{OwnedUnicodeString, UnicodeStr};
+
+bitflags! {
+    /// RegistryAccess
+    pub struct RegistryAccess: u32 {
+        const QUERY_VALUE        = win_kernel_sys::base::KEY_QUERY_VALUE;
+        const SET_VALUE          = win_kernel_sys::base::KEY_SET_VALUE;
+        const CREATE_SUB_KEY     = win_kernel_sys::base::KEY_CREATE_SUB_KEY;
+        const ENUMERATE_SUB_KEYS = win_kernel_sys::base::KEY_ENUMERATE_SUB_KEYS;
+        const NOTIFY             = win_kernel_sys::base::KEY_NOTIFY;
+        const DELETE             = win_kernel_sys::base::DELETE;
+        const READ               = win_kernel_sys::base::KEY_READ;
+        const WRITE              = win_kernel_sys::base::KEY_WRITE;
+        const ALL_ACCESS         = win_kernel_sys::base::KEY_ALL_ACCESS;
+    }
+}
+
+/// Whether [`RegistryKey::create`] created a new key or opened an existing one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CreateDisposition {
+    CreatedNewKey,
+    OpenedExistingKey,
+}
+
+/// A typed registry value.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RegistryValue {
+    /// `REG_DWORD`
+    Dword(u32),
+    /// `REG_QWORD`
+    Qword(u64),
+    /// `REG_SZ`
+    String(String),
+    /// `REG_EXPAND_SZ`, the environment variables are not expanded.
+    ExpandString(String),
+    /// `REG_MULTI_SZ`
+    MultiString(Vec<String>),
+    /// `REG_BINARY`
+    Binary(Vec<u8>),
+    /// Any other value type, together with its raw data.
+    Other(u32, Vec<u8>),
+}
+
+/// Decodes little-endian UTF-16 bytes up to the first NUL character.
+fn decode_utf16(bytes: &[u8]) -> String {
+    let units = bytes
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .take_while(|c| *c != 0);
+
+    char::decode_utf16(units)
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
+}
+
+/// Encodes a string as little-endian UTF-16 bytes, without a NUL terminator.
+fn encode_utf16(s: &str, buffer: &mut Vec<u8>) -> Result<(), Error> {
+    for c in s.encode_utf16() {
+        if c == 0 {
+            return Err(Error::INVALID_PARAMETER);
+        }
+
+        buffer.extend_from_slice(&c.to_le_bytes());
+    }
+
+    Ok(())
+}
+
+fn read_u32(buffer: &[u8], offset: usize) -> Result<u32, Error> {
+    buffer
+        .get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+        .ok_or(Error::INVALID_PARAMETER)
+}
+
+impl RegistryValue {
+    /// Decodes the data of a value of the given type. Strings are read up to the first NUL
+    /// character, as the registry does not guarantee that they are terminated.
+    pub fn decode(ty: u32, data: &[u8]) -> Result<Self, Error> {
+        Ok(match ty {
+            REG_DWORD => Self::Dword(
+                data.try_into()
+                    .map(u32::from_le_bytes)
+                    .map_err(|_| Error::INVALID_PARAMETER)?,
+            ),
+            REG_QWORD => Self::Qword(
+                data.try_into()
+                    .map(u64::from_le_bytes)
+                    .map_err(|_| Error::INVALID_PARAMETER)?,
+            ),
+            REG_SZ => Self::String(decode_utf16(data)),
+            REG_EXPAND_SZ => Self::ExpandString(decode_utf16(data)),
+            REG_MULTI_SZ => {
+                let units: Vec<u16> = data
+                    .chunks_exact(2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .collect();
+
+                let strings = units
+                    .split(|c| *c == 0)
+                    .take_while(|s| !s.is_empty())
+                    .map(|s| {
+                        char::decode_utf16(s.iter().copied())
+                            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                            .collect()
+                    })
+                    .collect();
+
+                Self::MultiString(strings)
+            }
+            REG_BINARY => Self::Binary(data.to_vec()),
+            ty => Self::Other(ty, data.to_vec()),
+        })
+    }
+
+    /// Encodes the value into its type and data, as expected by [`ZwSetValueKey`]. Strings are
+    /// NUL-terminated. Fails with [`Error::INVALID_PARAMETER`] on strings with embedded NUL
+    /// characters, or on empty strings in a `REG_MULTI_SZ`.
+    pub fn encode(&self) -> Result<(u32, Vec<u8>), Error> {
+        let mut data = Vec::new();
+
+        let ty = match self {
+            Self::Dword(value) => {
+                data.extend_from_slice(&value.to_le_bytes());
+                REG_DWORD
+            }
+            Self::Qword(value) => {
+                data.extend_from_slice(&value.to_le_bytes());
+                REG_QWORD
+            }
+            Self::String(s) | Self::ExpandString(s) => {
+                encode_utf16(s, &mut data)?;
+                data.extend_from_slice(&[0, 0]);
+
+                self.value_type()
+            }
+            Self::MultiString(strings) => {
+                for s in strings {
+                    if s.is_empty() {
+                        return Err(Error::INVALID_PARAMETER);
+                    }
+
+                    encode_utf16(s, &mut data)?;
+                    data.extend_from_slice(&[0, 0]);
+                }
+
+                data.extend_from_slice(&[0, 0]);
+                REG_MULTI_SZ
+            }
+            Self::Binary(bytes) => {
+                data.extend_from_slice(bytes);
+                REG_BINARY
+            }
+            Self::Other(ty, bytes) => {
+                data.extend_from_slice(bytes);
+                *ty
+            }
+        };
+
+        Ok((ty, data))
+    }
+
+    /// Returns the `REG_*` type of the value.
+    pub fn value_type(&self) -> u32 {
+        match self {
+            Self::Dword(_) => REG_DWORD,
+            Self::Qword(_) => REG_QWORD,
+            Self::String(_) => REG_SZ,
+            Self::ExpandString(_) => REG_EXPAND_SZ,
+            Self::MultiString(_) => REG_MULTI_SZ,
+            Self::Binary(_) => REG_BINARY,
+            Self::Other(ty, _) => *ty,
+        }
+    }
+
+    /// Decodes a `KEY_VALUE_PARTIAL_INFORMATION` buffer.
+    pub fn from_partial_information(buffer: &[u8]) -> Result<Self, Error> {
+        // TitleIndex, Type, DataLength, Data.
+        let ty = read_u32(buffer, 4)?;
+        let len = read_u32(buffer, 8)? as usize;
+        let data = buffer.get(12..12 + len).ok_or(Error::INVALID_PARAMETER)?;
+
+        Self::decode(ty, data)
+    }
+}
+
+/// Decodes a `KEY_VALUE_FULL_INFORMATION` buffer into the name and value.
+pub fn decode_value_full_information(buffer: &[u8]) -> Result<(String, RegistryValue), Error> {
+    // TitleIndex, Type, DataOffset, DataLength, NameLength, Name.
+    let ty = read_u32(buffer, 4)?;
+    let data_offset = read_u32(buffer, 8)? as usize;
+    let data_len = read_u32(buffer, 12)? as usize;
+    let name_len = read_u32(buffer, 16)? as usize;
+
+    let name = buffer.get(20..20 + name_len).ok_or(Error::INVALID_PARAMETER)?;
+    let data = buffer
+        .get(data_offset..data_offset + data_len)
+        .ok_or(Error::INVALID_PARAMETER)?;
+
+    Ok((decode_utf16(name), RegistryValue::decode(ty, data)?))
+}
+
+/// Decodes a `KEY_BASIC_INFORMATION` buffer into the name of the subkey.
+pub fn decode_key_basic_information(buffer: &[u8]) -> Result<String, Error> {
+    // LastWriteTime, TitleIndex, NameLength, Name.
+    let name_len = read_u32(buffer, 12)? as usize;
+    let name = buffer.get(16..16 + name_len).ok_or(Error::INVALID_PARAMETER)?;
+
+    Ok(decode_utf16(name))
+}
+
+/// Calls a `Zw*Key` query function, growing the buffer while it reports that the buffer is too
+/// small. Returns the filled part of the buffer, or `STATUS_NO_MORE_ENTRIES` as an error once an
+/// enumeration is exhausted.
+fn query_with_retry<F>(buffer: &mut Vec<u64>, mut f: F) -> Result<usize, Error>
+where
+    F: FnMut(*mut core::ffi::c_void, u32, &mut u32) -> NTSTATUS,
+{
+    loop {
+        let mut result_len = 0;
+        let status = f(buffer.as_mut_ptr() as _, (buffer.len() * 8) as _, &mut result_len);
+
+        match status {
+            STATUS_BUFFER_OVERFLOW | STATUS_BUFFER_TOO_SMALL
+                if result_len as usize > buffer.len() * 8 =>
+            {
+                buffer.resize((result_len as usize + 7) / 8, 0);
+            }
+            status => {
+                status.into_result()?;
+
+                return Ok(core::cmp::min(result_len as usize, buffer.len() * 8));
+            }
+        }
+    }
+}
+
+fn as_bytes(buffer: &[u64], len: usize) -> &[u8] {
+    unsafe { core::slice::from_raw_parts(buffer.as_ptr() as *const u8, len) }
+}
+
+/// A registry key, closed on drop.
+pub struct RegistryKey {
+    handle: HANDLE,
+}
+
+unsafe impl Send for RegistryKey {}
+
+unsafe impl Sync for RegistryKey {}
+
+impl RegistryKey {
+    /// The initial size of the query buffers in bytes.
+    const BUFFER_SIZE: usize = 256;
+
+    fn object_attributes(
+        parent: Option<&RegistryKey>,
+        name: &mut UnicodeStr<'_>,
+    ) -> OBJECT_ATTRIBUTES {
+        OBJECT_ATTRIBUTES {
+            Length: core::mem::size_of::<OBJECT_ATTRIBUTES>() as u32,
+            RootDirectory: parent.map_or(core::ptr::null_mut(), |parent| parent.handle),
+            ObjectName: name.as_mut_ptr(),
+            Attributes: (ObjectFlags::CASE_INSENSITIVE | ObjectFlags::KERNEL_HANDLE).bits(),
+            SecurityDescriptor: core::ptr::null_mut(),
+            SecurityQualityOfService: core::ptr::null_mut(),
+        }
+    }
+
+    fn open_inner(
+        parent: Option<&RegistryKey>,
+        path: &str,
+        access: RegistryAccess,
+    ) -> Result<Self, Error> {
+        let name = OwnedUnicodeString::try_from(path)?;
+        let mut name = name.as_unicode_str();
+        let mut attrs = Self::object_attributes(parent, &mut name);
+
+        let mut handle: HANDLE = core::ptr::null_mut();
+
+        unsafe { ZwOpenKey(&mut handle, access.bits(), &mut attrs) }.into_result()?;
+
+        Ok(Self { handle })
+    }
+
+    fn create_inner(
+        parent: Option<&RegistryKey>,
+        path: &str,
+        access: RegistryAccess,
+        volatile: bool,
+    ) -> Result<(Self, CreateDisposition), Error> {
+        let name = OwnedUnicodeString::try_from(path)?;
+        let mut name = name.as_unicode_str();
+        let mut attrs = Self::object_attributes(parent, &mut name);
+
+        let mut handle: HANDLE = core::ptr::null_mut();
+        let mut disposition = 0;
+
+        let options = if volatile {
+            REG_OPTION_VOLATILE
+        } else {
+            REG_OPTION_NON_VOLATILE
+        };
+
+        unsafe {
+            ZwCreateKey(
+                &mut handle,
+                access.bits(),
+                &mut attrs,
+                0,
+                core::ptr::null_mut(),
+                options,
+                &mut disposition,
+            )
+        }
+        .into_result()?;
+
+        let disposition = if disposition == REG_CREATED_NEW_KEY {
+            CreateDisposition::CreatedNewKey
+        } else {
+            CreateDisposition::OpenedExistingKey
+        };
+
+        Ok((Self { handle }, disposition))
+    }
+
+    /// Opens an existing key by its full path, e.g.
+    /// `\Registry\Machine\System\CurrentControlSet\Services\foo`.
+    pub fn open(path: &str, access: RegistryAccess) -> Result<Self, Error> {
+        Self::open_inner(None, path, access)
+    }
+
+    /// Opens an existing subkey relative to this key.
+    pub fn open_subkey(&self, name: &str, access: RegistryAccess) -> Result<Self, Error> {
+        Self::open_inner(Some(self), name, access)
+    }
+
+    /// Opens or creates a key by its full path. Volatile keys are not persisted across reboots.
+    pub fn create(
+        path: &str,
+        access: RegistryAccess,
+        volatile: bool,
+    ) -> Result<(Self, CreateDisposition), Error> {
+        Self::create_inner(None, path, access, volatile)
+    }
+
+    /// Opens or creates a subkey relative to this key.
+    pub fn create_subkey(
+        &self,
+        name: &str,
+        access: RegistryAccess,
+        volatile: bool,
+    ) -> Result<(Self, CreateDisposition), Error> {
+        Self::create_inner(Some(self), name, access, volatile)
+    }
+
+    /// Returns the raw handle.
+    pub fn handle(&self) -> HANDLE {
+        self.handle
+    }
+
+    /// Queries the value with the given name, use `""` for the default value of the key.
+    pub fn query_value(&self, name: &str) -> Result<RegistryValue, Error> {
+        let name = OwnedUnicodeString::try_from(name)?;
+        let mut name = name.as_unicode_str();
+
+        let mut buffer = vec![0u64; Self::BUFFER_SIZE / 8];
+
+        let len = query_with_retry(&mut buffer, |ptr, size, result_len| unsafe {
+            ZwQueryValueKey(
+                self.handle,
+                name.as_mut_ptr(),
+                KeyValuePartialInformation,
+                ptr,
+                size,
+                result_len,
+            )
+        })?;
+
+        RegistryValue::from_partial_information(as_bytes(&buffer, len))
+    }
+
+    /// Sets the value with the given name.
+    pub fn set_value(&self, name: &str, value: &RegistryValue) -> Result<(), Error> {
+        let name = OwnedUnicodeString::try_from(name)?;
+        let mut name = name.as_unicode_str();
+
+        let (ty, mut data) = value.encode()?;
+
+        unsafe {
+            ZwSetValueKey(
+                self.handle,
+                name.as_mut_ptr(),
+                0,
+                ty,
+                data.as_mut_ptr() as _,
+                data.len() as _,
+            )
+        }
+        .into_result()
+    }
+
+    /// Returns an iterator over the names of the subkeys.
+    pub fn subkeys(&self) -> SubKeys<'_> {
+        SubKeys {
+            key: self,
+            index: 0,
+            buffer: vec![0u64; Self::BUFFER_SIZE / 8],
+        }
+    }
+
+    /// Returns an iterator over the names and values of the values.
+    pub fn values(&self) -> Values<'_> {
+        Values {
+            key: self,
+            index: 0,
+            buffer: vec![0u64; Self::BUFFER_SIZE / 8],
+        }
+    }
+
+    /// Deletes the key, which must have no subkeys. The handle is closed afterwards.
+    pub fn delete(self) -> Result<(), Error> {
+        unsafe { ZwDeleteKey(self.handle) }.into_result()
+    }
+}
+
+impl Drop for RegistryKey {
+    fn drop(&mut self) {
+        unsafe {
+            ZwClose(self.handle);
+        }
+    }
+}
+
+/// An iterator over the subkeys of a [`RegistryKey`], backed by [`ZwEnumerateKey`].
+pub struct SubKeys<'a> {
+    key: &'a RegistryKey,
+    index: u32,
+    buffer: Vec<u64>,
+}
+
+impl<'a> Iterator for SubKeys<'a> {
+    type Item = Result<String, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let handle = self.key.handle;
+        let index = self.index;
+
+        let result = query_with_retry(&mut self.buffer, |ptr, size, result_len| unsafe {
+            ZwEnumerateKey(handle, index, KeyBasicInformation, ptr, size, result_len)
+        });
+
+        self.index += 1;
+
+        match result {
+            Ok(len) => Some(decode_key_basic_information(as_bytes(&self.buffer, len))),
+            Err(e) if e.to_ntstatus() == STATUS_NO_MORE_ENTRIES => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// An iterator over the values of a [`RegistryKey`], backed by [`ZwEnumerateValueKey`].
+pub struct Values<'a> {
+    key: &'a RegistryKey,
+    index: u32,
+    buffer: Vec<u64>,
+}
+
+impl<'a> Iterator for Values<'a> {
+    type Item = Result<(String, RegistryValue), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let handle = self.key.handle;
+        let index = self.index;
+
+        let result = query_with_retry(&mut self.buffer, |ptr, size, result_len| unsafe {
+            ZwEnumerateValueKey(handle, index, KeyValueFullInformation, ptr, size, result_len)
+        });
+
+        self.index += 1;
+
+        match result {
+            Ok(len) => Some(decode_value_full_information(as_bytes(&self.buffer, len))),
+            Err(e) if e.to_ntstatus() == STATUS_NO_MORE_ENTRIES => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+bitflags! {
+    /// NotifyFilter
+    pub struct NotifyFilter: u32 {
+        const NAME       = win_kernel_sys::base::REG_NOTIFY_CHANGE_NAME;
+        const ATTRIBUTES = win_kernel_sys::base::REG_NOTIFY_CHANGE_ATTRIBUTES;
+        const LAST_SET   = win_kernel_sys::base::REG_NOTIFY_CHANGE_LAST_SET;
+        const SECURITY   = win_kernel_sys::base::REG_NOTIFY_CHANGE_SECURITY;
+    }
+}
+
+/// The state shared between a [`RegistryWatcher`] and its thread. It is boxed, as
+/// [`ZwNotifyChangeKey`] keeps a pointer to the I/O status block while the notification is
+/// pending.
+struct WatcherInner {
+    /// The watched key, only used and finally closed by the thread.
+    key: UnsafeCell<Option<RegistryKey>>,
+    filter: NotifyFilter,
+    watch_tree: bool,
+    callback: Box<dyn Fn() + Send + Sync>,
+    io_status: UnsafeCell<IO_STATUS_BLOCK>,
+    /// The event handle passed to [`ZwNotifyChangeKey`], and the event object it refers to.
+    change_handle: HANDLE,
+    change: *mut KEVENT,
+    /// Signaled by the watcher to stop the thread.
+    stop: UnsafeCell<KEVENT>,
+    /// Signaled by the thread once the notification has been armed for the first time, with the
+    /// result in `start_status`.
+    started: UnsafeCell<KEVENT>,
+    start_status: UnsafeCell<NTSTATUS>,
+}
+
+impl WatcherInner {
+    /// Arms the notification, which signals the change event once the key changes or is closed.
+    /// A pending notification is the expected outcome, but a change that already happened may
+    /// complete it right away, so both count as armed.
+    unsafe fn arm(&self) -> NTSTATUS {
+        let key = match &*self.key.get() {
+            Some(key) => key,
+            None => return Error::INVALID_HANDLE.to_ntstatus(),
+        };
+
+        ZwNotifyChangeKey(
+            key.handle,
+            self.change_handle,
+            None,
+            core::ptr::null_mut(),
+            self.io_status.get(),
+            self.filter.bits(),
+            self.watch_tree as _,
+            core::ptr::null_mut(),
+            0,
+            true as _,
+        )
+    }
+
+    /// Waits until the change event or the stop event is signaled, and returns whether it was
+    /// the change event.
+    unsafe fn wait_for_change(&self) -> bool {
+        let mut objects = [self.change as *mut core::ffi::c_void, self.stop.get() as _];
+
+        let status = KeWaitForMultipleObjects(
+            objects.len() as _,
+            objects.as_mut_ptr(),
+            WaitAny,
+            Executive,
+            KernelMode as _,
+            false as _,
+            core::ptr::null_mut(),
+            core::ptr::null_mut(),
+        );
+
+        status == STATUS_WAIT_0
+    }
+
+    /// The thread of the watcher. The notification is armed from this thread only, such that
+    /// its completion is processed in the context of this thread, and runs the callback and
+    /// re-arms the notification for every change until the watcher stops it. The key is closed
+    /// here as well, and a pending notification is waited for, such that nothing refers to the
+    /// state or the driver once the thread has terminated.
+    unsafe extern "C" fn thread_routine(context: *mut core::ffi::c_void) {
+        let inner = &*(context as *const WatcherInner);
+
+        let mut armed = match inner.arm() {
+            STATUS_PENDING | STATUS_SUCCESS => true,
+            status => {
+                *inner.start_status.get() = status;
+                false
+            }
+        };
+
+        KeSetEvent(inner.started.get(), 0, false as _);
+
+        while armed && inner.wait_for_change() {
+            (inner.callback)();
+
+            armed = matches!(inner.arm(), STATUS_PENDING | STATUS_SUCCESS);
+        }
+
+        // Closing the key completes a pending notification, which signals the change event once
+        // the I/O status block has been written.
+        drop((*inner.key.get()).take());
+
+        if armed {
+            KeWaitForSingleObject(
+                inner.change as _,
+                Executive,
+                KernelMode as _,
+                false as _,
+                core::ptr::null_mut(),
+            );
+        }
+
+        PsTerminateSystemThread(STATUS_SUCCESS);
+    }
+}
+
+/// Watches a registry key for changes with [`ZwNotifyChangeKey`], and runs a callback on a
+/// dedicated system thread whenever the key, or optionally its subtree, changes. The notification
+/// is re-armed automatically after every callback. Dropping the watcher stops the thread and waits
+/// for it to terminate, such that the callback never outlives the watcher and no code of the
+/// driver runs on the thread afterwards, which makes it safe to drop the watcher on unload.
+///
+/// The watcher must be dropped at `PASSIVE_LEVEL`, and not from within its own callback.
+pub struct RegistryWatcher {
+    inner: Box<WatcherInner>,
+    thread: *mut core::ffi::c_void,
+}
+
+unsafe impl Send for RegistryWatcher {}
+
+unsafe impl Sync for RegistryWatcher {}
+
+impl RegistryWatcher {
+    /// Starts watching the given key, which must have been opened with
+    /// [`RegistryAccess::NOTIFY`]. Fails if the notification cannot be armed.
+    pub fn new<F>(
+        key: RegistryKey,
+        filter: NotifyFilter,
+        watch_tree: bool,
+        callback: F,
+    ) -> Result<Self, Error>
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        let (change_handle, change) = unsafe { create_event()? };
+
+        let mut inner = Box::new(WatcherInner {
+            key: UnsafeCell::new(Some(key)),
+            filter,
+            watch_tree,
+            callback: Box::new(callback),
+            io_status: UnsafeCell::new(unsafe { core::mem::zeroed() }),
+            change_handle,
+            change,
+            stop: UnsafeCell::new(unsafe { core::mem::zeroed() }),
+            started: UnsafeCell::new(unsafe { core::mem::zeroed() }),
+            start_status: UnsafeCell::new(STATUS_SUCCESS),
+        });
+
+        unsafe {
+            KeInitializeEvent(inner.stop.get(), NotificationEvent, false as _);
+            KeInitializeEvent(inner.started.get(), NotificationEvent, false as _);
+        }
+
+        let thread = match unsafe { create_thread(&mut *inner) } {
+            Ok(thread) => thread,
+            Err(e) => {
+                unsafe { close_event(change_handle, change) };
+                return Err(e);
+            }
+        };
+
+        let watcher = Self { inner, thread };
+
+        unsafe {
+            KeWaitForSingleObject(
+                watcher.inner.started.get() as _,
+                Executive,
+                KernelMode as _,
+                false as _,
+                core::ptr::null_mut(),
+            );
+
+            // The thread has terminated, or is about to, if arming failed.
+            (*watcher.inner.start_status.get()).into_result()?;
+        }
+
+        Ok(watcher)
+    }
+}
+
+impl Drop for RegistryWatcher {
+    fn drop(&mut self) {
+        unsafe {
+            KeSetEvent(self.inner.stop.get(), 0, false as _);
+
+            KeWaitForSingleObject(
+                self.thread,
+                Executive,
+                KernelMode as _,
+                false as _,
+                core::ptr::null_mut(),
+            );
+
+            ObDereferenceObject(self.thread);
+            close_event(self.inner.change_handle, self.inner.change);
+        }
+    }
+}
+
+/// Creates a synchronization event for [`ZwNotifyChangeKey`], and returns its kernel handle and
+/// a reference to the event object.
+unsafe fn create_event() -> Result<(HANDLE, *mut KEVENT), Error> {
+    let mut attrs: OBJECT_ATTRIBUTES = core::mem::zeroed();
+    attrs.Length = core::mem::size_of::<OBJECT_ATTRIBUTES>() as u32;
+    attrs.Attributes = ObjectFlags::KERNEL_HANDLE.bits();
+
+    let mut handle: HANDLE = core::ptr::null_mut();
+
+    ZwCreateEvent(
+        &mut handle,
+        EVENT_ALL_ACCESS,
+        &mut attrs,
+        SynchronizationEvent,
+        false as _,
+    )
+    .into_result()?;
+
+    let mut event = core::ptr::null_mut();
+
+    if let Err(e) = ObReferenceObjectByHandle(
+        handle,
+        SYNCHRONIZE,
+        core::ptr::null_mut(),
+        KernelMode as _,
+        &mut event,
+        core::ptr::null_mut(),
+    )
+    .into_result()
+    {
+        ZwClose(handle);
+        return Err(e);
+    }
+
+    Ok((handle, event as _))
+}
+
+/// Releases an event created with [`create_event`].
+unsafe fn close_event(handle: HANDLE, event: *mut KEVENT) {
+    ObDereferenceObject(event as _);
+    ZwClose(handle);
+}
+
+/// Creates the thread of a watcher, and returns a reference to the thread object. The thread runs
+/// as soon as it has been created, so if no reference can be obtained, it is stopped and waited
+/// for before returning, such that the caller can release `inner`.
+unsafe fn create_thread(inner: &mut WatcherInner) -> Result<*mut core::ffi::c_void, Error> {
+    let mut attrs: OBJECT_ATTRIBUTES = core::mem::zeroed();
+    attrs.Length = core::mem::size_of::<OBJECT_ATTRIBUTES>() as u32;
+    attrs.Attributes = ObjectFlags::KERNEL_HANDLE.bits();
+
+    let mut handle: HANDLE = core::ptr::null_mut();
+
+    PsCreateSystemThread(
+        &mut handle,
+        SYNCHRONIZE,
+        &mut attrs,
+        core::ptr::null_mut(),
+        core::ptr::null_mut(),
+        Some(WatcherInner::thread_routine),
+        inner as *mut WatcherInner as _,
+    )
+    .into_result()?;
+
+    let mut thread = core::ptr::null_mut();
+
+    let status = ObReferenceObjectByHandle(
+        handle,
+        SYNCHRONIZE,
+        core::ptr::null_mut(),
+        KernelMode as _,
+        &mut thread,
+        core::ptr::null_mut(),
+    );
+
+    if let Err(e) = status.into_result() {
+        KeSetEvent(inner.stop.get(), 0, false as _);
+        ZwWaitForSingleObject(handle, false as _, core::ptr::null_mut());
+        ZwClose(handle);
+
+        return Err(e);
+    }
+
+    ZwClose(handle);
+
+    Ok(thread)
+}
+
+/// The state shared between a [`RegistryChanges`] stream and the callback of its watcher.
+struct ChangesShared {
+    pending: AtomicUsize,
+    waker: AtomicWaker,
+}
+
+/// A [`Stream`] that yields once for every batch of changes to a registry key, built on top of
+/// [`RegistryWatcher`]. Changes that happen while the stream is not polled are coalesced.
+pub struct RegistryChanges {
+    shared: Arc<ChangesShared>,
+    _watcher: RegistryWatcher,
+}
+
+impl RegistryChanges {
+    /// Starts watching the given key, which must have been opened with
+    /// [`RegistryAccess::NOTIFY`].
+    pub fn new(key: RegistryKey, filter: NotifyFilter, watch_tree: bool) -> Result<Self, Error> {
+        let shared = Arc::new(ChangesShared {
+            pending: AtomicUsize::new(0),
+            waker: AtomicWaker::new(),
+        });
+
+        let callback_shared = shared.clone();
+
+        let watcher = RegistryWatcher::new(key, filter, watch_tree, move || {
+            callback_shared.pending.fetch_add(1, Ordering::Release);
+            callback_shared.waker.wake();
+        })?;
+
+        Ok(Self {
+            shared,
+            _watcher: watcher,
+        })
+    }
+}
+
+impl Stream for RegistryChanges {
+    type Item = ();
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<()>> {
+        self.shared.waker.register(cx.waker());
+
+        if self.shared.pending.swap(0, Ordering::Acquire) != 0 {
+            Poll::Ready(Some(()))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16(s: &str) -> Vec<u8> {
+        s.encode_utf16().flat_map(|c| c.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn decodes_integers() {
+        assert_eq!(
+            RegistryValue::decode(REG_DWORD, &[0x78, 0x56, 0x34, 0x12]),
+            Ok(RegistryValue::Dword(0x1234_5678))
+        );
+        assert_eq!(
+            RegistryValue::decode(REG_QWORD, &1u64.to_le_bytes()),
+            Ok(RegistryValue::Qword(1))
+        );
+        assert_eq!(
+            RegistryValue::decode(REG_DWORD, &[0; 3]),
+            Err(Error::INVALID_PARAMETER)
+        );
+        assert_eq!(
+            RegistryValue::decode(REG_QWORD, &[0; 4]),
+            Err(Error::INVALID_PARAMETER)
+        );
+    }
+
+    #[test]
+    fn decodes_strings_up_to_nul() {
+        let mut data = utf16("abc");
+        data.extend_from_slice(&[0, 0]);
+        data.extend(utf16("garbage"));
+
+        assert_eq!(
+            RegistryValue::decode(REG_SZ, &data),
+            Ok(RegistryValue::String("abc".into()))
+        );
+        assert_eq!(
+            RegistryValue::decode(REG_EXPAND_SZ, &utf16("%SystemRoot%")),
+            Ok(RegistryValue::ExpandString("%SystemRoot%".into()))
+        );
+    }
+
+    #[test]
+    fn decodes_multi_strings() {
+        let mut data = utf16("one");
+        data.extend_from_slice(&[0, 0]);
+        data.extend(utf16("two"));
+        data.extend_from_slice(&[0, 0, 0, 0]);
+
+        assert_eq!(
+            RegistryValue::decode(REG_MULTI_SZ, &data),
+            Ok(RegistryValue::MultiString(vec!["one".into(), "two".into()]))
+        );
+        assert_eq!(
+            RegistryValue::decode(REG_MULTI_SZ, &[0, 0]),
+            Ok(RegistryValue::MultiString(Vec::new()))
+        );
+    }
+
+    #[test]
+    fn round_trips_values() {
+        let values = [
+            RegistryValue::Dword(7),
+            RegistryValue::Qword(u64::MAX),
+            RegistryValue::String("value".into()),
+            RegistryValue::ExpandString("%TEMP%".into()),
+            RegistryValue::MultiString(vec!["a".into(), "bc".into()]),
+            RegistryValue::Binary(vec![1, 2, 3]),
+            RegistryValue::Other(0x1234, vec![4, 5]),
+        ];
+
+        for value in values {
+            let (ty, data) = value.encode().unwrap();
+
+            assert_eq!(ty, value.value_type());
+            assert_eq!(RegistryValue::decode(ty, &data), Ok(value));
+        }
+    }
+
+    #[test]
+    fn rejects_unencodable_strings() {
+        assert_eq!(
+            RegistryValue::String("a\0b".into()).encode(),
+            Err(Error::INVALID_PARAMETER)
+        );
+        assert_eq!(
+            RegistryValue::MultiString(vec!["a".into(), String::new()]).encode(),
+            Err(Error::INVALID_PARAMETER)
+        );
+    }
+
+    #[test]
+    fn decodes_partial_information() {
+        let mut buffer = vec![0u8; 12];
+        buffer[4..8].copy_from_slice(&REG_DWORD.to_le_bytes());
+        buffer[8..12].copy_from_slice(&4u32.to_le_bytes());
+        buffer.extend_from_slice(&42u32.to_le_bytes());
+
+        assert_eq!(
+            RegistryValue::from_partial_information(&buffer),
+            Ok(RegistryValue::Dword(42))
+        );
+        assert_eq!(
+            RegistryValue::from_partial_information(&buffer[..14]),
+            Err(Error::INVALID_PARAMETER)
+        );
+    }
+
+    #[test]
+    fn decodes_full_information() {
+        let name = utf16("Start");
+        let data_offset = 20 + name.len();
+
+        let mut buffer = vec![0u8; 20];
+        buffer[4..8].copy_from_slice(&REG_DWORD.to_le_bytes());
+        buffer[8..12].copy_from_slice(&(data_offset as u32).to_le_bytes());
+        buffer[12..16].copy_from_slice(&4u32.to_le_bytes());
+        buffer[16..20].copy_from_slice(&(name.len() as u32).to_le_bytes());
+        buffer.extend(name);
+        buffer.extend_from_slice(&3u32.to_le_bytes());
+
+        assert_eq!(
+            decode_value_full_information(&buffer),
+            Ok(("Start".into(), RegistryValue::Dword(3)))
+        );
+
+        buffer[12..16].copy_from_slice(&8u32.to_le_bytes());
+
+        assert_eq!(
+            decode_value_full_information(&buffer),
+            Err(Error::INVALID_PARAMETER)
+        );
+    }
+
+    #[test]
+    fn decodes_key_basic_information() {
+        let name = utf16("Parameters");
+
+        let mut buffer = vec![0u8; 16];
+        buffer[12..16].copy_from_slice(&(name.len() as u32).to_le_bytes());
+        buffer.extend(name);
+
+        assert_eq!(decode_key_basic_information(&buffer), Ok("Parameters".into()));
+        assert_eq!(
+            decode_key_basic_information(&buffer[..20]),
+            Err(Error::INVALID_PARAMETER)
+        );
+    }
+}
@@ -1,7 +1,5 @@
 //! section mod
 use bitflags::bitflags;
-use widestring::U16CString;
-
 use win_kernel_sys::base::{HANDLE, LARGE_INTEGER, OBJECT_ATTRIBUTES};
 use win_kernel_sys::ntoskrnl::{
     ZwClose, ZwMapViewOfSection, ZwOpenSection, ZwUnmapViewOfSection,
@@ -9,7 +7,7 @@ use win_kernel_sys::ntoskrnl::{
 
 use crate::error::{Error, IntoResult};
 use crate::process::ZwProcess;
-use crate::string::create_unicode_string;
+use crate::string::OwnedUnicodeString;
 
 bitflags! {
     pub struct AllocationFlags: u32 {
@@ -64,13 +62,13 @@ unsafe impl Sync for Section {}
 
 impl Section {
     pub fn open(path: &str, obj_flags: ObjectFlags, access: SectionAccess) -> Result<Self, Error> {
-        let name = U16CString::from_str(path).unwrap();
-        let mut name = create_unicode_string(name.as_slice());
+        let name = OwnedUnicodeString::try_from(path)?;
+        let mut name = name.as_unicode_str();
 
         let mut attrs = OBJECT_ATTRIBUTES {
             Length: core::mem::size_of::<OBJECT_ATTRIBUTES>() as u32,
             RootDirectory: core::ptr::null_mut(),
-            ObjectName: &mut name,
+            ObjectName: name.as_mut_ptr(),
             Attributes: obj_flags.bits(),
             SecurityDescriptor: core::ptr::null_mut(),
             SecurityQualityOfService: core::ptr::null_mut(),
@@ -0,0 +1,50 @@
+/*
+ * Wrappers around kernel routines that raise exceptions on invalid input. Each wrapper runs the
+ * routine in a __try/__except block and returns the exception code instead of raising it.
+ */
+
+typedef long NTSTATUS;
+typedef char KPROCESSOR_MODE;
+
+#define STATUS_SUCCESS ((NTSTATUS)0x00000000L)
+#define STATUS_INSUFFICIENT_RESOURCES ((NTSTATUS)0xC000009AL)
+#define EXCEPTION_EXECUTE_HANDLER 1
+
+unsigned long __cdecl _exception_code(void);
+
+void MmProbeAndLockPages(void *MemoryDescriptorList, KPROCESSOR_MODE AccessMode, int Operation);
+void *MmMapLockedPagesSpecifyCache(void *MemoryDescriptorList, KPROCESSOR_MODE AccessMode,
+                                   int CacheType, void *RequestedAddress,
+                                   unsigned char BugCheckOnFailure, unsigned long Priority);
+
+NTSTATUS probe_and_lock_pages_safe(void *MemoryDescriptorList, KPROCESSOR_MODE AccessMode,
+                                   int Operation)
+{
+    __try {
+        MmProbeAndLockPages(MemoryDescriptorList, AccessMode, Operation);
+    } __except (EXCEPTION_EXECUTE_HANDLER) {
+        return (NTSTATUS)_exception_code();
+    }
+
+    return STATUS_SUCCESS;
+}
+
+/*
+ * User mode mappings raise an exception on failure instead of returning NULL, which only kernel
+ * mode mappings do.
+ */
+NTSTATUS map_locked_pages_safe(void *MemoryDescriptorList, KPROCESSOR_MODE AccessMode,
+                               int CacheType, void *RequestedAddress, unsigned long Priority,
+                               void **MappedAddress)
+{
+    *MappedAddress = 0;
+
+    __try {
+        *MappedAddress = MmMapLockedPagesSpecifyCache(MemoryDescriptorList, AccessMode, CacheType,
+                                                      RequestedAddress, 0, Priority);
+    } __except (EXCEPTION_EXECUTE_HANDLER) {
+        return (NTSTATUS)_exception_code();
+    }
+
+    return *MappedAddress ? STATUS_SUCCESS : STATUS_INSUFFICIENT_RESOURCES;
+}
@@ -1,13 +1,476 @@
 //! string mod
+//!
+//! [`UnicodeStr`] is a borrowed view of UTF-16 data as a [`UNICODE_STRING`], and
+//! [`OwnedUnicodeString`] owns its buffer. Both check that the length fits in the 16-bit byte
+//! count of a [`UNICODE_STRING`], rather than silently truncating it. [`UnicodeStringBuf`] and
+//! [`AnsiStringBuf`] format into a fixed buffer on the stack without allocating.
 
 use alloc::string::String;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::str::FromStr;
 
-use widestring::U16CString;
+use smallvec::SmallVec;
+use widestring::{U16CStr, U16CString};
 
-use win_kernel_sys::base::UNICODE_STRING;
+use win_kernel_sys::base::{ANSI_STRING, UNICODE_STRING};
 
+use crate::error::Error;
+
+/// The maximum number of UTF-16 code units a [`UNICODE_STRING`] can describe, as its `Length` is
+/// a 16-bit byte count.
+pub const MAX_UNICODE_STRING_LEN: usize = (u16::MAX / 2) as usize;
+
+/// A borrowed [`UNICODE_STRING`] that points into UTF-16 data living for `'a`. The data does not
+/// have to be NUL-terminated.
+#[repr(transparent)]
+#[derive(Clone, Copy)]
+pub struct UnicodeStr<'a> {
+    raw: UNICODE_STRING,
+    _marker: PhantomData<&'a [u16]>,
+}
+
+impl<'a> UnicodeStr<'a> {
+    /// Creates a view of the given UTF-16 data, fails with [`Error::NAME_TOO_LONG`] if the data
+    /// does not fit in a [`UNICODE_STRING`].
+    pub fn new(s: &'a [u16]) -> Result<Self, Error> {
+        Self::with_capacity(s, s.len())
+    }
+
+    /// Creates a view whose `MaximumLength` covers `capacity` code units of `s`.
+    fn with_capacity(s: &'a [u16], capacity: usize) -> Result<Self, Error> {
+        if s.len() > MAX_UNICODE_STRING_LEN {
+            return Err(Error::NAME_TOO_LONG);
+        }
+
+        let capacity = core::cmp::min(capacity, MAX_UNICODE_STRING_LEN);
+
+        Ok(Self {
+            raw: UNICODE_STRING {
+                Length: (s.len() * 2) as u16,
+                MaximumLength: (capacity * 2) as u16,
+                Buffer: s.as_ptr() as _,
+            },
+            _marker: PhantomData,
+        })
+    }
+
+    /// Creates a view of an existing [`UNICODE_STRING`]. An odd `Length` is rounded down to whole
+    /// code units, and a `Length` greater than `MaximumLength` is clamped to the latter.
+    ///
+    /// # Safety
+    ///
+    /// The buffer of `raw` must be valid for `Length` bytes for `'a`.
+    pub unsafe fn from_raw(raw: &'a UNICODE_STRING) -> Self {
+        Self {
+            raw: UNICODE_STRING {
+                Length: core::cmp::min(raw.Length, raw.MaximumLength) & !1,
+                MaximumLength: raw.MaximumLength,
+                Buffer: raw.Buffer,
+            },
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the UTF-16 code units, as described by `Length`.
+    pub fn as_slice(&self) -> &'a [u16] {
+        if self.raw.Buffer.is_null() || self.raw.Length == 0 {
+            &[]
+        } else {
+            unsafe {
+                core::slice::from_raw_parts(self.raw.Buffer, self.raw.Length as usize / 2)
+            }
+        }
+    }
+
+    /// Returns the number of UTF-16 code units.
+    pub fn len(&self) -> usize {
+        self.raw.Length as usize / 2
+    }
+
+    /// Returns whether the string is empty.
+    pub fn is_empty(&self) -> bool {
+        self.raw.Length == 0
+    }
+
+    /// Returns the underlying [`UNICODE_STRING`].
+    pub fn as_raw(&self) -> &UNICODE_STRING {
+        &self.raw
+    }
+
+    /// Returns a pointer to the underlying [`UNICODE_STRING`], for functions that take a
+    /// `PUNICODE_STRING` they do not modify.
+    pub fn as_mut_ptr(&mut self) -> *mut UNICODE_STRING {
+        &mut self.raw
+    }
+
+    /// Converts the string to a [`String`], fails with [`Error::INVALID_PARAMETER`] on invalid
+    /// UTF-16.
+    pub fn to_string(&self) -> Result<String, Error> {
+        char::decode_utf16(self.as_slice().iter().copied())
+            .collect::<Result<String, _>>()
+            .map_err(|_| Error::INVALID_PARAMETER)
+    }
+
+    /// Converts the string to a [`String`], replacing invalid UTF-16 with
+    /// [`char::REPLACEMENT_CHARACTER`].
+    pub fn to_string_lossy(&self) -> String {
+        char::decode_utf16(self.as_slice().iter().copied())
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect()
+    }
+
+    /// Copies the string into an [`OwnedUnicodeString`].
+    pub fn to_unicode_string(&self) -> OwnedUnicodeString {
+        // The length has already been checked.
+        OwnedUnicodeString::from_slice(self.as_slice()).unwrap()
+    }
+}
+
+impl<'a> TryFrom<&'a [u16]> for UnicodeStr<'a> {
+    type Error = Error;
+
+    fn try_from(value: &'a [u16]) -> Result<Self, Self::Error> {
+        Self::new(value)
+    }
+}
+
+impl<'a> TryFrom<&'a U16CStr> for UnicodeStr<'a> {
+    type Error = Error;
+
+    fn try_from(value: &'a U16CStr) -> Result<Self, Self::Error> {
+        Self::with_capacity(value.as_slice(), value.len() + 1)
+    }
+}
+
+impl<'a> TryFrom<UnicodeStr<'a>> for U16CString {
+    type Error = Error;
+
+    /// Fails with [`Error::INVALID_PARAMETER`] if the string contains a NUL character.
+    fn try_from(value: UnicodeStr<'a>) -> Result<Self, Self::Error> {
+        U16CString::from_vec(value.as_slice()).map_err(|_| Error::INVALID_PARAMETER)
+    }
+}
+
+impl<'a> PartialEq for UnicodeStr<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl<'a> Eq for UnicodeStr<'a> {}
+
+impl<'a> core::fmt::Debug for UnicodeStr<'a> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Debug::fmt(&self.to_string_lossy(), f)
+    }
+}
+
+/// An owned, NUL-terminated UTF-16 buffer that can be viewed as a [`UNICODE_STRING`] through
+/// [`OwnedUnicodeString::as_unicode_str`].
+#[derive(Clone, PartialEq, Eq)]
+pub struct OwnedUnicodeString {
+    /// The code units, followed by a NUL terminator that is not part of the string.
+    buffer: Vec<u16>,
+}
+
+impl OwnedUnicodeString {
+    /// Copies the given UTF-16 data, fails with [`Error::NAME_TOO_LONG`] if it does not fit in a
+    /// [`UNICODE_STRING`].
+    pub fn from_slice(s: &[u16]) -> Result<Self, Error> {
+        if s.len() > MAX_UNICODE_STRING_LEN {
+            return Err(Error::NAME_TOO_LONG);
+        }
+
+        let mut buffer = Vec::with_capacity(s.len() + 1);
+        buffer.extend_from_slice(s);
+        buffer.push(0);
+
+        Ok(Self { buffer })
+    }
+
+    /// Encodes the given string as UTF-16, fails with [`Error::NAME_TOO_LONG`] if it does not fit
+    /// in a [`UNICODE_STRING`].
+    fn encode(s: &str) -> Result<Self, Error> {
+        let mut buffer: Vec<u16> = s.encode_utf16().collect();
+
+        if buffer.len() > MAX_UNICODE_STRING_LEN {
+            return Err(Error::NAME_TOO_LONG);
+        }
+
+        buffer.push(0);
+
+        Ok(Self { buffer })
+    }
+
+    /// Returns a borrowed [`UNICODE_STRING`] view of the buffer.
+    pub fn as_unicode_str(&self) -> UnicodeStr<'_> {
+        // The length has been checked upon construction.
+        UnicodeStr::with_capacity(self.as_slice(), self.buffer.len()).unwrap()
+    }
+
+    /// Returns the UTF-16 code units, without the NUL terminator.
+    pub fn as_slice(&self) -> &[u16] {
+        &self.buffer[..self.buffer.len() - 1]
+    }
+
+    /// Returns the number of UTF-16 code units.
+    pub fn len(&self) -> usize {
+        self.buffer.len() - 1
+    }
+
+    /// Returns whether the string is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Converts the string to a [`String`], fails with [`Error::INVALID_PARAMETER`] on invalid
+    /// UTF-16.
+    pub fn to_string(&self) -> Result<String, Error> {
+        self.as_unicode_str().to_string()
+    }
+
+    /// Converts the string to a [`String`], replacing invalid UTF-16 with
+    /// [`char::REPLACEMENT_CHARACTER`].
+    pub fn to_string_lossy(&self) -> String {
+        self.as_unicode_str().to_string_lossy()
+    }
+}
+
+impl FromStr for OwnedUnicodeString {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::encode(s)
+    }
+}
+
+impl TryFrom<&str> for OwnedUnicodeString {
+    type Error = Error;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Self::encode(value)
+    }
+}
+
+impl TryFrom<&String> for OwnedUnicodeString {
+    type Error = Error;
+
+    fn try_from(value: &String) -> Result<Self, Self::Error> {
+        Self::encode(value)
+    }
+}
+
+impl TryFrom<String> for OwnedUnicodeString {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::encode(&value)
+    }
+}
+
+impl TryFrom<&U16CStr> for OwnedUnicodeString {
+    type Error = Error;
+
+    fn try_from(value: &U16CStr) -> Result<Self, Self::Error> {
+        Self::from_slice(value.as_slice())
+    }
+}
+
+impl TryFrom<U16CString> for OwnedUnicodeString {
+    type Error = Error;
+
+    fn try_from(value: U16CString) -> Result<Self, Self::Error> {
+        if value.len() > MAX_UNICODE_STRING_LEN {
+            return Err(Error::NAME_TOO_LONG);
+        }
+
+        Ok(Self {
+            buffer: value.into_vec_with_nul(),
+        })
+    }
+}
+
+impl TryFrom<&OwnedUnicodeString> for String {
+    type Error = Error;
+
+    fn try_from(value: &OwnedUnicodeString) -> Result<Self, Self::Error> {
+        value.to_string()
+    }
+}
+
+impl TryFrom<OwnedUnicodeString> for U16CString {
+    type Error = Error;
+
+    /// Fails with [`Error::INVALID_PARAMETER`] if the string contains a NUL character.
+    fn try_from(value: OwnedUnicodeString) -> Result<Self, Self::Error> {
+        U16CString::from_vec(value.as_slice()).map_err(|_| Error::INVALID_PARAMETER)
+    }
+}
+
+impl<'a> From<UnicodeStr<'a>> for OwnedUnicodeString {
+    fn from(value: UnicodeStr<'a>) -> Self {
+        value.to_unicode_string()
+    }
+}
+
+impl core::fmt::Debug for OwnedUnicodeString {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Debug::fmt(&self.to_string_lossy(), f)
+    }
+}
+
+/// A [`core::fmt::Write`] sink that encodes into a fixed buffer of `N` UTF-16 code units, and
+/// that can be viewed as a [`UNICODE_STRING`] without allocating. Once the buffer is full, writes
+/// fail with [`core::fmt::Error`] and the text that did fit is kept.
+pub struct UnicodeStringBuf<const N: usize> {
+    buffer: SmallVec<[u16; N]>,
+}
+
+impl<const N: usize> UnicodeStringBuf<N> {
+    /// Creates an empty buffer.
+    pub fn new() -> Self {
+        Self {
+            buffer: SmallVec::new(),
+        }
+    }
+
+    /// Returns the number of code units that fit in the buffer.
+    pub const fn capacity(&self) -> usize {
+        if N < MAX_UNICODE_STRING_LEN {
+            N
+        } else {
+            MAX_UNICODE_STRING_LEN
+        }
+    }
+
+    /// Returns the UTF-16 code units written so far.
+    pub fn as_slice(&self) -> &[u16] {
+        &self.buffer
+    }
+
+    /// Returns the number of code units written so far.
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Returns whether nothing has been written so far.
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// Discards the contents of the buffer.
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+    }
+
+    /// Returns a [`UNICODE_STRING`] view of the contents.
+    pub fn as_unicode_str(&self) -> UnicodeStr<'_> {
+        // The length never exceeds the capacity.
+        UnicodeStr::new(&self.buffer).unwrap()
+    }
+}
+
+impl<const N: usize> Default for UnicodeStringBuf<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> core::fmt::Write for UnicodeStringBuf<N> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for c in s.chars() {
+            let mut units = [0; 2];
+            let units = c.encode_utf16(&mut units);
+
+            // Never spill to the heap, and never split a surrogate pair.
+            if self.buffer.len() + units.len() > self.capacity() {
+                return Err(core::fmt::Error);
+            }
+
+            self.buffer.extend_from_slice(units);
+        }
+
+        Ok(())
+    }
+}
+
+/// A [`core::fmt::Write`] sink that writes into a fixed buffer of `N` bytes, and that can be
+/// viewed as an [`ANSI_STRING`] without allocating. Once the buffer is full, writes fail with
+/// [`core::fmt::Error`] and the text that did fit is kept.
+pub struct AnsiStringBuf<const N: usize> {
+    buffer: SmallVec<[u8; N]>,
+}
+
+impl<const N: usize> AnsiStringBuf<N> {
+    /// Creates an empty buffer.
+    pub fn new() -> Self {
+        Self {
+            buffer: SmallVec::new(),
+        }
+    }
+
+    /// Returns the number of bytes that fit in the buffer.
+    pub const fn capacity(&self) -> usize {
+        if N < u16::MAX as usize {
+            N
+        } else {
+            u16::MAX as usize
+        }
+    }
+
+    /// Returns the text written so far.
+    pub fn as_str(&self) -> &str {
+        // Only whole characters are ever written.
+        unsafe { core::str::from_utf8_unchecked(&self.buffer) }
+    }
+
+    /// Discards the contents of the buffer.
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+    }
+
+    /// Returns an [`ANSI_STRING`] describing the contents, which is valid as long as the buffer
+    /// is neither moved nor modified.
+    pub fn as_ansi_string(&self) -> ANSI_STRING {
+        ANSI_STRING {
+            Length: self.buffer.len() as u16,
+            MaximumLength: self.buffer.len() as u16,
+            Buffer: self.buffer.as_ptr() as _,
+        }
+    }
+}
+
+impl<const N: usize> Default for AnsiStringBuf<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> core::fmt::Write for AnsiStringBuf<N> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let available = self.capacity() - self.buffer.len();
+
+        if s.len() <= available {
+            self.buffer.extend_from_slice(s.as_bytes());
+            return Ok(());
+        }
+
+        // Never spill to the heap, and never split a character.
+        let mut end = available;
+
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        self.buffer.extend_from_slice(&s.as_bytes()[..end]);
+
+        Err(core::fmt::Error)
+    }
+}
 
 /// create a [UNICODE_STRING] from a `&[u16]`
+#[deprecated(note = "truncates lengths over 32K code units, use `UnicodeStr::new` instead")]
 pub fn create_unicode_string(s: &[u16]) -> UNICODE_STRING {
     let len = s.len();
 
@@ -24,14 +487,214 @@ pub fn create_unicode_string(s: &[u16]) -> UNICODE_STRING {
     }
 }
 
-
-/// create a [UNICODE_STRING] from a `String`
+/// create a [UNICODE_STRING] from a `String`. A [UNICODE_STRING] cannot own its buffer, so the
+/// buffer is leaked.
+#[deprecated(note = "leaks its buffer, use `OwnedUnicodeString` instead")]
 pub fn create_unicode_from_str(name: &str) -> UNICODE_STRING {
     let name = U16CString::from_str(name).unwrap();
-    create_unicode_string(name.as_slice())
+
+    #[allow(deprecated)]
+    create_unicode_string(name.into_vec_with_nul().leak())
 }
 
-/// string from a unicode string
+/// string from a unicode string, reading exactly `Length` bytes as a [UNICODE_STRING] does not
+/// have to be NUL-terminated
 pub fn from_unicode_string(s: &UNICODE_STRING) -> String {
-    unsafe { U16CString::from_ptr_str(s.Buffer).to_string_lossy() }
+    unsafe { UnicodeStr::from_raw(s) }.to_string_lossy()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use alloc::vec;
+    use core::fmt::Write;
+
+    fn utf16(s: &str) -> Vec<u16> {
+        s.encode_utf16().collect()
+    }
+
+    #[test]
+    fn unicode_buf_truncates_at_capacity() {
+        let mut buf = UnicodeStringBuf::<4>::new();
+
+        assert!(write!(buf, "abc").is_ok());
+        assert!(write!(buf, "def").is_err());
+        assert_eq!(buf.as_slice(), utf16("abcd").as_slice());
+        assert_eq!(buf.as_unicode_str().as_raw().Length, 8);
+
+        buf.clear();
+        assert!(buf.is_empty());
+        assert!(write!(buf, "wxyz").is_ok());
+        assert_eq!(buf.len(), 4);
+    }
+
+    #[test]
+    fn unicode_buf_never_splits_a_surrogate_pair() {
+        let mut buf = UnicodeStringBuf::<4>::new();
+
+        assert!(write!(buf, "abc\u{1F600}").is_err());
+        assert_eq!(buf.as_slice(), utf16("abc").as_slice());
+    }
+
+    #[test]
+    fn ansi_buf_truncates_at_capacity() {
+        let mut buf = AnsiStringBuf::<4>::new();
+
+        assert!(write!(buf, "ab").is_ok());
+        assert!(write!(buf, "cdef").is_err());
+        assert_eq!(buf.as_str(), "abcd");
+        assert_eq!(buf.as_ansi_string().Length, 4);
+    }
+
+    #[test]
+    fn ansi_buf_never_splits_a_character() {
+        let mut buf = AnsiStringBuf::<4>::new();
+
+        assert!(write!(buf, "ab\u{e9}\u{e9}").is_err());
+        assert_eq!(buf.as_str(), "ab\u{e9}");
+    }
+
+    #[test]
+    fn from_unicode_string_reads_length_bytes() {
+        // Neither a NUL terminator nor a NUL within `Length` ends the string early.
+        let data = utf16("abc\0def");
+
+        let raw = UNICODE_STRING {
+            Length: 4,
+            MaximumLength: (data.len() * 2) as u16,
+            Buffer: data.as_ptr() as _,
+        };
+        assert_eq!(from_unicode_string(&raw), "ab");
+
+        let raw = UNICODE_STRING {
+            Length: (data.len() * 2) as u16,
+            MaximumLength: (data.len() * 2) as u16,
+            Buffer: data.as_ptr() as _,
+        };
+        assert_eq!(from_unicode_string(&raw), "abc\0def");
+    }
+
+    #[test]
+    fn checks_the_maximum_length() {
+        let data = vec![b'a' as u16; MAX_UNICODE_STRING_LEN + 1];
+
+        let s = UnicodeStr::new(&data[..MAX_UNICODE_STRING_LEN]).unwrap();
+        assert_eq!(s.as_raw().Length, (MAX_UNICODE_STRING_LEN * 2) as u16);
+        assert_eq!(s.len(), MAX_UNICODE_STRING_LEN);
+
+        assert_eq!(UnicodeStr::new(&data).unwrap_err(), Error::NAME_TOO_LONG);
+        assert_eq!(
+            OwnedUnicodeString::from_slice(&data).unwrap_err(),
+            Error::NAME_TOO_LONG
+        );
+
+        let text: String = core::iter::repeat('a').take(MAX_UNICODE_STRING_LEN + 1).collect();
+        assert_eq!(
+            OwnedUnicodeString::try_from(text.as_str()).unwrap_err(),
+            Error::NAME_TOO_LONG
+        );
+        assert_eq!(
+            OwnedUnicodeString::try_from(&text[1..]).unwrap().len(),
+            MAX_UNICODE_STRING_LEN
+        );
+    }
+
+    #[test]
+    fn rounds_odd_lengths_down() {
+        let data = utf16("abc");
+
+        let raw = UNICODE_STRING {
+            Length: 5,
+            MaximumLength: 6,
+            Buffer: data.as_ptr() as _,
+        };
+        let s = unsafe { UnicodeStr::from_raw(&raw) };
+
+        assert_eq!(s.as_raw().Length, 4);
+        assert_eq!(s.as_slice(), utf16("ab").as_slice());
+    }
+
+    #[test]
+    fn clamps_length_to_maximum_length() {
+        let data = utf16("abcdef");
+
+        let raw = UNICODE_STRING {
+            Length: 12,
+            MaximumLength: 6,
+            Buffer: data.as_ptr() as _,
+        };
+        let s = unsafe { UnicodeStr::from_raw(&raw) };
+
+        assert_eq!(s.len(), 3);
+        assert_eq!(s.to_string().unwrap(), "abc");
+        assert_eq!(from_unicode_string(&raw), "abc");
+    }
+
+    #[test]
+    fn converts_from_str() {
+        let owned = OwnedUnicodeString::from_str("C:\\Windows").unwrap();
+        assert_eq!(owned.as_slice(), utf16("C:\\Windows").as_slice());
+        assert_eq!(owned.to_string().unwrap(), "C:\\Windows");
+
+        let view = owned.as_unicode_str();
+        assert_eq!(view.as_raw().Length, 20);
+        assert_eq!(view.as_raw().MaximumLength, 22);
+
+        assert_eq!(
+            OwnedUnicodeString::try_from(String::from("x")).unwrap(),
+            OwnedUnicodeString::try_from("x").unwrap()
+        );
+        assert!(OwnedUnicodeString::try_from("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn converts_from_u16() {
+        let data = utf16("name");
+
+        let s = UnicodeStr::try_from(data.as_slice()).unwrap();
+        assert_eq!(s.as_raw().Length, 8);
+        assert_eq!(s.as_raw().MaximumLength, 8);
+        assert_eq!(s.to_unicode_string().as_slice(), data.as_slice());
+
+        let c = U16CString::from_vec(data.clone()).unwrap();
+
+        let s = UnicodeStr::try_from(c.as_ucstr()).unwrap();
+        assert_eq!(s.as_raw().Length, 8);
+        assert_eq!(s.as_raw().MaximumLength, 10);
+
+        let owned = OwnedUnicodeString::try_from(c.clone()).unwrap();
+        assert_eq!(U16CString::try_from(owned).unwrap(), c);
+    }
+
+    #[test]
+    fn rejects_invalid_conversions() {
+        let data = utf16("a\0b");
+        let s = UnicodeStr::new(&data).unwrap();
+
+        assert_eq!(
+            U16CString::try_from(s).unwrap_err(),
+            Error::INVALID_PARAMETER
+        );
+
+        let unpaired = [0xD800, b'a' as u16];
+        let s = UnicodeStr::new(&unpaired).unwrap();
+
+        assert_eq!(s.to_string().unwrap_err(), Error::INVALID_PARAMETER);
+        assert_eq!(s.to_string_lossy(), "\u{FFFD}a");
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn deprecated_constructors_still_work() {
+        let raw = create_unicode_from_str("abc");
+        assert_eq!(raw.Length, 6);
+        assert_eq!(raw.MaximumLength, 8);
+        assert_eq!(from_unicode_string(&raw), "abc");
+
+        let data = utf16("ab\0");
+        let raw = create_unicode_string(&data);
+        assert_eq!(raw.Length, 4);
+        assert_eq!(raw.MaximumLength, 6);
+    }
 }
@@ -1,41 +1,179 @@
-//! make SymbolicLink 
+//! make SymbolicLink
 
-use widestring::U16CString;
+use alloc::format;
+use alloc::vec;
 
+use win_kernel_sys::base::{
+    GENERIC_READ, HANDLE, OBJECT_ATTRIBUTES, STATUS_BUFFER_TOO_SMALL, UNICODE_STRING,
+};
 use win_kernel_sys::ntoskrnl::{IoCreateSymbolicLink, IoDeleteSymbolicLink};
+use win_kernel_sys::ntoskrnl::{ZwClose, ZwOpenSymbolicLinkObject, ZwQuerySymbolicLinkObject};
 
 use crate::error::{Error, IntoResult};
-use crate::string::create_unicode_string;
+use crate::section::ObjectFlags;
+use crate::string::{OwnedUnicodeString, UnicodeStr};
 
+/// Where a DOS device name is created, see [`SymbolicLink::new_dos_device`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkScope {
+    /// `\DosDevices\Global\`, visible to every session.
+    Global,
+    /// `\DosDevices\`, which resolves to the DOS devices directory of the session of the calling
+    /// process, and only falls back to the global directory when called from a system thread.
+    Session,
+}
+
+impl LinkScope {
+    /// Returns the directory of the DOS device names for the scope.
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            LinkScope::Global => "\\DosDevices\\Global\\",
+            LinkScope::Session => "\\DosDevices\\",
+        }
+    }
+}
 
-/// A symbolic link. 
+/// A symbolic link.
 pub struct SymbolicLink {
-    name: U16CString,
+    name: OwnedUnicodeString,
 }
 
 impl SymbolicLink {
     /// new SymbolicLink
     pub fn new(name: &str, target: &str) -> Result<Self, Error> {
-        // Convert the name to UTF-16 and then create a UNICODE_STRING.
-        let name = U16CString::from_str(name).unwrap();
-        let mut name_ptr = create_unicode_string(name.as_slice());
+        // Convert the name and the target to UTF-16 and then create a UNICODE_STRING.
+        let name = OwnedUnicodeString::try_from(name)?;
+        let mut name_ptr = name.as_unicode_str();
 
-        // Convert the target to UTF-16 and then create a UNICODE_STRING.
-        let target = U16CString::from_str(target).unwrap();
-        let mut target_ptr = create_unicode_string(target.as_slice());
+        let target = OwnedUnicodeString::try_from(target)?;
+        let mut target_ptr = target.as_unicode_str();
 
-        unsafe { IoCreateSymbolicLink(&mut name_ptr, &mut target_ptr) }.into_result()?;
+        unsafe { IoCreateSymbolicLink(name_ptr.as_mut_ptr(), target_ptr.as_mut_ptr()) }
+            .into_result()?;
 
         Ok(Self { name })
     }
+
+    /// Creates a DOS device name in the given scope, e.g. `MyDriver` with [`LinkScope::Global`]
+    /// creates `\DosDevices\Global\MyDriver`.
+    pub fn new_dos_device(name: &str, target: &str, scope: LinkScope) -> Result<Self, Error> {
+        if name.is_empty() || name.contains('\\') {
+            return Err(Error::OBJECT_NAME_INVALID);
+        }
+
+        Self::new(&format!("{}{}", scope.prefix(), name), target)
+    }
+
+    /// Returns the full name of the link.
+    pub fn name(&self) -> &OwnedUnicodeString {
+        &self.name
+    }
+
+    /// Queries the target of the link.
+    pub fn target(&self) -> Result<OwnedUnicodeString, Error> {
+        SymbolicLinkObject::open_unicode(self.name.as_unicode_str())?.target()
+    }
+
+    /// Detaches the link from this value, such that it is not deleted on drop and outlives the
+    /// driver unless it is deleted with [`SymbolicLink::delete`]. Returns the name of the link.
+    pub fn detach(self) -> OwnedUnicodeString {
+        let this = core::mem::ManuallyDrop::new(self);
+
+        unsafe { core::ptr::read(&this.name) }
+    }
+
+    /// Deletes the link with the given name, e.g. one that has been detached before.
+    pub fn delete(name: &str) -> Result<(), Error> {
+        let name = OwnedUnicodeString::try_from(name)?;
+        let mut name_ptr = name.as_unicode_str();
+
+        unsafe { IoDeleteSymbolicLink(name_ptr.as_mut_ptr()) }.into_result()
+    }
+
+    /// Queries the target of the existing symbolic link with the given name, e.g. `\??\C:`
+    /// resolves to `\Device\HarddiskVolume3`.
+    pub fn query_target(name: &str) -> Result<OwnedUnicodeString, Error> {
+        SymbolicLinkObject::open(name)?.target()
+    }
 }
 
 impl Drop for SymbolicLink {
     fn drop(&mut self) {
-        let mut name_ptr = create_unicode_string(self.name.as_slice());
+        let mut name_ptr = self.name.as_unicode_str();
 
         unsafe {
-            IoDeleteSymbolicLink(&mut name_ptr);
+            IoDeleteSymbolicLink(name_ptr.as_mut_ptr());
+        }
+    }
+}
+
+/// An open handle to an existing symbolic link object, closed on drop.
+pub struct SymbolicLinkObject {
+    handle: HANDLE,
+}
+
+unsafe impl Send for SymbolicLinkObject {}
+
+unsafe impl Sync for SymbolicLinkObject {}
+
+impl SymbolicLinkObject {
+    /// Opens the existing symbolic link with the given name using [`ZwOpenSymbolicLinkObject`].
+    pub fn open(name: &str) -> Result<Self, Error> {
+        let name = OwnedUnicodeString::try_from(name)?;
+
+        Self::open_unicode(name.as_unicode_str())
+    }
+
+    /// Opens the existing symbolic link with the given name using [`ZwOpenSymbolicLinkObject`].
+    pub fn open_unicode(mut name: UnicodeStr<'_>) -> Result<Self, Error> {
+        let mut attrs = OBJECT_ATTRIBUTES {
+            Length: core::mem::size_of::<OBJECT_ATTRIBUTES>() as u32,
+            RootDirectory: core::ptr::null_mut(),
+            ObjectName: name.as_mut_ptr(),
+            Attributes: (ObjectFlags::CASE_INSENSITIVE | ObjectFlags::KERNEL_HANDLE).bits(),
+            SecurityDescriptor: core::ptr::null_mut(),
+            SecurityQualityOfService: core::ptr::null_mut(),
+        };
+
+        let mut handle: HANDLE = core::ptr::null_mut();
+
+        unsafe { ZwOpenSymbolicLinkObject(&mut handle, GENERIC_READ, &mut attrs) }
+            .into_result()?;
+
+        Ok(Self { handle })
+    }
+
+    /// Queries the target of the link using [`ZwQuerySymbolicLinkObject`].
+    pub fn target(&self) -> Result<OwnedUnicodeString, Error> {
+        let mut buffer = vec![0u16; 128];
+
+        loop {
+            let mut target = UNICODE_STRING {
+                Length: 0,
+                MaximumLength: (buffer.len() * 2) as u16,
+                Buffer: buffer.as_mut_ptr(),
+            };
+
+            let mut len = 0;
+
+            match unsafe { ZwQuerySymbolicLinkObject(self.handle, &mut target, &mut len) } {
+                STATUS_BUFFER_TOO_SMALL if buffer.len() < len as usize / 2 => {
+                    buffer.resize(len as usize / 2, 0);
+                }
+                status => {
+                    status.into_result()?;
+
+                    return Ok(unsafe { UnicodeStr::from_raw(&target) }.to_unicode_string());
+                }
+            }
+        }
+    }
+}
+
+impl Drop for SymbolicLinkObject {
+    fn drop(&mut self) {
+        unsafe {
+            ZwClose(self.handle);
         }
     }
 }
@@ -0,0 +1,315 @@
+//! user_buffer mod
+//!
+//! User buffers that the driver keeps accessing after the request that handed them over has
+//! completed, e.g. a ring buffer shared with a service. The pages are probed and locked in the
+//! context of the requesting process and mapped into system space, such that the buffer can be
+//! accessed from any context. Locked pages must not outlive the process they belong to, so every
+//! buffer is tied to the file object of the request and to the requesting process, and released
+//! when the handle is cleaned up or when the process exits, whichever comes first. The handle
+//! alone is not enough, as it may have been duplicated into a process that outlives the owner of
+//! the pages. Process exit is observed through a callback that the driver registers with
+//! [`ProcessExitRelease::register`] and keeps registered while it locks buffers.
+//!
+//! ```ignore
+//! // In `DriverEntry`, kept until unload.
+//! let exit_release = ProcessExitRelease::register()?;
+//!
+//! fn ioctl(&mut self, _: &Device, request: IoControlRequest) -> Result<Completion, RequestError> {
+//!     let (address, len) = parse_registration(&request)?;
+//!     let ring = LockedUserBuffer::lock(&request, address, len, LockOperation::IoWriteAccess)?;
+//!
+//!     self.ring = Some(ring);
+//!
+//!     Ok(Completion::Complete(0, request.into()))
+//! }
+//!
+//! fn on_event(&self, event: &[u8]) {
+//!     if let Some(ring) = &self.ring {
+//!         let _ = ring.with(|buffer| buffer[..event.len()].copy_from_slice(event));
+//!     }
+//! }
+//! ```
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use win_kernel_sys::base::{FILE_OBJECT, HANDLE, KSPIN_LOCK, PEPROCESS, PVOID};
+use win_kernel_sys::ntoskrnl::{
+    IoGetRequestorProcess, KeAcquireSpinLockRaiseToDpc, KeReleaseSpinLock, PsGetCurrentProcess,
+};
+
+use crate::allocator::try_push;
+use crate::error::{Error, IntoResult};
+use crate::headers::PsSetCreateProcessNotifyRoutineEx;
+use crate::mdl::{AccessMode, LockOperation, LockedMapping, MemoryDescriptorList};
+use crate::memory::MemoryCaching;
+use crate::request::IoRequest;
+
+/// A value protected by a spin lock.
+struct SpinLocked<T> {
+    lock: UnsafeCell<KSPIN_LOCK>,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for SpinLocked<T> {}
+
+impl<T> SpinLocked<T> {
+    /// A spin lock is initialized by zeroing it, which is all `KeInitializeSpinLock` does.
+    const fn new(value: T) -> Self {
+        Self {
+            lock: UnsafeCell::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Runs `f` with the lock held, at `DISPATCH_LEVEL`.
+    fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        unsafe {
+            let irql = KeAcquireSpinLockRaiseToDpc(self.lock.get());
+            let result = f(&mut *self.value.get());
+            KeReleaseSpinLock(self.lock.get(), irql);
+
+            result
+        }
+    }
+}
+
+/// The state that is shared between a [`LockedUserBuffer`] and the list of buffers that are
+/// released on cleanup.
+struct Shared {
+    file_object: usize,
+    process: usize,
+    len: usize,
+    mapping: SpinLocked<Option<LockedMapping>>,
+}
+
+impl Shared {
+    /// Unmaps and unlocks the buffer, if it has not been released yet. The mapping is dropped
+    /// after the lock is released.
+    fn release(&self) {
+        drop(self.mapping.with(Option::take));
+    }
+}
+
+/// The buffers that are still locked, released on cleanup of their file object or on exit of
+/// their process.
+static BUFFERS: SpinLocked<Vec<Arc<Shared>>> = SpinLocked::new(Vec::new());
+
+/// Whether the process exit callback is registered, see [`ProcessExitRelease`].
+static REGISTERED: AtomicBool = AtomicBool::new(false);
+
+/// The registration of the callback that releases the buffers of exiting processes, see the
+/// [module documentation](self). Buffers can only be locked while it is registered, and dropping
+/// it releases the buffers that are still locked, so it is usually kept until unload.
+pub struct ProcessExitRelease {
+    _private: (),
+}
+
+impl ProcessExitRelease {
+    /// Registers the callback with `PsSetCreateProcessNotifyRoutineEx`, which fails with
+    /// `STATUS_ACCESS_DENIED` unless the driver is linked with `/INTEGRITYCHECK`. There can only
+    /// be one registration at a time, further ones fail with [`Error::INVALID_DEVICE_STATE`].
+    pub fn register() -> Result<Self, Error> {
+        if REGISTERED
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            return Err(Error::INVALID_DEVICE_STATE);
+        }
+
+        let status =
+            unsafe { PsSetCreateProcessNotifyRoutineEx(Some(process_notify), false as _) };
+
+        if let Err(e) = status.into_result() {
+            unregister();
+            return Err(e);
+        }
+
+        Ok(Self { _private: () })
+    }
+}
+
+impl Drop for ProcessExitRelease {
+    fn drop(&mut self) {
+        // Removing the callback waits for the callbacks that are still running.
+        unsafe { PsSetCreateProcessNotifyRoutineEx(Some(process_notify), true as _) };
+
+        // Nothing releases the remaining buffers on process exit anymore.
+        unregister();
+    }
+}
+
+/// Clears [`REGISTERED`] and releases every buffer. The flag is cleared with the list lock held,
+/// which [`LockedUserBuffer::lock`] checks it under as well, such that no buffer can be added
+/// after the list has been drained.
+fn unregister() {
+    let buffers = BUFFERS.with(|buffers| {
+        REGISTERED.store(false, Ordering::Release);
+
+        core::mem::take(buffers)
+    });
+
+    for shared in buffers {
+        shared.release();
+    }
+}
+
+/// Releases the buffers of a process when it exits, i.e. when `CreateInfo` is null. The callback
+/// runs when the last thread of the process exits, before its address space is torn down.
+unsafe extern "system" fn process_notify(process: PEPROCESS, _: HANDLE, create_info: PVOID) {
+    if create_info.is_null() {
+        release_where(|shared| shared.process == process as usize);
+    }
+}
+
+/// A user buffer that is locked into memory and mapped into system space, see the
+/// [module documentation](self). The buffer is released when it is dropped, or when the handle it
+/// was registered through is cleaned up, whichever comes first.
+pub struct LockedUserBuffer {
+    shared: Arc<Shared>,
+}
+
+unsafe impl Send for LockedUserBuffer {}
+
+unsafe impl Sync for LockedUserBuffer {}
+
+impl LockedUserBuffer {
+    /// Probes and locks `len` bytes at the user `address` for the given operation, and maps them
+    /// into system space. This must be called in the context of the process that issued the
+    /// request, i.e. from the dispatch routine of a `METHOD_NEITHER` request, and fails with
+    /// [`Error::INVALID_PARAMETER`] otherwise. An invalid user buffer is reported as an error.
+    /// Without a [`ProcessExitRelease`], nothing would release the buffer when the process exits,
+    /// so this fails with [`Error::INVALID_DEVICE_STATE`].
+    pub fn lock(
+        request: &IoRequest,
+        address: *mut core::ffi::c_void,
+        len: usize,
+        operation: LockOperation,
+    ) -> Result<Self, Error> {
+        if !REGISTERED.load(Ordering::Acquire) {
+            return Err(Error::INVALID_DEVICE_STATE);
+        }
+
+        let process = unsafe { PsGetCurrentProcess() };
+
+        if unsafe { IoGetRequestorProcess(request.irp_mut()) != process } {
+            return Err(Error::INVALID_PARAMETER);
+        }
+
+        let file_object: *mut FILE_OBJECT = request.stack_location().FileObject;
+
+        if file_object.is_null() {
+            return Err(Error::INVALID_PARAMETER);
+        }
+
+        let mut mdl = MemoryDescriptorList::new(address, len)?;
+        mdl.probe_and_lock(AccessMode::UserMode, operation)?;
+
+        let mapping = mdl.map_locked_pages(AccessMode::KernelMode, MemoryCaching::Cached, None)?;
+
+        let shared = Arc::new(Shared {
+            file_object: file_object as usize,
+            process: process as usize,
+            len,
+            mapping: SpinLocked::new(Some(mapping)),
+        });
+
+        // The callback may have been removed since the check above, in which case the list has
+        // already been drained and nothing would release the buffer.
+        let registered = BUFFERS.with(|buffers| {
+            if !REGISTERED.load(Ordering::Acquire) {
+                return Ok(false);
+            }
+
+            try_push(buffers, shared.clone()).map(|_| true)
+        })?;
+
+        if !registered {
+            shared.release();
+            return Err(Error::INVALID_DEVICE_STATE);
+        }
+
+        Ok(Self { shared })
+    }
+
+    /// Returns the size of the buffer in bytes.
+    pub fn len(&self) -> usize {
+        self.shared.len
+    }
+
+    /// Returns whether the buffer is empty, which it never is.
+    pub fn is_empty(&self) -> bool {
+        self.shared.len == 0
+    }
+
+    /// Returns whether the buffer has been released, e.g. because its handle was cleaned up.
+    pub fn is_released(&self) -> bool {
+        self.shared.mapping.with(|mapping| mapping.is_none())
+    }
+
+    /// Runs `f` with the system space alias of the buffer, or fails with [`Error::FILE_CLOSED`]
+    /// if the buffer has been released. `f` runs at `DISPATCH_LEVEL` with a spin lock held, such
+    /// that the buffer cannot be released meanwhile, so it should be short.
+    ///
+    /// The process can still access the buffer concurrently, so its contents are untrusted and
+    /// may change between two reads.
+    pub fn with<R>(&self, f: impl FnOnce(&mut [u8]) -> R) -> Result<R, Error> {
+        let len = self.shared.len;
+
+        self.shared.mapping.with(|mapping| match mapping {
+            Some(mapping) => {
+                let buffer = unsafe { core::slice::from_raw_parts_mut(mapping.ptr() as _, len) };
+
+                Ok(f(buffer))
+            }
+            None => Err(Error::FILE_CLOSED),
+        })
+    }
+
+    /// Unmaps and unlocks the buffer now, rather than on drop.
+    pub fn release(self) {
+        drop(self);
+    }
+}
+
+impl Drop for LockedUserBuffer {
+    fn drop(&mut self) {
+        self.shared.release();
+
+        let removed = BUFFERS.with(|buffers| {
+            let index = buffers
+                .iter()
+                .position(|shared| Arc::ptr_eq(shared, &self.shared))?;
+
+            Some(buffers.swap_remove(index))
+        });
+
+        drop(removed);
+    }
+}
+
+/// Releases the buffers that were locked through the given file object. This is called for every
+/// `IRP_MJ_CLEANUP` that is dispatched to a [`DeviceOperations`](crate::device::DeviceOperations)
+/// implementation, after its `cleanup`.
+pub(crate) fn release_file(file_object: *mut FILE_OBJECT) {
+    release_where(|shared| shared.file_object == file_object as usize);
+}
+
+/// Removes and releases every buffer that matches the predicate. The buffers are released one at
+/// a time, after the list lock is released.
+fn release_where(f: impl Fn(&Shared) -> bool) {
+    loop {
+        let removed = BUFFERS.with(|buffers| {
+            let index = buffers.iter().position(|shared| f(shared))?;
+
+            Some(buffers.swap_remove(index))
+        });
+
+        match removed {
+            Some(shared) => shared.release(),
+            None => break,
+        }
+    }
+}
@@ -1,23 +1,219 @@
 //! This module provides utilities to query information about the version of Microsoft Windows.
+//!
+//! The version of the running system never changes, so it is queried once and cached, see
+//! [`VersionInfo::current`]. Features that depend on a release can be gated by comparing the
+//! version against a [`WindowsRelease`]:
+//!
+//! ```ignore
+//! if VersionInfo::current() >= WindowsRelease::Win10_2004 {
+//!     // Use ExAllocatePool2.
+//! }
+//! ```
+
+use core::cmp::Ordering;
+
+use bitflags::bitflags;
+use lazy_static::lazy_static;
 
 use crate::error::{Error, IntoResult};
-use win_kernel_sys::base::RTL_OSVERSIONINFOW;
+use win_kernel_sys::base::{RTL_OSVERSIONINFOEXW, RTL_OSVERSIONINFOW};
 use win_kernel_sys::ntoskrnl::RtlGetVersion;
 
+lazy_static! {
+    /// The version of Microsoft Windows that is currently running.
+    static ref CURRENT: VersionInfo = {
+        // RtlGetVersion always succeeds, it merely fills in the structure.
+        VersionInfo::query().unwrap()
+    };
+}
+
+bitflags! {
+    /// The product suites that are available on the system, see `VER_SUITE_*`.
+    pub struct SuiteMask: u16 {
+        const SMALLBUSINESS            = 0x0001;
+        const ENTERPRISE               = 0x0002;
+        const BACKOFFICE               = 0x0004;
+        const COMMUNICATIONS           = 0x0008;
+        const TERMINAL                 = 0x0010;
+        const SMALLBUSINESS_RESTRICTED = 0x0020;
+        const EMBEDDEDNT               = 0x0040;
+        const DATACENTER               = 0x0080;
+        const SINGLEUSERTS             = 0x0100;
+        const PERSONAL                 = 0x0200;
+        const BLADE                    = 0x0400;
+        const EMBEDDED_RESTRICTED      = 0x0800;
+        const SECURITY_APPLIANCE       = 0x1000;
+        const STORAGE_SERVER           = 0x2000;
+        const COMPUTE_SERVER           = 0x4000;
+        const WH_SERVER                = 0x8000;
+    }
+}
+
+/// The product type of the system, see `VER_NT_*`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProductType {
+    /// A client release, e.g. Windows 10.
+    Workstation,
+    /// A server that is a domain controller.
+    DomainController,
+    /// A server that is not a domain controller.
+    Server,
+    /// A product type that is not known.
+    Unknown(u8),
+}
+
+impl From<u8> for ProductType {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => ProductType::Workstation,
+            2 => ProductType::DomainController,
+            3 => ProductType::Server,
+            value => ProductType::Unknown(value),
+        }
+    }
+}
+
+/// A named release of Microsoft Windows, identified by its build number. Server releases share
+/// the build number of the client release they were built from, e.g. Windows Server 2019 is
+/// [`WindowsRelease::Win10_1809`], except for Windows Server 2022, which has its own build.
+///
+/// Releases are ordered by build number.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum WindowsRelease {
+    /// Windows 7 and Windows Server 2008 R2.
+    Win7 = 7600,
+    /// Windows 7 SP1 and Windows Server 2008 R2 SP1.
+    Win7Sp1 = 7601,
+    /// Windows 8 and Windows Server 2012.
+    Win8 = 9200,
+    /// Windows 8.1 and Windows Server 2012 R2.
+    Win81 = 9600,
+    /// Windows 10 1507.
+    Win10_1507 = 10240,
+    /// Windows 10 1511.
+    Win10_1511 = 10586,
+    /// Windows 10 1607 and Windows Server 2016.
+    Win10_1607 = 14393,
+    /// Windows 10 1703.
+    Win10_1703 = 15063,
+    /// Windows 10 1709.
+    Win10_1709 = 16299,
+    /// Windows 10 1803.
+    Win10_1803 = 17134,
+    /// Windows 10 1809 and Windows Server 2019.
+    Win10_1809 = 17763,
+    /// Windows 10 1903.
+    Win10_1903 = 18362,
+    /// Windows 10 1909.
+    Win10_1909 = 18363,
+    /// Windows 10 2004, the first release to export `ExAllocatePool2`.
+    Win10_2004 = 19041,
+    /// Windows 10 20H2.
+    Win10_20H2 = 19042,
+    /// Windows 10 21H1.
+    Win10_21H1 = 19043,
+    /// Windows 10 21H2.
+    Win10_21H2 = 19044,
+    /// Windows 10 22H2.
+    Win10_22H2 = 19045,
+    /// Windows Server 2022.
+    Server2022 = 20348,
+    /// Windows 11 21H2.
+    Win11_21H2 = 22000,
+    /// Windows 11 22H2.
+    Win11_22H2 = 22621,
+    /// Windows 11 23H2.
+    Win11_23H2 = 22631,
+    /// Windows 11 24H2 and Windows Server 2025.
+    Win11_24H2 = 26100,
+}
+
+impl WindowsRelease {
+    /// Every known release, in ascending order.
+    pub const ALL: &'static [WindowsRelease] = &[
+        WindowsRelease::Win7,
+        WindowsRelease::Win7Sp1,
+        WindowsRelease::Win8,
+        WindowsRelease::Win81,
+        WindowsRelease::Win10_1507,
+        WindowsRelease::Win10_1511,
+        WindowsRelease::Win10_1607,
+        WindowsRelease::Win10_1703,
+        WindowsRelease::Win10_1709,
+        WindowsRelease::Win10_1803,
+        WindowsRelease::Win10_1809,
+        WindowsRelease::Win10_1903,
+        WindowsRelease::Win10_1909,
+        WindowsRelease::Win10_2004,
+        WindowsRelease::Win10_20H2,
+        WindowsRelease::Win10_21H1,
+        WindowsRelease::Win10_21H2,
+        WindowsRelease::Win10_22H2,
+        WindowsRelease::Server2022,
+        WindowsRelease::Win11_21H2,
+        WindowsRelease::Win11_22H2,
+        WindowsRelease::Win11_23H2,
+        WindowsRelease::Win11_24H2,
+    ];
+
+    /// Returns the build number of the release.
+    pub fn build_number(self) -> u32 {
+        self as u32
+    }
+
+    /// Returns the major and minor version of the release, e.g. `(6, 1)` for Windows 7. Windows
+    /// 11 still reports itself as 10.0.
+    pub fn version(self) -> (u32, u32) {
+        match self {
+            WindowsRelease::Win7 | WindowsRelease::Win7Sp1 => (6, 1),
+            WindowsRelease::Win8 => (6, 2),
+            WindowsRelease::Win81 => (6, 3),
+            _ => (10, 0),
+        }
+    }
+
+    /// Returns the release with exactly the given build number.
+    pub fn from_build_number(build_number: u32) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|release| release.build_number() == build_number)
+    }
+
+    /// Returns the latest release with a build number that is not greater than the given one,
+    /// i.e. the release that an insider or unknown build is based on.
+    pub fn at_or_before(build_number: u32) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .take_while(|release| release.build_number() <= build_number)
+            .last()
+    }
+
+    /// Returns the version triple of the release, used for ordering.
+    fn key(self) -> (u32, u32, u32) {
+        let (major, minor) = self.version();
+
+        (major, minor, self.build_number())
+    }
+}
+
 /// Represents version information for Microsoft Windows.
+#[derive(Clone, Copy)]
 pub struct VersionInfo {
-    version_info: RTL_OSVERSIONINFOW,
+    version_info: RTL_OSVERSIONINFOEXW,
 }
 
 impl VersionInfo {
     /// Uses [`RtlGetVersion`] to query the version info for Microsoft Windows.
     pub fn query() -> Result<Self, Error> {
-        let mut version_info: RTL_OSVERSIONINFOW = unsafe { core::mem::zeroed() };
+        let mut version_info: RTL_OSVERSIONINFOEXW = unsafe { core::mem::zeroed() };
 
-        version_info.dwOSVersionInfoSize = core::mem::size_of::<RTL_OSVERSIONINFOW>() as u32;
+        version_info.dwOSVersionInfoSize = core::mem::size_of::<RTL_OSVERSIONINFOEXW>() as u32;
 
         unsafe {
-            RtlGetVersion(&mut version_info)
+            RtlGetVersion(&mut version_info as *mut _ as *mut RTL_OSVERSIONINFOW)
         }.into_result()?;
 
         Ok(Self {
@@ -25,6 +221,12 @@ impl VersionInfo {
         })
     }
 
+    /// Returns the version of Microsoft Windows that is currently running. The version is only
+    /// queried once, so this is cheap enough to call on every allocation.
+    pub fn current() -> &'static VersionInfo {
+        &CURRENT
+    }
+
     /// Retrieves the major version of Microsoft Windows.
     pub fn major(&self) -> u32 {
         self.version_info.dwMajorVersion
@@ -39,4 +241,199 @@ impl VersionInfo {
     pub fn build_number(&self) -> u32 {
         self.version_info.dwBuildNumber
     }
-}
\ No newline at end of file
+
+    /// Retrieves the platform identifier, which is always `VER_PLATFORM_WIN32_NT`.
+    pub fn platform_id(&self) -> u32 {
+        self.version_info.dwPlatformId
+    }
+
+    /// Retrieves the major and minor version of the latest service pack that is installed.
+    pub fn service_pack(&self) -> (u16, u16) {
+        (
+            self.version_info.wServicePackMajor,
+            self.version_info.wServicePackMinor,
+        )
+    }
+
+    /// Retrieves the name of the latest service pack that is installed as UTF-16, e.g.
+    /// `Service Pack 1`, or an empty slice if none is installed.
+    pub fn service_pack_name(&self) -> &[u16] {
+        let name = &self.version_info.szCSDVersion;
+        let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+
+        &name[..len]
+    }
+
+    /// Retrieves the product suites that are available on the system.
+    pub fn suite_mask(&self) -> SuiteMask {
+        SuiteMask::from_bits_truncate(self.version_info.wSuiteMask)
+    }
+
+    /// Retrieves the product type of the system.
+    pub fn product_type(&self) -> ProductType {
+        ProductType::from(self.version_info.wProductType)
+    }
+
+    /// Returns whether the system is a server release.
+    pub fn is_server(&self) -> bool {
+        matches!(
+            self.product_type(),
+            ProductType::Server | ProductType::DomainController
+        )
+    }
+
+    /// Returns the named release of the system, i.e. the latest known release that is not newer
+    /// than the running build.
+    pub fn release(&self) -> Option<WindowsRelease> {
+        WindowsRelease::at_or_before(self.build_number())
+    }
+
+    /// Returns the version triple, used for ordering.
+    fn key(&self) -> (u32, u32, u32) {
+        (self.major(), self.minor(), self.build_number())
+    }
+}
+
+impl core::fmt::Debug for VersionInfo {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("VersionInfo")
+            .field("major", &self.major())
+            .field("minor", &self.minor())
+            .field("build_number", &self.build_number())
+            .field("service_pack", &self.service_pack())
+            .field("suite_mask", &self.suite_mask())
+            .field("product_type", &self.product_type())
+            .finish()
+    }
+}
+
+impl core::fmt::Display for VersionInfo {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}.{}.{}", self.major(), self.minor(), self.build_number())
+    }
+}
+
+impl PartialEq for VersionInfo {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl PartialOrd for VersionInfo {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.key().cmp(&other.key()))
+    }
+}
+
+impl PartialEq<WindowsRelease> for VersionInfo {
+    fn eq(&self, other: &WindowsRelease) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl PartialOrd<WindowsRelease> for VersionInfo {
+    fn partial_cmp(&self, other: &WindowsRelease) -> Option<Ordering> {
+        Some(self.key().cmp(&other.key()))
+    }
+}
+
+impl PartialEq<WindowsRelease> for &VersionInfo {
+    fn eq(&self, other: &WindowsRelease) -> bool {
+        (**self).eq(other)
+    }
+}
+
+impl PartialOrd<WindowsRelease> for &VersionInfo {
+    fn partial_cmp(&self, other: &WindowsRelease) -> Option<Ordering> {
+        (**self).partial_cmp(other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(major: u32, minor: u32, build_number: u32) -> VersionInfo {
+        let mut version_info: RTL_OSVERSIONINFOEXW = unsafe { core::mem::zeroed() };
+
+        version_info.dwMajorVersion = major;
+        version_info.dwMinorVersion = minor;
+        version_info.dwBuildNumber = build_number;
+
+        VersionInfo { version_info }
+    }
+
+    #[test]
+    fn releases_are_sorted() {
+        assert!(WindowsRelease::ALL.windows(2).all(|w| w[0] < w[1]));
+        assert!(WindowsRelease::ALL
+            .windows(2)
+            .all(|w| w[0].build_number() < w[1].build_number()));
+    }
+
+    #[test]
+    fn finds_releases_by_build_number() {
+        assert_eq!(WindowsRelease::from_build_number(7600), Some(WindowsRelease::Win7));
+        assert_eq!(WindowsRelease::from_build_number(7601), Some(WindowsRelease::Win7Sp1));
+        assert_eq!(WindowsRelease::from_build_number(9200), Some(WindowsRelease::Win8));
+        assert_eq!(WindowsRelease::from_build_number(19041), Some(WindowsRelease::Win10_2004));
+        assert_eq!(WindowsRelease::from_build_number(22000), Some(WindowsRelease::Win11_21H2));
+        assert_eq!(WindowsRelease::from_build_number(26100), Some(WindowsRelease::Win11_24H2));
+        assert_eq!(WindowsRelease::from_build_number(19040), None);
+
+        for &release in WindowsRelease::ALL {
+            assert_eq!(WindowsRelease::from_build_number(release.build_number()), Some(release));
+        }
+    }
+
+    #[test]
+    fn finds_the_release_at_or_before_a_build() {
+        assert_eq!(WindowsRelease::at_or_before(7599), None);
+        assert_eq!(WindowsRelease::at_or_before(7600), Some(WindowsRelease::Win7));
+        assert_eq!(WindowsRelease::at_or_before(7601), Some(WindowsRelease::Win7Sp1));
+        assert_eq!(WindowsRelease::at_or_before(9199), Some(WindowsRelease::Win7Sp1));
+        assert_eq!(WindowsRelease::at_or_before(9200), Some(WindowsRelease::Win8));
+        assert_eq!(WindowsRelease::at_or_before(19040), Some(WindowsRelease::Win10_1909));
+        assert_eq!(WindowsRelease::at_or_before(19041), Some(WindowsRelease::Win10_2004));
+        assert_eq!(WindowsRelease::at_or_before(21999), Some(WindowsRelease::Server2022));
+        assert_eq!(WindowsRelease::at_or_before(22000), Some(WindowsRelease::Win11_21H2));
+        assert_eq!(WindowsRelease::at_or_before(26100), Some(WindowsRelease::Win11_24H2));
+        assert_eq!(WindowsRelease::at_or_before(27000), Some(WindowsRelease::Win11_24H2));
+    }
+
+    #[test]
+    fn compares_versions_with_releases() {
+        let win7 = version(6, 1, 7600);
+        let win7_sp1 = version(6, 1, 7601);
+        let win8 = version(6, 2, 9200);
+        let win10_2004 = version(10, 0, 19041);
+        let win11 = version(10, 0, 22000);
+        let win11_24h2 = version(10, 0, 26100);
+
+        assert!(win7 == WindowsRelease::Win7);
+        assert!(win7 < WindowsRelease::Win7Sp1);
+        assert!(win7_sp1 >= WindowsRelease::Win7Sp1);
+        assert!(win7_sp1 < WindowsRelease::Win8);
+        assert!(win8 >= WindowsRelease::Win8);
+        assert!(win8 < WindowsRelease::Win81);
+        assert!(win10_2004 >= WindowsRelease::Win10_2004);
+        assert!(version(10, 0, 19040) < WindowsRelease::Win10_2004);
+        assert!(win11 > WindowsRelease::Win10_22H2);
+        assert!(win11 < WindowsRelease::Win11_22H2);
+        assert!(win11_24h2 == WindowsRelease::Win11_24H2);
+        assert!(win11_24h2 > WindowsRelease::Win11_23H2);
+
+        assert!(&win8 >= WindowsRelease::Win8);
+        assert!(&win7_sp1 < WindowsRelease::Win8);
+    }
+
+    #[test]
+    fn identifies_the_release() {
+        assert_eq!(version(6, 1, 7601).release(), Some(WindowsRelease::Win7Sp1));
+        assert_eq!(version(6, 2, 9200).release(), Some(WindowsRelease::Win8));
+        assert_eq!(version(10, 0, 19045).release(), Some(WindowsRelease::Win10_22H2));
+        assert_eq!(version(10, 0, 22000).release(), Some(WindowsRelease::Win11_21H2));
+        assert_eq!(version(10, 0, 26200).release(), Some(WindowsRelease::Win11_24H2));
+        assert_eq!(version(6, 0, 6002).release(), None);
+    }
+}